@@ -1,4 +1,79 @@
-use crate::word::Word;
+use std::{collections::HashMap, fs};
+
+use anyhow::{anyhow, bail};
+use eframe::epaint::{Pos2, Rect};
+use enum_iterator::all;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::EidosError,
+    field::{ControlKind, ScalarInputFieldKind, Type},
+    function::{Function, Variable},
+    person::PersonId,
+    stack::Stack,
+    utils::{fatal_error, find_resources_path},
+    word::Word,
+    world::World,
+};
+
+/// Definitions of conduit stones, loaded from `conduits.yaml`
+#[derive(Debug, Deserialize)]
+pub struct ConduitsDef {
+    pub materials: HashMap<String, ConduitMaterial>,
+    /// The materials of the stones in a rack, in the order they are gained
+    pub rack: Vec<String>,
+    pub starting_stones: usize,
+}
+
+/// The properties of a material that conduit stones are made of
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConduitMaterial {
+    /// The maximum number of words that can be etched
+    pub capacity: usize,
+    /// The mana each etched word reserves
+    pub etch_cost: f32,
+    #[serde(default = "default_cost_multiplier")]
+    pub cost_multiplier: f32,
+    /// Cost multipliers for specific words
+    #[serde(default)]
+    pub word_costs: HashMap<Word, f32>,
+}
+
+fn default_cost_multiplier() -> f32 {
+    1.0
+}
+
+impl ConduitMaterial {
+    /// The cost of a word run from a stone of this material
+    pub fn word_cost(&self, word: Word) -> f32 {
+        word.cost()
+            * self
+                .word_costs
+                .get(&word)
+                .copied()
+                .unwrap_or(self.cost_multiplier)
+    }
+}
+
+pub static CONDUITS: Lazy<ConduitsDef> =
+    Lazy::new(|| load_conduits().unwrap_or_else(|e| fatal_error(e)));
+
+/// Read and check the conduit definitions in `conduits.yaml`
+pub fn load_conduits() -> anyhow::Result<ConduitsDef> {
+    let yaml = fs::read_to_string(find_resources_path()?.join("conduits.yaml"));
+    let yaml = yaml
+        .as_deref()
+        .unwrap_or(include_str!("../resources/conduits.yaml"));
+    let def: ConduitsDef =
+        serde_yaml::from_str(yaml).map_err(|e| anyhow!("Unable to read conduits file: {e}"))?;
+    for material in &def.rack {
+        if !def.materials.contains_key(material) {
+            bail!("Unknown conduit material {material}");
+        }
+    }
+    Ok(def)
+}
 
 pub struct ConduitRack {
     pub conduits: Vec<ConduitStone>,
@@ -6,20 +81,125 @@ pub struct ConduitRack {
 
 impl ConduitRack {
     pub fn new(size: usize) -> Self {
-        ConduitRack {
-            conduits: vec![ConduitStone::default(); size],
+        let mut rack = ConduitRack {
+            conduits: Vec::new(),
+        };
+        rack.grow(size);
+        rack
+    }
+    /// Add stones until the rack has the given size
+    pub fn grow(&mut self, size: usize) {
+        let size = size.min(CONDUITS.rack.len());
+        while self.conduits.len() < size {
+            let material = CONDUITS.rack[self.conduits.len()].clone();
+            self.conduits.push(ConduitStone {
+                material,
+                words: Vec::new(),
+                trigger: None,
+            });
         }
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct ConduitStone {
+    pub material: String,
     pub words: Vec<Word>,
+    /// An event that runs the stone automatically
+    pub trigger: Option<ConduitTrigger>,
+}
+
+/// An event that runs a conduit stone automatically
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TriggerEvent {
+    /// When a control is activated
+    Activation(ControlKind),
+    /// Every given number of seconds
+    Timer(f32),
+    /// When the memory at a position rises above a threshold
+    Memory { pos: Pos2, threshold: f32 },
+    /// When an object enters a region
+    Enter(Rect),
+}
+
+/// A trigger event along with the state needed to detect it
+#[derive(Debug, Clone)]
+pub struct ConduitTrigger {
+    pub event: TriggerEvent,
+    /// The level of the event's condition last tick
+    level: usize,
+    /// The time since the trigger last fired
+    elapsed: f32,
+}
+
+impl ConduitTrigger {
+    pub fn new(event: TriggerEvent) -> Self {
+        ConduitTrigger {
+            event,
+            level: 0,
+            elapsed: 0.0,
+        }
+    }
+    /// Advance the trigger of a person's stone by one tick and return whether it fires
+    pub fn update(&mut self, person_id: PersonId, world: &World, dt: f32) -> bool {
+        let level = match self.event {
+            TriggerEvent::Activation(kind) => {
+                (world.person(person_id).controls.get(kind) > 0.0) as usize
+            }
+            TriggerEvent::Timer(period) => {
+                self.elapsed += dt;
+                if self.elapsed >= period.max(dt) {
+                    self.elapsed = 0.0;
+                    return true;
+                }
+                return false;
+            }
+            TriggerEvent::Memory { pos, threshold } => {
+                let memory =
+                    world.sample_input_scalar_field(ScalarInputFieldKind::Memory, pos, false);
+                (memory > threshold) as usize
+            }
+            TriggerEvent::Enter(rect) => world
+                .objects
+                .values()
+                .filter(|obj| rect.contains(obj.pr.pos))
+                .count(),
+        };
+        let fired = level > self.level;
+        self.level = level;
+        fired
+    }
 }
 
 impl ConduitStone {
+    pub fn material(&self) -> &'static ConduitMaterial {
+        &CONDUITS.materials[&self.material]
+    }
+    pub fn capacity(&self) -> usize {
+        self.material().capacity
+    }
+    /// The mana needed to etch the given number of words
+    pub fn etch_cost(&self, word_count: usize) -> f32 {
+        self.material().etch_cost * word_count as f32
+    }
+    /// The mana held by the etched words
+    pub fn reserved_mana(&self) -> f32 {
+        self.etch_cost(self.words.len())
+    }
     pub fn etch(&mut self, words: impl IntoIterator<Item = Word>) {
-        self.words = words.into_iter().filter(Word::etchable).collect();
+        self.words = words.into_iter().collect();
+    }
+    /// The cost of running the etched words
+    pub fn cost(&self) -> f32 {
+        let material = self.material();
+        self.words
+            .iter()
+            .map(|word| material.word_cost(*word))
+            .sum()
+    }
+    /// The types of the arguments this conduit pops from the stack
+    pub fn params(&self) -> Vec<Type> {
+        conduit_params(&self.words)
     }
     pub fn format(&self, max_length: usize) -> String {
         if self.words.is_empty() {
@@ -40,3 +220,232 @@ impl ConduitStone {
         s
     }
 }
+
+/// Get the parameter types of a conduit, one for each variable word in order
+pub fn conduit_params(words: &[Word]) -> Vec<Type> {
+    words
+        .iter()
+        .filter_map(|word| match word.function() {
+            Function::Variable(Variable::Scalar) => Some(Type::Scalar),
+            Function::Variable(Variable::Vector) => Some(Type::Vector),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Say a sequence of words on top of a stack without casting anything,
+/// leaving any parameters unbound
+pub fn check_words(
+    person_id: PersonId,
+    words: &[Word],
+    mut stack: Stack,
+) -> Result<Stack, EidosError> {
+    for (index, word) in words.iter().enumerate() {
+        stack
+            .say(person_id, *word, None)
+            .map_err(|error| EidosError::ConduitWord {
+                index,
+                error: error.into(),
+            })?;
+    }
+    Ok(stack)
+}
+
+/// The version of the conduit code format
+const CODE_VERSION: u8 = 1;
+/// The RFC 4648 base32 alphabet
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The base cost of a sequence of words
+pub fn words_cost(words: &[Word]) -> f32 {
+    words.iter().map(Word::cost).sum()
+}
+
+/// Encode conduit words as a shareable text code
+///
+/// The code is base32 of a version byte, the base cost, the word count,
+/// the word indices, and a checksum.
+pub fn export_code(conduit_words: &[Word]) -> anyhow::Result<String> {
+    if conduit_words.len() > u8::MAX as usize {
+        bail!("Codes can have at most {} words", u8::MAX)
+    }
+    let words = all::<Word>().collect::<Vec<_>>();
+    let cost = words_cost(conduit_words)
+        .round()
+        .clamp(0.0, u16::MAX as f32) as u16;
+    let mut bytes = vec![CODE_VERSION];
+    bytes.extend(cost.to_le_bytes());
+    bytes.push(conduit_words.len() as u8);
+    for word in conduit_words {
+        bytes.push(words.iter().position(|w| w == word).unwrap() as u8);
+    }
+    bytes.extend(checksum(&bytes).to_le_bytes());
+    Ok(base32_encode(&bytes))
+}
+
+/// Decode conduit words from a text code
+pub fn import_code(code: &str) -> anyhow::Result<Vec<Word>> {
+    let Some(bytes) = base32_decode(code) else {
+        bail!("The code contains invalid characters")
+    };
+    if bytes.len() < 6 {
+        bail!("The code is too short")
+    }
+    let (data, sum) = bytes.split_at(bytes.len() - 2);
+    if checksum(data).to_le_bytes() != sum {
+        bail!("The code is corrupted")
+    }
+    if data[0] != CODE_VERSION {
+        bail!("Unsupported code version {}", data[0])
+    }
+    let cost = u16::from_le_bytes([data[1], data[2]]);
+    let count = data[3] as usize;
+    let indices = &data[4..];
+    if indices.len() != count {
+        bail!("The code is corrupted")
+    }
+    let words = all::<Word>().collect::<Vec<_>>();
+    let mut conduit_words = Vec::new();
+    for &i in indices {
+        let Some(word) = words.get(i as usize) else {
+            bail!("The code contains an unknown word")
+        };
+        conduit_words.push(*word);
+    }
+    if words_cost(&conduit_words).round() as u16 != cost {
+        bail!("The code's cost does not match its words")
+    }
+    Ok(conduit_words)
+}
+
+/// Fletcher-16 checksum
+fn checksum(bytes: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &byte in bytes {
+        a = (a + byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    (b << 8) | a
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        s.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    s
+}
+
+/// Decode base32, ignoring case, whitespace, dashes, and padding
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.chars() {
+        if c.is_whitespace() || c == '-' || c == '=' {
+            continue;
+        }
+        let c = c.to_ascii_uppercase() as u8;
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[test]
+fn conduit_code_test() {
+    use Word::*;
+    let words = vec![Su, Te, Ma, Ro, Nu];
+    let code = export_code(&words).unwrap();
+    let imported = import_code(&code.to_lowercase()).unwrap();
+    assert_eq!(imported, words);
+    let mut corrupted = code.into_bytes();
+    corrupted[3] = if corrupted[3] == b'A' { b'B' } else { b'A' };
+    assert!(import_code(&String::from_utf8(corrupted).unwrap()).is_err());
+    assert!(import_code("!!").is_err());
+    assert!(export_code(&[Su; 256]).is_err());
+    let longest = [Su; 255];
+    assert_eq!(
+        import_code(&export_code(&longest).unwrap()).unwrap(),
+        longest
+    );
+}
+
+#[test]
+fn conduit_trigger_test() {
+    use crate::{field::ScalarOutputFieldKind, replay::Command};
+    let mut world = World::test_place("arena");
+    let heat_spells = |world: &World| {
+        (world.player.person.active_spells)
+            .spell_words(ScalarOutputFieldKind::Heat.into())
+            .len()
+    };
+    let command = Command::Etch {
+        stone: 0,
+        words: vec![Word::Lo],
+        trigger: Some(TriggerEvent::Timer(0.1)),
+    };
+    world.command(PersonId::Player, command).unwrap();
+    world
+        .command(PersonId::Player, Command::Say(Word::Te))
+        .unwrap();
+    for _ in 0..10 {
+        world.update();
+    }
+    assert_eq!(heat_spells(&world), 1);
+    assert_eq!(world.player.person.stack.len(), 0);
+    // Undoing takes back what the trigger did before what the player did
+    world.player.person.rack.conduits[0].trigger = None;
+    world.command(PersonId::Player, Command::Undo).unwrap();
+    assert_eq!(heat_spells(&world), 0);
+    assert_eq!(world.player.person.stack.len(), 1);
+    world.command(PersonId::Player, Command::Undo).unwrap();
+    assert_eq!(world.player.person.stack.len(), 0);
+}
+
+#[test]
+fn etch_limits_test() {
+    use crate::{error::EidosError, replay::Command};
+    let mut world = World::test_place("arena");
+    let etch = |world: &mut World, count| {
+        let command = Command::Etch {
+            stone: 0,
+            words: vec![Word::Lo; count],
+            trigger: None,
+        };
+        world.command(PersonId::Player, command)
+    };
+    let stone = world.player.person.rack.conduits[0].clone();
+    let capacity = stone.capacity();
+    let err = etch(&mut world, capacity + 1).unwrap_err();
+    assert!(matches!(err, EidosError::ConduitCapacity { .. }), "{err}");
+    assert!(world.player.person.rack.conduits[0].words.is_empty());
+    // Etched words reserve their cost, and etching again gives it back first
+    let mana = world.player.person.capped_mana();
+    etch(&mut world, capacity).unwrap();
+    assert_eq!(
+        world.player.person.capped_mana(),
+        mana - stone.etch_cost(capacity)
+    );
+    etch(&mut world, 1).unwrap();
+    assert_eq!(world.player.person.capped_mana(), mana - stone.etch_cost(1));
+    world.player.person.max_mana = stone.etch_cost(2);
+    let err = etch(&mut world, 3).unwrap_err();
+    assert!(matches!(err, EidosError::NotEnoughMana { .. }), "{err}");
+    etch(&mut world, 2).unwrap();
+}
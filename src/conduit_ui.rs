@@ -0,0 +1,540 @@
+use std::mem::discriminant;
+
+use eframe::egui::*;
+use enum_iterator::all;
+use itertools::Itertools;
+
+use crate::{
+    conduit::{
+        check_words, conduit_params, export_code, import_code, words_cost, ConduitStone,
+        TriggerEvent,
+    },
+    error::EidosError,
+    field::ControlKind,
+    game::{Game, SMALL_PLOT_SIZE},
+    locale::tr,
+    person::PersonId,
+    replay::Command,
+    stack::Stack,
+    word::Word,
+};
+
+/// The state of the conduit etching editor
+pub struct ConduitEditor {
+    /// The index of the stone being edited
+    pub stone: usize,
+    /// The words that will be etched
+    pub words: Vec<Word>,
+    /// Where new words are inserted
+    pub cursor: usize,
+    /// Whether to check the words on top of the current stack
+    pub on_stack: bool,
+    /// A code being imported
+    pub import_code: String,
+    /// The event that will run the stone automatically
+    pub trigger: Option<TriggerEvent>,
+    dragging: Option<usize>,
+}
+
+impl ConduitEditor {
+    pub fn new(index: usize, stone: &ConduitStone) -> Self {
+        ConduitEditor {
+            stone: index,
+            cursor: stone.words.len(),
+            words: stone.words.clone(),
+            trigger: stone.trigger.as_ref().map(|trigger| trigger.event),
+            on_stack: false,
+            import_code: String::new(),
+            dragging: None,
+        }
+    }
+    pub fn insert(&mut self, words: impl IntoIterator<Item = Word>) {
+        for word in words {
+            self.words.insert(self.cursor, word);
+            self.cursor += 1;
+        }
+    }
+    /// Delete the word before the cursor
+    pub fn delete(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.words.remove(self.cursor);
+        }
+    }
+    /// Move a word so that it ends up before the word currently at `to`
+    pub fn move_word(&mut self, from: usize, to: usize) {
+        let word = self.words.remove(from);
+        let to = if to > from { to - 1 } else { to };
+        self.words.insert(to, word);
+        self.cursor = to + 1;
+    }
+}
+
+impl Game {
+    pub fn conduit_ui(&mut self, ui: &mut Ui) {
+        if !self.world.player.progression.conduit {
+            return;
+        }
+        let available_mana = self.world.player.person.capped_mana();
+        let mut run = None;
+        let mut etch = None;
+        Grid::new("conduits").show(ui, |ui| {
+            for (i, stone) in self.world.player.person.rack.conduits.iter().enumerate() {
+                let mut stack = self.world.player.person.stack.clone();
+                let mut text = stone.format(16);
+                if stone.trigger.is_some() {
+                    text = format!("⚡ {text}");
+                }
+                let button = Button::new(text);
+                let res =
+                    stack.apply_conduit(PersonId::Player, &stone.words, stone.material(), None);
+                let on_hover = |ui: &mut Ui| {
+                    ui.label(stone.format(usize::MAX));
+                    ui.label(format!(
+                        "{} ({} {})",
+                        tr(&stone.material),
+                        stone.cost(),
+                        tr("mana")
+                    ));
+                    let params = stone.params();
+                    if !params.is_empty() {
+                        ui.label(format!(
+                            "{}: {}",
+                            tr("Parameters"),
+                            params.iter().join(", ")
+                        ));
+                    }
+                    if let Some(trigger) = &stone.trigger {
+                        ui.label(format!(
+                            "{}: {}",
+                            tr("Trigger"),
+                            trigger_name(trigger.event)
+                        ));
+                    }
+                };
+                if res.is_ok() && available_mana >= stone.cost() {
+                    if button.ui(ui).on_hover_ui(on_hover).clicked() {
+                        run = Some(i);
+                    }
+                } else {
+                    ui.add_enabled(false, button).on_disabled_hover_ui(on_hover);
+                }
+                let person = &self.world.player.person;
+                let can_add = !person.stack.is_empty()
+                    && (person.check_etch(i, person.stack.words().count(), true)).is_ok();
+                if ui.add_enabled(can_add, Button::new("+")).clicked() {
+                    etch = Some(i);
+                }
+                if ui.button("✏").clicked() {
+                    self.ui_state.conduit_editor = Some(ConduitEditor::new(i, stone));
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = run {
+            self.command(PersonId::Player, Command::RunConduit(i));
+        }
+        if let Some(i) = etch {
+            self.command(PersonId::Player, Command::EtchStack(i));
+        }
+    }
+    pub fn conduit_editor_ui(&mut self, ctx: &Context) {
+        let Some(mut editor) = self.ui_state.conduit_editor.take() else {
+            return;
+        };
+        let mut open = true;
+        let mut close = false;
+        Window::new(tr("Conduit"))
+            .open(&mut open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                // Stone selection
+                ui.horizontal(|ui| {
+                    let conduits = &self.world.player.person.rack.conduits;
+                    for (i, stone) in conduits.iter().enumerate() {
+                        if ui
+                            .selectable_label(editor.stone == i, (i + 1).to_string())
+                            .on_hover_text(format!(
+                                "{}\n{}",
+                                tr(&stone.material),
+                                stone.format(usize::MAX)
+                            ))
+                            .clicked()
+                            && editor.stone != i
+                        {
+                            editor = ConduitEditor {
+                                on_stack: editor.on_stack,
+                                ..ConduitEditor::new(i, stone)
+                            };
+                        }
+                    }
+                });
+                ui.separator();
+                // Stone properties
+                let stone = &self.world.player.person.rack.conduits[editor.stone];
+                let material = stone.material();
+                let etch_res =
+                    (self.world.player.person).check_etch(editor.stone, editor.words.len(), false);
+                let etch_cost = stone.etch_cost(editor.words.len());
+                let over_capacity = editor.words.len() > material.capacity;
+                let run_cost: f32 = editor
+                    .words
+                    .iter()
+                    .map(|word| material.word_cost(*word))
+                    .sum();
+                ui.horizontal(|ui| {
+                    ui.label(tr(&stone.material));
+                    let capacity = format!("{}/{}", editor.words.len(), material.capacity);
+                    if over_capacity {
+                        ui.colored_label(ui.visuals().error_fg_color, capacity);
+                    } else {
+                        ui.label(capacity);
+                    }
+                    ui.label(format!("{}: {run_cost}", tr("Cost")));
+                    let etch_text = format!("{}: {etch_cost}", tr("Etch cost"));
+                    if let Err(EidosError::NotEnoughMana { .. }) = etch_res {
+                        ui.colored_label(ui.visuals().error_fg_color, etch_text);
+                    } else {
+                        ui.label(etch_text);
+                    }
+                });
+                // Type check
+                let res = if editor.on_stack {
+                    let mut stack = self.world.player.person.stack.clone();
+                    stack
+                        .apply_conduit(PersonId::Player, &editor.words, material, None)
+                        .map(|_| stack)
+                } else {
+                    check_words(PersonId::Player, &editor.words, Stack::default())
+                };
+                let error_index = match &res {
+                    Err(EidosError::ConduitWord { index, .. }) => Some(*index),
+                    _ => None,
+                };
+                // Words
+                self.conduit_words_ui(ui, &mut editor, error_index);
+                // Editing
+                ui.horizontal(|ui| {
+                    if ui.button(tr("Delete")).clicked() {
+                        editor.delete();
+                    }
+                    if ui.button(tr("Clear")).clicked() {
+                        editor.words.clear();
+                        editor.cursor = 0;
+                    }
+                    if ui.button(tr("Copy")).clicked() {
+                        self.ui_state.conduit_clipboard = editor.words.clone();
+                    }
+                    let can_paste = !self.ui_state.conduit_clipboard.is_empty();
+                    if ui
+                        .add_enabled(can_paste, Button::new(tr("Paste")))
+                        .clicked()
+                    {
+                        editor.insert(self.ui_state.conduit_clipboard.clone());
+                    }
+                    let can_insert_stack = !self.world.player.person.stack.is_empty();
+                    if ui
+                        .add_enabled(can_insert_stack, Button::new(tr("Insert stack")))
+                        .clicked()
+                    {
+                        editor.insert(self.world.player.person.stack.words());
+                    }
+                });
+                // Known words
+                ui.horizontal_wrapped(|ui| {
+                    let progression = &self.world.player.progression;
+                    for word in all::<Word>().filter(|word| progression.known_words.contains(word))
+                    {
+                        let mut text = RichText::new(word.to_string());
+                        if let Some(color) = word.text_color() {
+                            text = text.color(color);
+                        }
+                        if ui.small_button(text).clicked() {
+                            editor.insert([word]);
+                        }
+                    }
+                });
+                ui.separator();
+                // Check results and preview
+                let params = conduit_params(&editor.words);
+                if !params.is_empty() {
+                    ui.label(format!(
+                        "{}: {}",
+                        tr("Parameters"),
+                        params.iter().join(", ")
+                    ));
+                }
+                ui.checkbox(&mut editor.on_stack, tr("Apply to current stack"));
+                match &res {
+                    Ok(stack) => {
+                        ui.horizontal(|ui| {
+                            for item in stack.iter() {
+                                let _ =
+                                    self.plot_stack_field(ui, SMALL_PLOT_SIZE, 1.0, &item.field);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+                    }
+                }
+                ui.separator();
+                // Trigger
+                trigger_ui(ui, &mut editor.trigger);
+                ui.separator();
+                // Sharing
+                self.conduit_code_ui(ui, &mut editor);
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let can_etch = res.is_ok() && etch_res.is_ok();
+                    if ui.add_enabled(can_etch, Button::new(tr("Etch"))).clicked() {
+                        let command = Command::Etch {
+                            stone: editor.stone,
+                            words: editor.words.clone(),
+                            trigger: editor.trigger,
+                        };
+                        self.command(PersonId::Player, command);
+                        close = true;
+                    }
+                    if ui.button(tr("Cancel")).clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if open && !close {
+            self.ui_state.conduit_editor = Some(editor);
+        }
+    }
+    /// Show the export code of the words being edited and a field to import a code
+    fn conduit_code_ui(&self, ui: &mut Ui, editor: &mut ConduitEditor) {
+        // Export
+        match export_code(&editor.words) {
+            Ok(mut code) => {
+                ui.horizontal(|ui| {
+                    ui.label(tr("Code"));
+                    TextEdit::singleline(&mut code)
+                        .desired_width(200.0)
+                        .show(ui);
+                    if ui.button(tr("Copy code")).clicked() {
+                        ui.output_mut(|output| output.copied_text = code.clone());
+                    }
+                });
+            }
+            Err(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+            }
+        }
+        // Import
+        ui.horizontal(|ui| {
+            ui.label(tr("Import"));
+            TextEdit::singleline(&mut editor.import_code)
+                .desired_width(200.0)
+                .show(ui);
+        });
+        if editor.import_code.trim().is_empty() {
+            return;
+        }
+        match import_code(&editor.import_code) {
+            Ok(words) => {
+                let known_words = &self.world.player.progression.known_words;
+                let unknown = words
+                    .iter()
+                    .filter(|word| !known_words.contains(word))
+                    .unique()
+                    .collect_vec();
+                ui.label(format!(
+                    "{} ({} {})",
+                    words.iter().join(" "),
+                    words_cost(&words),
+                    tr("mana")
+                ));
+                if unknown.is_empty() {
+                    if ui.button(tr("Import")).clicked() {
+                        editor.cursor = words.len();
+                        editor.words = words;
+                        editor.import_code.clear();
+                    }
+                } else {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        format!("{}: {}", tr("Unknown words"), unknown.iter().join(" ")),
+                    );
+                }
+            }
+            Err(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, tr(&e.to_string()));
+            }
+        }
+    }
+    /// Show the words being edited, which can be clicked to move the cursor,
+    /// right-clicked to delete, and dragged to reorder
+    fn conduit_words_ui(
+        &self,
+        ui: &mut Ui,
+        editor: &mut ConduitEditor,
+        error_index: Option<usize>,
+    ) {
+        let mut rects = Vec::with_capacity(editor.words.len());
+        let mut to_delete = None;
+        ui.horizontal_wrapped(|ui| {
+            ui.spacing_mut().item_spacing.x = 2.0;
+            for i in 0..=editor.words.len() {
+                // Cursor
+                if i == editor.cursor {
+                    ui.label(RichText::new("|").strong());
+                }
+                let Some(word) = editor.words.get(i) else {
+                    break;
+                };
+                // Word
+                let mut text = RichText::new(word.to_string()).heading();
+                if error_index == Some(i) {
+                    text = text.color(ui.visuals().error_fg_color).underline();
+                } else if let Some(color) = word.text_color() {
+                    text = text.color(color);
+                }
+                if editor.dragging == Some(i) {
+                    text = text.weak();
+                }
+                let resp = ui.add(Label::new(text).sense(Sense::click_and_drag()));
+                if resp.drag_started() {
+                    editor.dragging = Some(i);
+                }
+                if resp.clicked() {
+                    editor.cursor = i + 1;
+                }
+                if resp.secondary_clicked() {
+                    to_delete = Some(i);
+                }
+                rects.push(resp.rect);
+            }
+            if editor.words.is_empty() {
+                ui.weak("...");
+            }
+        });
+        // Finish dragging
+        if let Some(from) = editor.dragging {
+            if ui.input(|input| input.pointer.any_released()) {
+                editor.dragging = None;
+                if let Some(pos) = ui.input(|input| input.pointer.interact_pos()) {
+                    let to = rects
+                        .iter()
+                        .position(|rect| {
+                            pos.y < rect.top() || pos.y < rect.bottom() && pos.x < rect.center().x
+                        })
+                        .unwrap_or(rects.len());
+                    editor.move_word(from, to);
+                }
+            }
+        }
+        if let Some(i) = to_delete {
+            editor.words.remove(i);
+            if editor.cursor > i {
+                editor.cursor -= 1;
+            }
+        }
+    }
+}
+
+/// The translated name of a trigger event's kind
+fn trigger_name(event: TriggerEvent) -> String {
+    match event {
+        TriggerEvent::Activation(ControlKind::Activation1) => format!("{} 1", tr("Activation")),
+        TriggerEvent::Activation(ControlKind::Activation2) => format!("{} 2", tr("Activation")),
+        TriggerEvent::Activation(ControlKind::Activation3) => format!("{} 3", tr("Activation")),
+        TriggerEvent::Activation(kind) => format!("{} ({kind:?})", tr("Activation")),
+        TriggerEvent::Timer(period) => format!("{} ({period}s)", tr("Timer")),
+        TriggerEvent::Memory { .. } => tr("Memory"),
+        TriggerEvent::Enter(_) => tr("Region"),
+    }
+}
+
+/// Show controls for choosing the event that runs a stone
+fn trigger_ui(ui: &mut Ui, trigger: &mut Option<TriggerEvent>) {
+    let choices = [
+        (tr("None"), None),
+        (
+            format!("{} 1", tr("Activation")),
+            Some(TriggerEvent::Activation(ControlKind::Activation1)),
+        ),
+        (
+            format!("{} 2", tr("Activation")),
+            Some(TriggerEvent::Activation(ControlKind::Activation2)),
+        ),
+        (
+            format!("{} 3", tr("Activation")),
+            Some(TriggerEvent::Activation(ControlKind::Activation3)),
+        ),
+        (tr("Timer"), Some(TriggerEvent::Timer(1.0))),
+        (
+            tr("Memory"),
+            Some(TriggerEvent::Memory {
+                pos: Pos2::ZERO,
+                threshold: 0.5,
+            }),
+        ),
+        (
+            tr("Region"),
+            Some(TriggerEvent::Enter(Rect::from_center_size(
+                pos2(0.0, 1.0),
+                vec2(2.0, 2.0),
+            ))),
+        ),
+    ];
+    let same_kind = |a: Option<TriggerEvent>, b: Option<TriggerEvent>| match (a, b) {
+        (Some(TriggerEvent::Activation(a)), Some(TriggerEvent::Activation(b))) => a == b,
+        (a, b) => a.as_ref().map(discriminant) == b.as_ref().map(discriminant),
+    };
+    let selected = choices
+        .iter()
+        .find(|(_, choice)| same_kind(*trigger, *choice))
+        .map(|(name, _)| name.clone())
+        .unwrap_or_default();
+    ui.horizontal(|ui| {
+        ui.label(tr("Trigger"));
+        ComboBox::from_id_source("conduit trigger")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                for (name, choice) in choices {
+                    let is_selected = same_kind(*trigger, choice);
+                    if ui.selectable_label(is_selected, name).clicked() && !is_selected {
+                        *trigger = choice;
+                    }
+                }
+            });
+    });
+    match trigger {
+        Some(TriggerEvent::Timer(period)) => {
+            ui.horizontal(|ui| {
+                ui.label(tr("Period"));
+                ui.add(
+                    DragValue::new(period)
+                        .speed(0.1)
+                        .clamp_range(0.1..=60.0)
+                        .suffix("s"),
+                );
+            });
+        }
+        Some(TriggerEvent::Memory { pos, threshold }) => {
+            ui.horizontal(|ui| {
+                ui.label(tr("Position"));
+                ui.add(DragValue::new(&mut pos.x).speed(0.1));
+                ui.add(DragValue::new(&mut pos.y).speed(0.1));
+                ui.label(tr("Threshold"));
+                ui.add(DragValue::new(threshold).speed(0.05));
+            });
+        }
+        Some(TriggerEvent::Enter(rect)) => {
+            ui.horizontal(|ui| {
+                ui.label(tr("From"));
+                ui.add(DragValue::new(&mut rect.min.x).speed(0.1));
+                ui.add(DragValue::new(&mut rect.min.y).speed(0.1));
+                ui.label(tr("To"));
+                ui.add(DragValue::new(&mut rect.max.x).speed(0.1));
+                ui.add(DragValue::new(&mut rect.max.y).speed(0.1));
+            });
+            *rect = Rect::from_two_pos(rect.min, rect.max);
+        }
+        _ => {}
+    }
+}
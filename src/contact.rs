@@ -0,0 +1,150 @@
+use eframe::egui::*;
+use rapier2d::prelude::{ColliderHandle, CollisionEvent};
+use serde::Deserialize;
+
+use crate::{
+    math::Convert,
+    object::{ObjectKind, OBJECTS},
+    world::World,
+};
+
+/// The number of ticks contacts are remembered for
+pub const CONTACT_MEMORY_TICKS: u64 = 60;
+/// The distance at which an impact is no longer felt
+const IMPACT_RADIUS: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContactKind {
+    Started,
+    Stopped,
+    /// A hard contact with the given impulse
+    Impact(f32),
+}
+
+/// Something that happened between two objects on a tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactEvent {
+    pub tick: u64,
+    pub kind: ContactKind,
+    /// The names of the objects, with the ground called `ground`
+    pub names: [String; 2],
+    pub pos: Pos2,
+}
+
+/// Two objects that touch, by name
+///
+/// A missing `b` matches any object. The ground is called `ground`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContactFilter {
+    pub a: String,
+    #[serde(default)]
+    pub b: Option<String>,
+}
+
+impl ContactFilter {
+    pub fn matches(&self, [x, y]: &[String; 2]) -> bool {
+        let b_matches = |name: &String| self.b.iter().all(|b| b == name);
+        (&self.a == x && b_matches(y)) || (&self.a == y && b_matches(x))
+    }
+    /// Make sure the filter only names objects that exist
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for name in std::iter::once(&self.a).chain(&self.b) {
+            if name != "ground" && !OBJECTS.contains_key(name) {
+                anyhow::bail!("unknown object {name}");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl World {
+    /// The name and position of the object a collider belongs to
+    fn collider_object(&self, handle: ColliderHandle) -> Option<(String, Pos2)> {
+        let collider = self.physics.colliders.get(handle)?;
+        let obj = self.objects.get(&collider.parent()?)?;
+        let name = if obj.kind == ObjectKind::Ground {
+            "ground".into()
+        } else {
+            obj.def.name.clone()
+        };
+        Some((name, collider.translation().convert()))
+    }
+    /// Log the collisions and impacts of the last physics step and forget old contacts
+    pub fn record_contacts(&mut self) {
+        let tick = self.tick;
+        self.contacts
+            .retain(|event| tick - event.tick < CONTACT_MEMORY_TICKS);
+        let collisions = (self.physics.collisions.iter()).map(|event| match *event {
+            CollisionEvent::Started(a, b, _) => (ContactKind::Started, a, b, None),
+            CollisionEvent::Stopped(a, b, _) => (ContactKind::Stopped, a, b, None),
+        });
+        let impacts = (self.physics.impacts.iter()).map(|impact| {
+            let kind = ContactKind::Impact(impact.impulse);
+            (kind, impact.collider1, impact.collider2, Some(impact.pos))
+        });
+        let mut events = Vec::new();
+        for (kind, a, b, pos) in collisions.chain(impacts) {
+            // Colliders of removed objects have no names
+            let (Some((a, a_pos)), Some((b, b_pos))) =
+                (self.collider_object(a), self.collider_object(b))
+            else {
+                continue;
+            };
+            events.push(ContactEvent {
+                tick,
+                kind,
+                names: [a, b],
+                pos: pos.unwrap_or_else(|| a_pos.lerp(b_pos, 0.5)),
+            });
+        }
+        self.contacts.extend(events);
+    }
+    /// Check whether a collision between objects started recently
+    pub fn collided(&self, filter: &ContactFilter) -> bool {
+        (self.contacts.iter())
+            .any(|event| event.kind == ContactKind::Started && filter.matches(&event.names))
+    }
+    /// The impulse of recent impacts near a position, fading with time and distance
+    pub fn impact_at(&self, pos: Pos2) -> f32 {
+        (self.contacts.iter())
+            .filter_map(|event| {
+                let ContactKind::Impact(impulse) = event.kind else {
+                    return None;
+                };
+                let age = (self.tick - event.tick) as f32 / CONTACT_MEMORY_TICKS as f32;
+                let nearness = 1.0 - event.pos.distance(pos) / IMPACT_RADIUS;
+                Some(impulse * (1.0 - age).max(0.0) * nearness.max(0.0))
+            })
+            .sum()
+    }
+}
+
+#[test]
+fn contact_test() {
+    use crate::player::{Gender, Player};
+    let mut world = World::new(Player::new("Kai".into(), Gender::Male));
+    world.update();
+    // The cubes start out touching the ground
+    let filter = |a: &str, b: Option<&str>| ContactFilter {
+        a: a.into(),
+        b: b.map(Into::into),
+    };
+    assert!(world.collided(&filter("small_cube", Some("ground"))));
+    assert!(!world.collided(&filter("sofa", Some("small_cube"))));
+    // Throw a cube at the ground
+    let (&handle, cube) = (world.objects.iter())
+        .find(|(_, obj)| obj.def.name == "small_cube")
+        .unwrap();
+    let pos = cube.pr.pos;
+    world.physics.bodies[handle].set_linvel([0.0, -20.0].into(), true);
+    for _ in 0..5 {
+        world.update();
+    }
+    assert!(world.impact_at(pos) > 0.1);
+    assert_eq!(world.impact_at(pos2(-4.0, 4.0)), 0.0);
+    // Impacts fade
+    for _ in 0..CONTACT_MEMORY_TICKS {
+        world.update();
+    }
+    assert!(world.impact_at(pos) < 0.1);
+}
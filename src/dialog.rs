@@ -10,79 +10,261 @@ use once_cell::sync::Lazy;
 use serde::Deserialize;
 
 use crate::{
-    field::InputFieldKind,
+    contact::ContactFilter,
+    field::{InputFieldKind, OutputFieldKind, ScalarInputFieldKind},
     game::Game,
     image::{image_plot, ImagePlotKind},
+    locale::{dialog_choice, dialog_line, dialog_next, gender_forms, tr},
+    object::OBJECTS,
+    person::PersonId,
     player::Gender,
+    replay::Command,
     utils::{fatal_error, resources_path},
     word::Word,
     world::World,
 };
 
-type DialogScenes = HashMap<String, DialogScene<DeserializedLine>>;
+pub type DialogScenes = HashMap<String, DialogScene<DeserializedLine>>;
 
 pub static DIALOG_SCENES: Lazy<DialogScenes> =
-    Lazy::new(|| load_scenes().map_err(fatal_error).unwrap());
+    Lazy::new(|| load_scenes().unwrap_or_else(|errors| fatal_error(errors.iter().join("\n"))));
 
-fn load_scenes() -> anyhow::Result<DialogScenes> {
+/// Load and validate all dialog scenes
+pub fn load_scenes() -> Result<DialogScenes, Vec<anyhow::Error>> {
+    let mut errors = Vec::new();
+    let scenes = read_scenes(&mut errors);
+    if errors.is_empty() {
+        Ok(scenes)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Load and validate all dialog scenes, collecting every error found
+/// and returning the scenes that could be read
+pub fn read_scenes(errors: &mut Vec<anyhow::Error>) -> DialogScenes {
     let mut map = HashMap::new();
-    for entry in fs::read_dir(resources_path().join("dialog"))
-        .map_err(|e| fatal_error(format!("Unable to open dialog directory: {e}")))
-        .unwrap()
-    {
-        let entry = entry.unwrap();
-        if entry.file_type()?.is_file() {
-            let path = entry.path();
-            if path.extension().map_or(false, |ext| ext == "yaml") {
-                let yaml = fs::read_to_string(&path)?;
-                let name = path.file_stem().unwrap().to_string_lossy().into_owned();
-                let scene: DialogScene<SerializedLine> = serde_yaml::from_str(&yaml)
-                    .map_err(|e| anyhow!("Unable to read {name} dialog: {e}"))?;
-                if scene.nodes.is_empty() {
-                    continue;
-                }
-                let scene: DialogScene<DeserializedLine> = scene
-                    .try_into()
-                    .map_err(|e| anyhow!("Error parsing fragment in {name}: {e}"))?;
-                for (node_name, node) in &scene.nodes {
-                    validate_children(&name, &scene, node_name, &node.children)?;
-                }
+    let entries = match fs::read_dir(resources_path().join("dialog")) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(anyhow!("Unable to open dialog directory: {e}"));
+            return map;
+        }
+    };
+    for entry in entries {
+        match load_scene(entry) {
+            Ok(Some((name, scene))) => {
                 map.insert(name, scene);
             }
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    validate_scenes(&map, errors);
+    map
+}
+
+fn load_scene(
+    entry: std::io::Result<fs::DirEntry>,
+) -> anyhow::Result<Option<(String, DialogScene<DeserializedLine>)>> {
+    let entry = entry?;
+    let path = entry.path();
+    if !entry.file_type()?.is_file() || path.extension() != Some("yaml".as_ref()) {
+        return Ok(None);
+    }
+    let yaml = fs::read_to_string(&path)?;
+    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+    Ok(parse_scene(&name, &yaml)?.map(|scene| (name, scene)))
+}
+
+/// Parse a scene's yaml, returning `None` if it has no nodes
+pub fn parse_scene(
+    name: &str,
+    yaml: &str,
+) -> anyhow::Result<Option<DialogScene<DeserializedLine>>> {
+    let scene: DialogScene<SerializedLine> =
+        serde_yaml::from_str(yaml).map_err(|e| anyhow!("Unable to read {name} dialog: {e}"))?;
+    if scene.nodes.is_empty() {
+        return Ok(None);
+    }
+    let scene = scene
+        .try_into()
+        .map_err(|e| anyhow!("Error parsing fragment in {name}: {e}"))?;
+    Ok(Some(scene))
+}
+
+/// Check the references between scenes
+fn validate_scenes(scenes: &DialogScenes, errors: &mut Vec<anyhow::Error>) {
+    for (scene_name, scene) in scenes.iter().sorted_by_key(|(name, _)| *name) {
+        for (node_name, node) in &scene.nodes {
+            validate_commands(scenes, scene_name, node_name, node, errors);
+            validate_children(scenes, scene_name, node_name, &node.children, errors);
+        }
+    }
+    validate_variables(scenes, errors);
+}
+
+/// Split a node target into its scene and node names
+///
+/// Targets of the form `scene/node` refer to a node in another scene.
+/// Other targets refer to a node in the current scene.
+pub fn split_target<'a>(current_scene: &'a str, target: &'a str) -> (&'a str, &'a str) {
+    target.split_once('/').unwrap_or((current_scene, target))
+}
+
+/// Split a scene target into its scene name and optional node name
+///
+/// Targets of the form `scene/node` start at the given node.
+/// Other targets start at the first node of the scene.
+pub fn split_scene_target(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('/') {
+        Some((scene, node)) => (scene, Some(node)),
+        None => (target, None),
+    }
+}
+
+fn validate_commands(
+    scenes: &DialogScenes,
+    scene_name: &str,
+    node_name: &str,
+    node: &DialogNode<DeserializedLine>,
+    errors: &mut Vec<anyhow::Error>,
+) {
+    for line in &node.lines {
+        let (Line::Command(DialogCommand::Call(target))
+        | Line::Command(DialogCommand::StartScene(target))) = line
+        else {
+            continue;
+        };
+        let (target_scene, target_node) = split_scene_target(target);
+        let Some(scene) = scenes.get(target_scene) else {
+            errors.push(anyhow!(
+                "In {scene_name} scene, node {node_name} starts unknown scene {target_scene}"
+            ));
+            continue;
+        };
+        if let Some(target_node) = target_node {
+            if !scene.nodes.contains_key(target_node) {
+                errors.push(anyhow!(
+                    "In {scene_name} scene, node {node_name} starts \
+                    {target_scene} scene at unknown node {target_node}"
+                ));
+            }
         }
     }
-    Ok(map)
 }
 
 fn validate_children(
+    scenes: &DialogScenes,
     scene_name: &str,
-    scene: &DialogScene<DeserializedLine>,
     node_name: &str,
     children: &NodeChildren<DeserializedLine>,
-) -> anyhow::Result<()> {
+    errors: &mut Vec<anyhow::Error>,
+) {
     let child_nodes = match children {
         NodeChildren::Choices(choices) => choices.keys().collect_vec(),
         NodeChildren::Jump { jump } => vec![jump],
-        NodeChildren::Condition { then, els, .. } => {
-            validate_children(scene_name, scene, node_name, then)?;
-            validate_children(scene_name, scene, node_name, els)?;
+        NodeChildren::Condition {
+            condition,
+            then,
+            els,
+        } => {
+            validate_condition(scene_name, node_name, condition, errors);
+            validate_children(scenes, scene_name, node_name, then, errors);
+            validate_children(scenes, scene_name, node_name, els, errors);
             Vec::new()
         }
-        NodeChildren::Wait { then: node, .. } => vec![node],
+        NodeChildren::Wait {
+            condition,
+            then: node,
+        } => {
+            match condition {
+                WaitCondition::Until(condition) => {
+                    validate_condition(scene_name, node_name, condition, errors)
+                }
+                WaitCondition::Collision(filter) => {
+                    if let Err(e) = filter.validate() {
+                        errors.push(anyhow!("In {scene_name} scene, node {node_name}: {e}"));
+                    }
+                }
+                _ => {}
+            }
+            vec![node]
+        }
         NodeChildren::List(list) => {
             for child in list {
-                validate_children(scene_name, scene, node_name, child)?;
+                validate_children(scenes, scene_name, node_name, child, errors);
             }
             Vec::new()
         }
         NodeChildren::Next(_) => Vec::new(),
     };
     for child_name in child_nodes {
-        if !scene.nodes.contains_key(child_name) {
-            bail!("In {scene_name} scene, node {node_name}'s child {child_name} does not exist")
+        let (child_scene, child_node) = split_target(scene_name, child_name);
+        let exists = scenes
+            .get(child_scene)
+            .is_some_and(|scene| scene.nodes.contains_key(child_node));
+        if !exists {
+            errors.push(anyhow!(
+                "In {scene_name} scene, node {node_name}'s child {child_name} does not exist"
+            ));
+        }
+    }
+}
+
+fn validate_condition(
+    scene_name: &str,
+    node_name: &str,
+    condition: &Condition,
+    errors: &mut Vec<anyhow::Error>,
+) {
+    for query in condition.queries() {
+        if let WorldQuery::Height(object) = query {
+            if !OBJECTS.contains_key(object) {
+                errors.push(anyhow!(
+                    "In {scene_name} scene, node {node_name} queries unknown object {object}"
+                ));
+            }
+        }
+    }
+    for filter in condition.contacts() {
+        if let Err(e) = filter.validate() {
+            errors.push(anyhow!("In {scene_name} scene, node {node_name}: {e}"));
+        }
+    }
+}
+
+/// Make sure every variable that is read is assigned somewhere
+fn validate_variables(scenes: &DialogScenes, errors: &mut Vec<anyhow::Error>) {
+    let mut assigned = HashSet::default();
+    for scene in scenes.values() {
+        for node in scene.nodes.values() {
+            for line in &node.lines {
+                if let Line::Command(
+                    DialogCommand::SetVar(var, _) | DialogCommand::AddVar(var, _),
+                ) = line
+                {
+                    assigned.insert(var.as_str());
+                }
+            }
+        }
+    }
+    for (scene_name, scene) in scenes.iter().sorted_by_key(|(name, _)| *name) {
+        for (node_name, node) in &scene.nodes {
+            for condition in node.children.conditions() {
+                for query in condition.queries() {
+                    if let WorldQuery::Var(var) = query {
+                        if !assigned.contains(var.as_str()) {
+                            errors.push(anyhow!(
+                                "In {scene_name} scene, node {node_name} reads \
+                                variable {var}, which is never set"
+                            ));
+                        }
+                    }
+                }
+            }
         }
     }
-    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
@@ -95,6 +277,11 @@ pub struct DialogScene<T> {
 pub struct DialogNode<T> {
     #[serde(default = "Vec::new")]
     pub lines: Vec<Line<T>>,
+    /// The ids of the lines that have them, by index
+    ///
+    /// Translations refer to lines by id so that they survive lines being added.
+    #[serde(skip)]
+    pub line_ids: HashMap<usize, String>,
     #[serde(default = "NodeChildren::default")]
     pub children: NodeChildren<T>,
 }
@@ -132,18 +319,88 @@ impl<T> Default for NodeChildren<T> {
 #[serde(rename_all = "snake_case")]
 pub enum Condition {
     FieldKnown(InputFieldKind),
+    WordKnown(Word),
     Flag(String),
+    SpellActive(OutputFieldKind),
+    /// Two objects started touching recently
+    Collided(ContactFilter),
+    Less(Quantity, Quantity),
+    Greater(Quantity, Quantity),
+    Equal(Quantity, Quantity),
     Not(Box<Self>),
     And(Vec<Self>),
     Or(Vec<Self>),
 }
 
+impl Condition {
+    /// Get all the world queries used by this condition
+    fn queries(&self) -> Vec<&WorldQuery> {
+        match self {
+            Condition::Less(a, b) | Condition::Greater(a, b) | Condition::Equal(a, b) => {
+                [a, b].into_iter().filter_map(Quantity::query).collect()
+            }
+            Condition::Not(inner) => inner.queries(),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().flat_map(Self::queries).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+    /// Get all the contact filters used by this condition
+    fn contacts(&self) -> Vec<&ContactFilter> {
+        match self {
+            Condition::Collided(filter) => vec![filter],
+            Condition::Not(inner) => inner.contacts(),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().flat_map(Self::contacts).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A number used in a dialog comparison
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Quantity {
+    Number(f32),
+    Query(WorldQuery),
+}
+
+impl Quantity {
+    fn query(&self) -> Option<&WorldQuery> {
+        match self {
+            Quantity::Number(_) => None,
+            Quantity::Query(query) => Some(query),
+        }
+    }
+}
+
+/// A number read from the dialog or world state
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorldQuery {
+    /// A dialog variable
+    Var(String),
+    /// The player's available mana
+    Mana,
+    /// The height of the highest object with the given name
+    Height(String),
+    /// The value of an input field at a point
+    Sample {
+        field: ScalarInputFieldKind,
+        at: [f32; 2],
+    },
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum WaitCondition {
     KnowField(InputFieldKind),
     SayWord(Word),
     EmptyStack,
+    Until(Condition),
+    Collision(ContactFilter),
 }
 
 #[derive(Debug, Deserialize)]
@@ -157,6 +414,11 @@ pub enum Line<T> {
 #[serde(untagged)]
 enum SerializedLine {
     String(String),
+    /// A line of text with an id for translations
+    Identified {
+        id: String,
+        text: String,
+    },
     Catch(serde_yaml::Value),
 }
 
@@ -172,9 +434,22 @@ pub enum DialogCommand {
     RevealManaBar,
     RevealFree,
     RevealConduit,
+    /// Give the player the next stone for their conduit rack
+    AddConduitStone,
+    /// Take the current state of the objects in a circle as their ordered state
+    RecordOrder {
+        at: [f32; 2],
+        radius: f32,
+    },
     RevealField(InputFieldKind),
     Set(String),
     Unset(String),
+    SetVar(String, f32),
+    AddVar(String, f32),
+    /// Run another scene, then return to the line after this one
+    Call(String),
+    /// Switch to another scene without returning
+    StartScene(String),
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -185,13 +460,13 @@ pub enum Speaker {
 }
 
 impl Speaker {
-    fn name(&self) -> &str {
+    pub fn name(&self) -> &str {
         match self {
             Speaker::Npc(name) => name,
             Speaker::Image { name, .. } => name,
         }
     }
-    fn image(&self) -> Cow<str> {
+    pub fn image(&self) -> Cow<'_, str> {
         match self {
             Speaker::Npc(name) => Cow::Owned(format!("{}.png", name)),
             Speaker::Image { image, .. } => image.into(),
@@ -207,16 +482,16 @@ pub enum CurrentSpeaker {
 }
 
 impl CurrentSpeaker {
-    fn name(&self) -> &str {
+    pub fn name(&self) -> &str {
         match self {
             CurrentSpeaker::Stranger { stranger } => stranger,
             CurrentSpeaker::Npc(name) => name,
         }
     }
-    fn display(&self) -> &str {
+    fn display(&self) -> String {
         match self {
-            CurrentSpeaker::Stranger { .. } => "Stranger",
-            CurrentSpeaker::Npc(name) => name,
+            CurrentSpeaker::Stranger { .. } => tr("Stranger"),
+            CurrentSpeaker::Npc(name) => name.clone(),
         }
     }
 }
@@ -232,6 +507,10 @@ pub enum DialogFragment {
 pub enum DialogVariable {
     Variable(Variable),
     Gendered(GenderedWord),
+    /// A word whose form depends on the player's gender in the current locale
+    Inflected {
+        gendered: String,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -254,7 +533,24 @@ pub enum GenderedWord {
     Adult,
 }
 
-type DeserializedLine = Vec<DialogFragment>;
+impl GenderedWord {
+    /// The key of this word in locale gender tables
+    pub fn key(&self) -> &'static str {
+        match self {
+            GenderedWord::Sub => "sub",
+            GenderedWord::Obj => "obj",
+            GenderedWord::Pos => "pos",
+            GenderedWord::Reflexive => "reflexive",
+            GenderedWord::SubIs => "sub_is",
+            GenderedWord::SubWas => "sub_was",
+            GenderedWord::Subs => "subs",
+            GenderedWord::Has => "has",
+            GenderedWord::Adult => "adult",
+        }
+    }
+}
+
+pub type DeserializedLine = Vec<DialogFragment>;
 
 impl TryFrom<DialogScene<SerializedLine>> for DialogScene<DeserializedLine> {
     type Error = anyhow::Error;
@@ -263,11 +559,19 @@ impl TryFrom<DialogScene<SerializedLine>> for DialogScene<DeserializedLine> {
         let mut nodes = IndexMap::new();
         for (name, node) in scene.nodes {
             let mut lines = Vec::new();
+            let mut line_ids = HashMap::new();
             for line in node.lines {
                 lines.push(match line {
                     Line::Text(SerializedLine::String(text)) => {
                         parser.parse(text).map_err(|mut e| anyhow!(e.remove(0)))?
                     }
+                    Line::Text(SerializedLine::Identified { id, text }) => {
+                        if line_ids.values().contains(&id) {
+                            bail!("Node {name} has multiple lines with id {id}")
+                        }
+                        line_ids.insert(lines.len(), id);
+                        parser.parse(text).map_err(|mut e| anyhow!(e.remove(0)))?
+                    }
                     Line::Command(com) => Line::Command(com),
                     Line::Text(SerializedLine::Catch(value)) => {
                         bail!(
@@ -281,6 +585,7 @@ impl TryFrom<DialogScene<SerializedLine>> for DialogScene<DeserializedLine> {
                 name,
                 DialogNode {
                     lines,
+                    line_ids,
                     children: node.children.try_into()?,
                 },
             );
@@ -350,6 +655,17 @@ trait FragmentParser<T>: Parser<char, T, Error = Simple<char>> {}
 
 impl<P, T> FragmentParser<T> for P where P: Parser<char, T, Error = Simple<char>> {}
 
+/// Parse a single line of dialog text
+pub fn parse_line(text: &str) -> anyhow::Result<DeserializedLine> {
+    match line_parser()
+        .parse(text)
+        .map_err(|mut e| anyhow!(e.remove(0)))?
+    {
+        Line::Text(fragments) => Ok(fragments),
+        Line::Command(_) => bail!("`{text}` is not a line of text"),
+    }
+}
+
 fn line_parser() -> impl FragmentParser<Line<DeserializedLine>> {
     fragments().map(Line::Text).then_ignore(end())
 }
@@ -396,11 +712,115 @@ pub struct DialogState {
     speaker: Option<CurrentSpeaker>,
     can_cast: bool,
     flags: HashSet<String>,
+    variables: HashMap<String, f32>,
+    call_stack: Vec<ReturnPoint>,
+    /// Whether the current line has been added to the transcript since it was reached
+    recorded: bool,
+}
+
+/// Where to resume dialog when a called scene ends
+struct ReturnPoint {
+    scene: String,
+    node: String,
+    line: usize,
 }
 
 const DIALOG_SPEED: usize = 3;
 
 impl DialogState {
+    fn new(scene_name: &str) -> Self {
+        let mut dialog = DialogState {
+            scene: String::new(),
+            node: String::new(),
+            line: 0,
+            character: 0,
+            speaker: None,
+            can_cast: false,
+            left_speaker: None,
+            right_speaker: None,
+            flags: HashSet::default(),
+            variables: HashMap::new(),
+            call_stack: Vec::new(),
+            recorded: false,
+        };
+        dialog.start_scene(&DIALOG_SCENES, scene_name);
+        dialog
+    }
+    /// Go to a node, possibly in another scene
+    fn jump_to(&mut self, target: &str) {
+        let (scene, node) = split_target(&self.scene, target);
+        let (scene, node) = (scene.to_string(), node.to_string());
+        self.scene = scene;
+        self.node = node;
+        self.line = 0;
+        self.character = 0;
+        self.recorded = false;
+    }
+    /// Go to the start of a scene, or to a node in it, abandoning any calls in progress
+    fn start_scene(&mut self, scenes: &DialogScenes, target: &str) {
+        self.call_stack.clear();
+        self.enter_scene(scenes, target);
+    }
+    /// Run a scene, then return to the current line when it ends
+    fn call_scene(&mut self, scenes: &DialogScenes, target: &str) {
+        self.call_stack.push(ReturnPoint {
+            scene: self.scene.clone(),
+            node: self.node.clone(),
+            line: self.line,
+        });
+        self.enter_scene(scenes, target);
+    }
+    fn enter_scene(&mut self, scenes: &DialogScenes, target: &str) {
+        match split_scene_target(target) {
+            (_, Some(_)) => self.jump_to(target),
+            (scene_name, None) => {
+                let scene = &scenes[scene_name];
+                self.scene = scene_name.into();
+                self.node = scene.nodes.first().unwrap().0.clone();
+                self.line = 0;
+                self.character = 0;
+                self.recorded = false;
+            }
+        }
+    }
+    /// Return from a called scene
+    ///
+    /// Returns false if there is nowhere to return to
+    fn return_from_call(&mut self) -> bool {
+        let Some(ret) = self.call_stack.pop() else {
+            return false;
+        };
+        self.scene = ret.scene;
+        self.node = ret.node;
+        self.line = ret.line;
+        self.character = 0;
+        self.recorded = false;
+        true
+    }
+    /// Move on to the next line, returning from calls at the ends of scenes
+    ///
+    /// Returns false if the dialog is over
+    fn advance(&mut self, scenes: &DialogScenes) -> bool {
+        let scene = &scenes[&self.scene];
+        let node = &scene.nodes[&self.node];
+        if self.line < node.lines.len().saturating_sub(1) {
+            self.line += 1;
+            self.character = 0;
+            self.recorded = false;
+        } else {
+            let node_index = scene.nodes.get_index_of(&self.node).unwrap();
+            if let Some((node_name, _)) = scene.nodes.get_index(node_index + 1) {
+                self.node = node_name.clone();
+                self.line = 0;
+                self.character = 0;
+                self.recorded = false;
+            } else if matches!(&node.children, NodeChildren::Choices(choices) if choices.is_empty())
+            {
+                return self.return_from_call() && self.advance(scenes);
+            }
+        }
+        true
+    }
     pub fn allows_casting(&self) -> bool {
         if self.can_cast {
             return true;
@@ -455,6 +875,8 @@ impl NodeChildren<DeserializedLine> {
                 WaitCondition::KnowField(_) => true,
                 WaitCondition::SayWord(_) => true,
                 WaitCondition::EmptyStack => true,
+                WaitCondition::Until(_) => true,
+                WaitCondition::Collision(_) => true,
             },
             NodeChildren::Choices(_) => false,
             NodeChildren::Jump { .. } => false,
@@ -462,13 +884,45 @@ impl NodeChildren<DeserializedLine> {
             NodeChildren::Next(_) => false,
         }
     }
+    /// Get all the conditions used by these children
+    pub fn conditions(&self) -> Vec<&Condition> {
+        match self {
+            NodeChildren::Condition {
+                condition,
+                then,
+                els,
+            } => [condition]
+                .into_iter()
+                .chain(then.conditions())
+                .chain(els.conditions())
+                .collect(),
+            NodeChildren::Wait {
+                condition: WaitCondition::Until(condition),
+                ..
+            } => vec![condition],
+            NodeChildren::List(list) => list.iter().flat_map(Self::conditions).collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl DialogState {
+    fn set_var(&mut self, var: &str, value: f32) {
+        self.variables.insert(var.into(), value);
+    }
+    fn add_var(&mut self, var: &str, value: f32) {
+        *self.variables.entry(var.into()).or_default() += value;
+    }
     fn check_condition(&self, world: &World, condition: &Condition) -> bool {
         match condition {
             Condition::FieldKnown(kind) => world.player.progression.known_fields.contains(kind),
+            Condition::WordKnown(word) => world.player.progression.known_words.contains(word),
             Condition::Flag(flag) => self.flags.contains(flag),
+            Condition::SpellActive(kind) => world.player.person.active_spells.contains(*kind),
+            Condition::Collided(filter) => world.collided(filter),
+            Condition::Less(a, b) => self.quantity(world, a) < self.quantity(world, b),
+            Condition::Greater(a, b) => self.quantity(world, a) > self.quantity(world, b),
+            Condition::Equal(a, b) => self.quantity(world, a) == self.quantity(world, b),
             Condition::Not(inner) => !self.check_condition(world, inner),
             Condition::And(conditions) => conditions
                 .iter()
@@ -478,23 +932,30 @@ impl DialogState {
                 .any(|condition| self.check_condition(world, condition)),
         }
     }
+    fn quantity(&self, world: &World, quantity: &Quantity) -> f32 {
+        let query = match quantity {
+            Quantity::Number(n) => return *n,
+            Quantity::Query(query) => query,
+        };
+        match query {
+            WorldQuery::Var(var) => self.variables.get(var).copied().unwrap_or(0.0),
+            WorldQuery::Mana => world.player.person.capped_mana(),
+            WorldQuery::Height(name) => world
+                .objects
+                .values()
+                .filter(|obj| &obj.def.name == name)
+                .map(|obj| obj.pr.pos.y)
+                .fold(f32::NEG_INFINITY, f32::max),
+            WorldQuery::Sample { field, at: [x, y] } => {
+                world.sample_input_scalar_field(*field, pos2(*x, *y), true)
+            }
+        }
+    }
 }
 
 impl Game {
     pub fn set_dialog(&mut self, scene_name: &str) {
-        let scene = &DIALOG_SCENES[scene_name];
-        let dialog = DialogState {
-            scene: scene_name.into(),
-            node: scene.nodes.first().unwrap().0.clone(),
-            line: 0,
-            character: 0,
-            speaker: None,
-            can_cast: false,
-            left_speaker: None,
-            right_speaker: None,
-            flags: HashSet::default(),
-        };
-        self.ui_state.dialog = Some(dialog);
+        self.ui_state.dialog = Some(DialogState::new(scene_name));
     }
     pub fn dialog_ui(&mut self, ui: &mut Ui) {
         if self.ui_state.dialog.is_none() {
@@ -506,22 +967,8 @@ impl Game {
         let Some(dialog) = &mut self.ui_state.dialog else {
             return;
         };
-        let scene = &DIALOG_SCENES[&dialog.scene];
-        let node = &scene.nodes[&dialog.node];
-
-        if dialog.line < node.lines.len().saturating_sub(1) {
-            dialog.line += 1;
-            dialog.character = 0;
-        } else {
-            let node_index = scene.nodes.get_index_of(&dialog.node).unwrap();
-            if let Some((node_name, _)) = scene.nodes.get_index(node_index + 1) {
-                dialog.node = node_name.clone();
-                dialog.line = 0;
-                dialog.character = 0;
-            } else if matches!(&node.children, NodeChildren::Choices(choices) if choices.is_empty())
-            {
-                self.ui_state.dialog = None;
-            }
+        if !dialog.advance(&DIALOG_SCENES) {
+            self.ui_state.dialog = None;
         }
     }
     fn dialog_ui_impl(&mut self, ui: &mut Ui) {
@@ -539,9 +986,21 @@ impl Game {
         let line = &node.lines[dialog.line];
         match line {
             Line::Text(fragments) => {
+                let fragments = (node.line_ids.get(&dialog.line))
+                    .and_then(|id| dialog_line(&dialog.scene, &dialog.node, id))
+                    .unwrap_or(fragments);
                 // Space the group
                 ui.allocate_at_least(vec2(ui.max_rect().width(), 0.0), Sense::hover());
                 let line_text = self.world.format_dialog_fragments(fragments);
+                // Record the line in the transcript
+                if !dialog.recorded {
+                    let speaker = dialog.speaker.as_ref().map(CurrentSpeaker::display);
+                    self.world
+                        .player
+                        .transcript
+                        .push_line(speaker, line_text.clone());
+                    dialog.recorded = true;
+                }
                 let char_indices = line_text.char_indices().collect_vec();
                 let char_index = dialog.character / DIALOG_SPEED;
                 ui.horizontal(|ui| {
@@ -560,7 +1019,7 @@ impl Game {
                 dialog.character = (dialog.character + 1).min(max_dialog_char);
                 let mut next = || {
                     ui.with_layout(Layout::bottom_up(Align::Max), |ui| {
-                        ui.button("Next").clicked()
+                        ui.button(tr("Next")).clicked()
                     })
                     .inner
                 };
@@ -598,12 +1057,45 @@ impl Game {
                     }
                     DialogCommand::RevealFree => progression.free = true,
                     DialogCommand::RevealConduit => progression.conduit = true,
+                    DialogCommand::AddConduitStone => {
+                        progression.conduit_stones += 1;
+                        let command = Command::GrowRack(progression.conduit_stones);
+                        self.ui_state.command_error = self
+                            .world
+                            .command(PersonId::Player, command)
+                            .err()
+                            .map(|e| e.to_string());
+                    }
+                    DialogCommand::RecordOrder { at, radius } => {
+                        let center = pos2(at[0], at[1]);
+                        let command = Command::RecordOrder {
+                            center,
+                            radius: *radius,
+                        };
+                        self.ui_state.command_error = self
+                            .world
+                            .command(PersonId::Player, command)
+                            .err()
+                            .map(|e| e.to_string());
+                    }
                     DialogCommand::Set(flag) => {
                         dialog.flags.insert(flag.clone());
                     }
                     DialogCommand::Unset(flag) => {
                         dialog.flags.remove(flag);
                     }
+                    DialogCommand::SetVar(var, value) => dialog.set_var(var, *value),
+                    DialogCommand::AddVar(var, value) => dialog.add_var(var, *value),
+                    DialogCommand::Call(target) => {
+                        dialog.call_scene(&DIALOG_SCENES, target);
+                        self.dialog_ui_impl(ui);
+                        return;
+                    }
+                    DialogCommand::StartScene(target) => {
+                        dialog.start_scene(&DIALOG_SCENES, target);
+                        self.dialog_ui_impl(ui);
+                        return;
+                    }
                 }
                 self.progress_dialog();
                 self.dialog_ui_impl(ui);
@@ -619,7 +1111,7 @@ impl Game {
         let dialog = self.ui_state.dialog.as_mut().unwrap();
         let mut next = || {
             ui.with_layout(Layout::bottom_up(Align::Max), |ui| {
-                ui.button("Next").clicked()
+                ui.button(tr("Next")).clicked()
             })
             .inner
         };
@@ -634,17 +1126,13 @@ impl Game {
                 // Choices
                 ui.with_layout(Layout::bottom_up(Align::Min), |ui| {
                     for (name, fragments) in choices.iter().rev() {
-                        for fragments in fragments.iter().rev() {
-                            if ui
-                                .button(
-                                    RichText::new(self.world.format_dialog_fragments(fragments))
-                                        .heading(),
-                                )
-                                .clicked()
-                            {
-                                dialog.node = name.clone();
-                                dialog.line = 0;
-                                dialog.character = 0;
+                        for (i, fragments) in fragments.iter().enumerate().rev() {
+                            let fragments = dialog_choice(&dialog.scene, &dialog.node, name, i)
+                                .unwrap_or(fragments);
+                            let text = self.world.format_dialog_fragments(fragments);
+                            if ui.button(RichText::new(&text).heading()).clicked() {
+                                self.world.player.transcript.push_choice(text);
+                                dialog.jump_to(name);
                             }
                         }
                     }
@@ -652,9 +1140,7 @@ impl Game {
             }
             NodeChildren::Jump { jump } => {
                 if next() {
-                    dialog.node = jump;
-                    dialog.line = 0;
-                    dialog.character = 0;
+                    dialog.jump_to(&jump);
                 }
             }
             NodeChildren::Condition {
@@ -672,10 +1158,12 @@ impl Game {
                 condition,
                 then: node,
             } => {
-                if self.world.wait_condition(&condition) {
-                    dialog.node = node;
-                    dialog.line = 0;
-                    dialog.character = 0;
+                if self.world.wait_condition(dialog, &condition) {
+                    dialog.jump_to(&node);
+                }
+                // Let the player reread what they missed while casting
+                if ui.small_button(tr("History")).clicked() {
+                    self.ui_state.show_transcript = true;
                 }
                 ui.allocate_exact_size(ui.available_size(), Sense::hover());
             }
@@ -687,12 +1175,15 @@ impl Game {
             NodeChildren::Next(fragments) => {
                 let clicked = ui
                     .with_layout(Layout::bottom_up(Align::Min), |ui| {
-                        fragments.iter().any(|fragments| {
-                            ui.button(
-                                RichText::new(self.world.format_dialog_fragments(fragments))
-                                    .heading(),
-                            )
-                            .clicked()
+                        fragments.iter().enumerate().any(|(i, fragments)| {
+                            let fragments =
+                                dialog_next(&dialog.scene, &dialog.node, i).unwrap_or(fragments);
+                            let text = self.world.format_dialog_fragments(fragments);
+                            let clicked = ui.button(RichText::new(&text).heading()).clicked();
+                            if clicked {
+                                self.world.player.transcript.push_choice(text);
+                            }
+                            clicked
                         })
                     })
                     .inner;
@@ -705,11 +1196,13 @@ impl Game {
 }
 
 impl World {
-    fn wait_condition(&self, condition: &WaitCondition) -> bool {
+    fn wait_condition(&self, dialog: &DialogState, condition: &WaitCondition) -> bool {
         match condition {
             WaitCondition::SayWord(word) => self.player.person.stack.words().last() == Some(*word),
             WaitCondition::KnowField(kind) => self.player.progression.known_fields.contains(kind),
             WaitCondition::EmptyStack => self.player.person.stack.is_empty(),
+            WaitCondition::Until(condition) => dialog.check_condition(self, condition),
+            WaitCondition::Collision(filter) => self.collided(filter),
         }
     }
     fn format_dialog_fragments(&self, fragments: &[DialogFragment]) -> String {
@@ -721,35 +1214,41 @@ impl World {
                     DialogVariable::Variable(var) => match var {
                         Variable::Name => &self.player.name,
                     },
-                    DialogVariable::Gendered(pronoun) => match (pronoun, self.player.gender) {
-                        (GenderedWord::Sub, Gender::Male) => "he",
-                        (GenderedWord::Obj, Gender::Male) => "him",
-                        (GenderedWord::Pos, Gender::Male) => "his",
-                        (GenderedWord::Reflexive, Gender::Male) => "himself",
-                        (GenderedWord::SubIs, Gender::Male) => "he is",
-                        (GenderedWord::SubWas, Gender::Male) => "he was",
-                        (GenderedWord::Subs, Gender::Male) => "he's",
-                        (GenderedWord::Has, Gender::Male) => "has",
-                        (GenderedWord::Adult, Gender::Male) => "man",
-                        (GenderedWord::Sub, Gender::Female) => "she",
-                        (GenderedWord::Obj, Gender::Female) => "her",
-                        (GenderedWord::Pos, Gender::Female) => "her",
-                        (GenderedWord::Reflexive, Gender::Female) => "herself",
-                        (GenderedWord::SubIs, Gender::Female) => "she is",
-                        (GenderedWord::SubWas, Gender::Female) => "she was",
-                        (GenderedWord::Subs, Gender::Female) => "she's",
-                        (GenderedWord::Has, Gender::Female) => "has",
-                        (GenderedWord::Adult, Gender::Female) => "woman",
-                        (GenderedWord::Sub, Gender::Enby) => "they",
-                        (GenderedWord::Obj, Gender::Enby) => "them",
-                        (GenderedWord::Pos, Gender::Enby) => "their",
-                        (GenderedWord::Reflexive, Gender::Enby) => "themselves",
-                        (GenderedWord::SubIs, Gender::Enby) => "they are",
-                        (GenderedWord::SubWas, Gender::Enby) => "they were",
-                        (GenderedWord::Subs, Gender::Enby) => "they're",
-                        (GenderedWord::Has, Gender::Enby) => "have",
-                        (GenderedWord::Adult, Gender::Enby) => "person",
+                    DialogVariable::Gendered(pronoun) => match gender_forms(pronoun.key()) {
+                        Some(forms) => forms.get(self.player.gender),
+                        None => match (pronoun, self.player.gender) {
+                            (GenderedWord::Sub, Gender::Male) => "he",
+                            (GenderedWord::Obj, Gender::Male) => "him",
+                            (GenderedWord::Pos, Gender::Male) => "his",
+                            (GenderedWord::Reflexive, Gender::Male) => "himself",
+                            (GenderedWord::SubIs, Gender::Male) => "he is",
+                            (GenderedWord::SubWas, Gender::Male) => "he was",
+                            (GenderedWord::Subs, Gender::Male) => "he's",
+                            (GenderedWord::Has, Gender::Male) => "has",
+                            (GenderedWord::Adult, Gender::Male) => "man",
+                            (GenderedWord::Sub, Gender::Female) => "she",
+                            (GenderedWord::Obj, Gender::Female) => "her",
+                            (GenderedWord::Pos, Gender::Female) => "her",
+                            (GenderedWord::Reflexive, Gender::Female) => "herself",
+                            (GenderedWord::SubIs, Gender::Female) => "she is",
+                            (GenderedWord::SubWas, Gender::Female) => "she was",
+                            (GenderedWord::Subs, Gender::Female) => "she's",
+                            (GenderedWord::Has, Gender::Female) => "has",
+                            (GenderedWord::Adult, Gender::Female) => "woman",
+                            (GenderedWord::Sub, Gender::Enby) => "they",
+                            (GenderedWord::Obj, Gender::Enby) => "them",
+                            (GenderedWord::Pos, Gender::Enby) => "their",
+                            (GenderedWord::Reflexive, Gender::Enby) => "themselves",
+                            (GenderedWord::SubIs, Gender::Enby) => "they are",
+                            (GenderedWord::SubWas, Gender::Enby) => "they were",
+                            (GenderedWord::Subs, Gender::Enby) => "they're",
+                            (GenderedWord::Has, Gender::Enby) => "have",
+                            (GenderedWord::Adult, Gender::Enby) => "person",
+                        },
                     },
+                    DialogVariable::Inflected { gendered } => gender_forms(gendered)
+                        .map(|forms| forms.get(self.player.gender))
+                        .unwrap_or(gendered),
                 },
             };
             if i == 0
@@ -767,3 +1266,164 @@ impl World {
         formatted
     }
 }
+
+#[test]
+fn dialog_condition_test() {
+    let world = World::test_place("magician_house");
+    let (_, sofa) = world.objects_named("sofa").next().unwrap();
+    let temp =
+        world.sample_input_scalar_field(ScalarInputFieldKind::Temperature, pos2(1.0, 2.0), true);
+    let yaml = format!(
+        "
+start:
+  lines: [Hello]
+  children:
+    if:
+      and:
+        - less: [mana, 51]
+        - greater: [{{ height: sofa }}, {}]
+        - equal: [{{ sample: {{ field: Temperature, at: [1, 2] }} }}, {temp}]
+        - not: {{ greater: [{{ var: gold }}, 0] }}
+    then: {{ jump: yes }}
+    else: {{ jump: no }}
+yes:
+  lines: [Yes]
+no:
+  lines: [No]
+",
+        sofa.pr.pos.y - 0.5
+    );
+    let scene = parse_scene("a", &yaml).unwrap().unwrap();
+    let conditions = scene.nodes["start"].children.conditions();
+    let Condition::And(parts) = conditions[0] else {
+        panic!("expected and, got {:?}", conditions[0]);
+    };
+    assert!(matches!(
+        parts[..],
+        [
+            Condition::Less(Quantity::Query(WorldQuery::Mana), Quantity::Number(_)),
+            Condition::Greater(Quantity::Query(WorldQuery::Height(_)), _),
+            Condition::Equal(Quantity::Query(WorldQuery::Sample { .. }), _),
+            Condition::Not(_),
+        ]
+    ));
+    let dialog = DialogState::new("intro");
+    assert!(dialog.check_condition(&world, conditions[0]));
+    for part in parts {
+        assert!(dialog.check_condition(&world, part), "{part:?}");
+    }
+    // Reading a variable that is never set and an unknown object are errors
+    let scenes: DialogScenes = [("a".to_string(), scene)].into_iter().collect();
+    let mut errors = Vec::new();
+    validate_scenes(&scenes, &mut errors);
+    assert!(errors
+        .iter()
+        .any(|e| e.to_string().contains("variable gold")));
+    let scene = parse_scene(
+        "b",
+        "
+start:
+  lines: [Hello]
+  children: { if: { less: [{ height: nothing }, 1] }, then: { jump: start }, else: { jump: start } }
+",
+    )
+    .unwrap()
+    .unwrap();
+    let scenes: DialogScenes = [("b".to_string(), scene)].into_iter().collect();
+    let mut errors = Vec::new();
+    validate_scenes(&scenes, &mut errors);
+    assert!(errors
+        .iter()
+        .any(|e| e.to_string().contains("unknown object nothing")));
+}
+
+#[test]
+fn dialog_variables_test() {
+    let scene = parse_scene(
+        "a",
+        "
+start:
+  lines:
+    - set_var: [gold, 2]
+    - add_var: [gold, 3]
+    - add_var: [debt, -1.5]
+  children: { if: { equal: [{ var: gold }, 5] }, then: { jump: start }, else: { jump: start } }
+",
+    )
+    .unwrap()
+    .unwrap();
+    let node = &scene.nodes["start"];
+    let mut dialog = DialogState::new("intro");
+    for line in &node.lines {
+        match line {
+            Line::Command(DialogCommand::SetVar(var, value)) => dialog.set_var(var, *value),
+            Line::Command(DialogCommand::AddVar(var, value)) => dialog.add_var(var, *value),
+            line => panic!("unexpected line {line:?}"),
+        }
+    }
+    assert_eq!(dialog.variables["gold"], 5.0);
+    assert_eq!(dialog.variables["debt"], -1.5);
+    let world = World::test_place("magician_house");
+    let condition = node.children.conditions()[0];
+    assert!(dialog.check_condition(&world, condition));
+    dialog.add_var("gold", 1.0);
+    assert!(!dialog.check_condition(&world, condition));
+    let mut errors = Vec::new();
+    let scenes: DialogScenes = [("a".to_string(), scene)].into_iter().collect();
+    validate_scenes(&scenes, &mut errors);
+    assert!(errors.is_empty(), "{errors:?}");
+}
+
+#[test]
+fn dialog_call_test() {
+    let scenes: DialogScenes = [
+        ("a", "start: { lines: [A1, call: b, A2] }"),
+        (
+            "b",
+            "start: { lines: [B1, call: c, B2] }\nlast: { lines: [B3] }",
+        ),
+        ("c", "start: { lines: [C1] }"),
+        ("d", "start: { lines: [D1, call: e, D2] }"),
+        ("e", "start: { lines: [E1, start_scene: a/start, E2] }"),
+    ]
+    .into_iter()
+    .map(|(name, yaml)| (name.to_string(), parse_scene(name, yaml).unwrap().unwrap()))
+    .collect();
+    let mut errors = Vec::new();
+    validate_scenes(&scenes, &mut errors);
+    assert!(errors.is_empty(), "{errors:?}");
+    // Run a scene to its end, giving the text of each line
+    let run = |scene_name: &str| {
+        let mut dialog = DialogState::new("intro");
+        dialog.start_scene(&scenes, scene_name);
+        let mut said = Vec::new();
+        loop {
+            match &scenes[&dialog.scene].nodes[&dialog.node].lines[dialog.line] {
+                Line::Text(fragments) => {
+                    said.push(
+                        (fragments.iter())
+                            .filter_map(|fragment| match fragment {
+                                DialogFragment::String(s) => Some(s.clone()),
+                                DialogFragment::Variable(_) => None,
+                            })
+                            .collect::<String>(),
+                    );
+                    if !dialog.advance(&scenes) {
+                        break;
+                    }
+                }
+                Line::Command(DialogCommand::Call(target)) => dialog.call_scene(&scenes, target),
+                Line::Command(DialogCommand::StartScene(target)) => {
+                    dialog.start_scene(&scenes, target)
+                }
+                line => panic!("unexpected line {line:?}"),
+            }
+        }
+        assert!(dialog.call_stack.is_empty());
+        said
+    };
+    // Nested calls return to the line after each call, across scenes and nodes
+    assert_eq!(run("a"), ["A1", "B1", "C1", "B2", "B3", "A2"]);
+    // Starting a scene abandons the calls that led to it
+    assert_eq!(run("d"), ["D1", "E1", "A1", "B1", "C1", "B2", "B3", "A2"]);
+}
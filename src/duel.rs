@@ -0,0 +1,70 @@
+use eframe::egui::*;
+use enum_iterator::all;
+
+use crate::{
+    field::InputFieldKind,
+    game::Game,
+    input::{action_down, Action},
+    locale::tr,
+    person::{Person, PersonId},
+    player::{Gender, Player},
+    word::Word,
+    world::World,
+};
+
+/// The mana each duelist starts with
+const DUEL_MANA: f32 = 50.0;
+/// How fast the rival's target moves, in meters per second
+const RIVAL_TARGET_SPEED: f32 = 5.0;
+
+impl Game {
+    /// Start a local duel between two people sharing the keyboard and mouse
+    ///
+    /// Both know every word, and there is no dialog.
+    pub fn new_duel() -> Self {
+        let mut player = Player::new(tr("Player 1"), Gender::Enby);
+        player.person.max_mana = DUEL_MANA;
+        let progression = &mut player.progression;
+        progression.known_words = all::<Word>().collect();
+        progression.known_fields = all::<InputFieldKind>().collect();
+        progression.mana_bar = true;
+        progression.free = true;
+        let mut world = World::new(player);
+        world.rival = Some(Person::new(DUEL_MANA));
+        world.load_place("arena");
+        Game::from_world(world)
+    }
+    /// Move the rival's target with their keys
+    ///
+    /// The target starts in the middle of the place and stays within it.
+    pub fn move_rival_target(&mut self, ctx: &Context) {
+        let bounds = self.world.max_rect();
+        let Some(rival) = &mut self.world.rival else {
+            return;
+        };
+        if ctx.memory(|mem| mem.focus().is_some()) {
+            return;
+        }
+        let (dir, dt) = ctx.input(|input| {
+            let held = |action| action_down(input, action) as u8 as f32;
+            let dir = vec2(
+                held(Action::RivalTargetRight) - held(Action::RivalTargetLeft),
+                held(Action::RivalTargetUp) - held(Action::RivalTargetDown),
+            );
+            (dir, input.stable_dt)
+        });
+        let target = rival.target.unwrap_or(bounds.center());
+        rival.target = Some(bounds.clamp(target + dir * RIVAL_TARGET_SPEED * dt));
+    }
+    /// Show both duelists' words and controls side by side
+    pub fn duel_words_ui(&mut self, ui: &mut Ui) {
+        ui.columns(2, |uis| {
+            for (ui, person_id) in uis.iter_mut().zip([PersonId::Player, PersonId::Rival]) {
+                ui.horizontal_top(|ui| {
+                    self.words_grid(ui, person_id);
+                    self.controls_ui(ui, person_id);
+                });
+            }
+        });
+    }
+}
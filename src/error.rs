@@ -1,6 +1,10 @@
 use std::{borrow::Cow, error::Error, fmt};
 
-use crate::{field::Type, function::*};
+use crate::{
+    field::{OutputFieldKind, Type},
+    function::*,
+    person::PersonId,
+};
 
 #[derive(Debug)]
 pub enum EidosError {
@@ -15,6 +19,39 @@ pub enum EidosError {
         expected: usize,
         stack_size: usize,
     },
+    InvalidConduitArgument {
+        position: usize,
+        expected: Type,
+        found: Type,
+    },
+    NotEnoughConduitArguments {
+        expected: usize,
+        stack_size: usize,
+    },
+    /// An error saying one of a conduit's words
+    ConduitWord {
+        index: usize,
+        error: Box<EidosError>,
+    },
+    /// A command for a person who is not in the world
+    UnknownPerson(PersonId),
+    /// A command for a conduit stone that is not in the rack
+    UnknownConduit(usize),
+    /// A command to dispel a spell that is not active
+    UnknownSpell {
+        kind: OutputFieldKind,
+        index: usize,
+    },
+    /// Etching more words than a conduit stone can hold
+    ConduitCapacity {
+        stone: usize,
+        capacity: usize,
+        words: usize,
+    },
+    NotEnoughMana {
+        needed: f32,
+        available: f32,
+    },
 }
 
 impl fmt::Display for EidosError {
@@ -37,16 +74,57 @@ impl fmt::Display for EidosError {
                 f,
                 "Not enough arguments to {function}. It expects {expected}, \
                 but the stack {}.",
-                match stack_size {
-                    0 => "is empty".into(),
-                    1 => "only has 1 value".into(),
-                    n => format!("only has {n} values"),
-                }
+                stack_size_description(*stack_size)
+            ),
+            EidosError::InvalidConduitArgument {
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Invalid argument {position} to conduit. Expected {expected} but found {found}."
+            ),
+            EidosError::NotEnoughConduitArguments {
+                expected,
+                stack_size,
+            } => write!(
+                f,
+                "Not enough arguments to conduit. It expects {expected}, but the stack {}.",
+                stack_size_description(*stack_size)
+            ),
+            EidosError::ConduitWord { index, error } => {
+                write!(f, "Word {}: {error}", index + 1)
+            }
+            EidosError::UnknownPerson(person_id) => write!(f, "There is no {person_id:?}."),
+            EidosError::UnknownConduit(i) => write!(f, "There is no conduit stone {}.", i + 1),
+            EidosError::UnknownSpell { kind, index } => {
+                write!(f, "There is no {kind} spell {}.", index + 1)
+            }
+            EidosError::ConduitCapacity {
+                stone,
+                capacity,
+                words,
+            } => write!(
+                f,
+                "Conduit stone {} can only hold {capacity} words, but {words} were given.",
+                stone + 1
+            ),
+            EidosError::NotEnoughMana { needed, available } => write!(
+                f,
+                "Not enough mana. It needs {needed}, but only {available} is available."
             ),
         }
     }
 }
 
+fn stack_size_description(stack_size: usize) -> String {
+    match stack_size {
+        0 => "is empty".into(),
+        1 => "only has 1 value".into(),
+        n => format!("only has {n} values"),
+    }
+}
+
 impl Error for EidosError {}
 
 fn _plural(s: &str, n: usize) -> Cow<str> {
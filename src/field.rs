@@ -1,7 +1,7 @@
 use derive_more::{Display, From};
 use eframe::epaint::{Pos2, Vec2};
 use enum_iterator::Sequence;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{function::*, person::PersonId, world::World};
 
@@ -26,6 +26,17 @@ impl Field {
             Field::Vector(field) => field.controls(),
         }
     }
+    /// Substitute variables in slots starting at `first` with the given arguments
+    pub fn bind(self, first: usize, args: &[Field]) -> Self {
+        match self {
+            Field::Scalar(field) => field.bind(first, args).into(),
+            Field::Vector(field) => field.bind(first, args).into(),
+        }
+    }
+}
+
+fn bound_arg(slot: usize, first: usize, args: &[Field]) -> Option<&Field> {
+    slot.checked_sub(first).and_then(|i| args.get(i))
 }
 
 #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -48,9 +59,10 @@ pub enum ScalarField {
     Index(Box<VectorField>, Box<Self>),
     #[from]
     Input(ScalarInputFieldKind),
-    #[from]
-    Control(ControlKind),
-    Variable,
+    /// A control of the person who cast the field
+    Control(PersonId, ControlKind),
+    /// A conduit parameter, identified by its slot
+    Variable(usize),
 }
 
 #[derive(Debug, Clone, From)]
@@ -63,7 +75,10 @@ pub enum VectorField {
     BinVV(TypedBinOp<HomoBinOp>, Box<Self>, Box<Self>),
     Index(Box<Self>, Box<Self>),
     Input(VectorInputFieldKind),
-    Variable,
+    /// A control of the person who cast the field
+    Control(PersonId, ControlKind),
+    /// A conduit parameter, identified by its slot
+    Variable(usize),
 }
 
 #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, From, Sequence, Deserialize)]
@@ -120,7 +135,9 @@ pub enum InputFieldKind {
     Vector(VectorInputFieldKind),
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, From, Sequence, Deserialize)]
+#[derive(
+    Debug, Display, Clone, Copy, PartialEq, Eq, Hash, From, Sequence, Serialize, Deserialize,
+)]
 #[serde(untagged)]
 pub enum OutputFieldKind {
     Scalar(ScalarOutputFieldKind),
@@ -157,12 +174,17 @@ pub enum ScalarInputFieldKind {
     Magic,
     #[display(fmt = "🕯Light")]
     Light,
+    #[display(fmt = "💥Impact")]
+    Impact,
 }
 
 #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Sequence, Deserialize)]
-pub enum VectorInputFieldKind {}
+pub enum VectorInputFieldKind {
+    #[display(fmt = "🌬Wind")]
+    Wind,
+}
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Sequence, Deserialize)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Sequence, Serialize, Deserialize)]
 pub enum ScalarOutputFieldKind {
     #[display(fmt = "🔥Heat")]
     Heat,
@@ -172,7 +194,7 @@ pub enum ScalarOutputFieldKind {
     Anchor,
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Sequence, Deserialize)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Sequence, Serialize, Deserialize)]
 pub enum VectorOutputFieldKind {
     #[display(fmt = "⬇ Gravity")]
     Gravity,
@@ -182,12 +204,41 @@ pub enum VectorOutputFieldKind {
     Write,
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Debug,
+    Display,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Sequence,
+    Serialize,
+    Deserialize,
+)]
 pub enum ControlKind {
     XSlider,
     YSlider,
     Activation1,
     Activation2,
+    Activation3,
+    /// Ramps up while held
+    Charge,
+    /// Accumulates scrolling
+    Scroll,
+    /// The pointer position over a field
+    Pointer,
+}
+
+impl ControlKind {
+    pub fn ty(&self) -> Type {
+        match self {
+            ControlKind::Pointer => Type::Vector,
+            _ => Type::Scalar,
+        }
+    }
 }
 
 impl ScalarField {
@@ -229,8 +280,8 @@ impl ScalarField {
             ScalarField::Input(kind) => {
                 world.sample_input_scalar_field(*kind, pos, allow_recursion)
             }
-            ScalarField::Control(kind) => world.controls.get(*kind),
-            ScalarField::Variable => pos.to_vec2().length(),
+            ScalarField::Control(person_id, kind) => world.person(*person_id).controls.get(*kind),
+            ScalarField::Variable(_) => pos.to_vec2().length(),
         }
     }
     fn uniform(&self) -> Option<f32> {
@@ -275,10 +326,29 @@ impl ScalarField {
             ScalarField::Index(a, b) => {
                 [a.controls(), b.controls()].into_iter().flatten().collect()
             }
-            ScalarField::Control(kind) => vec![*kind],
+            ScalarField::Control(_, kind) => vec![*kind],
             _ => Vec::new(),
         }
     }
+    /// Substitute variables in slots starting at `first` with the given arguments
+    pub fn bind(self, first: usize, args: &[Field]) -> Self {
+        let bind = |field: Box<Self>| Box::new(field.bind(first, args));
+        match self {
+            ScalarField::Variable(slot) => match bound_arg(slot, first, args) {
+                Some(Field::Scalar(field)) => field.clone(),
+                _ => ScalarField::Variable(slot),
+            },
+            ScalarField::ScalarUn(op, field) => ScalarField::ScalarUn(op, bind(field)).reduce(),
+            ScalarField::VectorUn(op, field) => {
+                ScalarField::VectorUn(op, Box::new(field.bind(first, args))).reduce()
+            }
+            ScalarField::Bin(op, a, b) => ScalarField::Bin(op, bind(a), bind(b)).reduce(),
+            ScalarField::Index(index, field) => {
+                ScalarField::Index(Box::new(index.bind(first, args)), bind(field))
+            }
+            field => field,
+        }
+    }
     pub fn derivative_at(&self, world: &World, pos: Pos2, allow_recursion: bool) -> Vec2 {
         const RANGE: f32 = 0.1;
         let left_x = self.sample(world, pos - Vec2::X * RANGE, allow_recursion);
@@ -318,7 +388,10 @@ impl VectorField {
                 allow_recursion,
             ),
             VectorField::Input(kind) => world.sample_input_vector_field(*kind, pos),
-            VectorField::Variable => pos.to_vec2(),
+            VectorField::Control(person_id, kind) => {
+                world.person(*person_id).controls.get_vector(*kind)
+            }
+            VectorField::Variable(_) => pos.to_vec2(),
         }
     }
     fn uniform(&self) -> Option<Vec2> {
@@ -363,6 +436,7 @@ impl VectorField {
     pub fn controls(&self) -> Vec<ControlKind> {
         match self {
             VectorField::VectorUn(_, field) => field.controls(),
+            VectorField::ScalarUn(_, field) => field.controls(),
             VectorField::BinSV(_, a, b) => {
                 [a.controls(), b.controls()].into_iter().flatten().collect()
             }
@@ -375,7 +449,31 @@ impl VectorField {
             VectorField::Index(a, b) => {
                 [a.controls(), b.controls()].into_iter().flatten().collect()
             }
+            VectorField::Control(_, kind) => vec![*kind],
             _ => Vec::new(),
         }
     }
+    /// Substitute variables in slots starting at `first` with the given arguments
+    pub fn bind(self, first: usize, args: &[Field]) -> Self {
+        let bind = |field: Box<Self>| Box::new(field.bind(first, args));
+        match self {
+            VectorField::Variable(slot) => match bound_arg(slot, first, args) {
+                Some(Field::Vector(field)) => field.clone(),
+                _ => VectorField::Variable(slot),
+            },
+            VectorField::VectorUn(op, field) => VectorField::VectorUn(op, bind(field)).reduce(),
+            VectorField::ScalarUn(op, field) => {
+                VectorField::ScalarUn(op, Box::new(field.bind(first, args)))
+            }
+            VectorField::BinSV(op, a, b) => {
+                VectorField::BinSV(op, a.bind(first, args), bind(b)).reduce()
+            }
+            VectorField::BinVS(op, a, b) => {
+                VectorField::BinVS(op, bind(a), b.bind(first, args)).reduce()
+            }
+            VectorField::BinVV(op, a, b) => VectorField::BinVV(op, bind(a), bind(b)).reduce(),
+            VectorField::Index(index, field) => VectorField::Index(bind(index), bind(field)),
+            field => field,
+        }
+    }
 }
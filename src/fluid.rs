@@ -0,0 +1,408 @@
+use std::ops::{Add, Mul};
+
+use eframe::egui::*;
+use rayon::prelude::*;
+
+use crate::{
+    field::VectorOutputFieldKind,
+    thermal::CellMaterial,
+    world::{ambient_temp_at, World, ABSOLUTE_ZERO, GRAVITY, HEAT_GRID_RESOLUTION},
+};
+
+pub const FLUID_GRID_RESOLUTION: f32 = 0.5;
+/// The number of iterations used to make the air incompressible
+const PRESSURE_ITERATIONS: usize = 30;
+/// The fastest the wind can blow
+const MAX_WIND_SPEED: f32 = 20.0;
+/// The fraction of the wind's speed lost each second
+const WIND_DAMPING: f32 = 0.2;
+/// The acceleration of the air per unit of the force field
+const FORCE_WIND_ACCELERATION: f32 = 1.0;
+/// The temperature above which air gives off smoke
+const SMOKE_TEMP: f32 = 250.0;
+/// The smoke given off each second per degree above [`SMOKE_TEMP`]
+const SMOKE_RATE: f32 = 0.01;
+/// The fraction of smoke that clears each second
+const SMOKE_DECAY: f32 = 0.1;
+/// The density a unit of smoke adds to the air
+pub const SMOKE_DENSITY: f32 = 0.01;
+/// How strongly moving air drags objects along
+pub const DRAG_COEFFICIENT: f32 = 1.0;
+
+/// A coarse grid of moving air
+#[derive(Debug, Clone, Default)]
+pub struct FluidGrid {
+    pub velocity: Vec<Vec<Vec2>>,
+    pub smoke: Vec<Vec<f32>>,
+}
+
+impl FluidGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        FluidGrid {
+            velocity: vec![vec![Vec2::ZERO; height]; width],
+            smoke: vec![vec![0.0; height]; width],
+        }
+    }
+    fn size(&self) -> (usize, usize) {
+        let height = self.velocity.first().map_or(0, Vec::len);
+        (self.velocity.len(), height)
+    }
+}
+
+/// Sample a grid of cell-centered values at a position, blending the four nearest cells
+///
+/// Positions outside the grid take the value at its edge.
+fn sample_grid<T>(grid: &[Vec<T>], min: Pos2, resolution: f32, pos: Pos2) -> Option<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let width = grid.len();
+    let height = grid.first()?.len();
+    if height == 0 {
+        return None;
+    }
+    let x = ((pos.x - min.x) / resolution - 0.5).clamp(0.0, (width - 1) as f32);
+    let y = ((pos.y - min.y) / resolution - 0.5).clamp(0.0, (height - 1) as f32);
+    let (i, j) = (x as usize, y as usize);
+    let (i1, j1) = ((i + 1).min(width - 1), (j + 1).min(height - 1));
+    let (fx, fy) = (x - i as f32, y - j as f32);
+    let bottom = grid[i][j] * (1.0 - fx) + grid[i1][j] * fx;
+    let top = grid[i][j1] * (1.0 - fx) + grid[i1][j1] * fx;
+    Some(bottom * (1.0 - fy) + top * fy)
+}
+
+/// Carry the values of a grid along a velocity grid of the same size
+fn advect<T>(grid: &[Vec<T>], velocity: &[Vec<Vec2>], resolution: f32, dt: f32) -> Vec<Vec<T>>
+where
+    T: Copy + Send + Sync + Add<Output = T> + Mul<f32, Output = T>,
+{
+    (grid.par_iter().enumerate())
+        .map(|(i, col)| {
+            (col.iter().enumerate())
+                .map(|(j, &value)| {
+                    let pos = (vec2(i as f32 + 0.5, j as f32 + 0.5) * resolution).to_pos2();
+                    let from = pos - velocity[i][j] * dt;
+                    sample_grid(grid, Pos2::ZERO, resolution, from).unwrap_or(value)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Carry the heat of air cells along a velocity grid of the same size
+///
+/// Each air cell takes the temperature of the air the wind brings to it, blended from
+/// the air cells around where that air came from, so no new extremes appear and an even
+/// temperature stays even. Air blown in past the sides or top has the temperature `edge`
+/// gives for its height, so heat leaves through them as the wind does. Cells that are
+/// not air keep their heat and lend none to the air.
+fn advect_heat(
+    grid: &mut Vec<Vec<f32>>,
+    velocity: &[Vec<Vec2>],
+    air: &[Vec<bool>],
+    resolution: f32,
+    dt: f32,
+    edge: impl Fn(f32) -> f32 + Sync,
+) {
+    let (width, height) = (grid.len(), grid.first().map_or(0, Vec::len));
+    let size = vec2(width as f32, height as f32) * resolution;
+    let sample = |from: Pos2| -> Option<f32> {
+        if from.x < 0.0 || from.x > size.x || from.y > size.y {
+            return Some(edge(from.y));
+        }
+        // The ground is closed, so air below it comes from just above it
+        let x = (from.x / resolution - 0.5).clamp(0.0, (width - 1) as f32);
+        let y = (from.y.max(0.0) / resolution - 0.5).clamp(0.0, (height - 1) as f32);
+        let (i, j) = (x as usize, y as usize);
+        let (fx, fy) = (x - i as f32, y - j as f32);
+        let corners = [
+            (i, j, (1.0 - fx) * (1.0 - fy)),
+            (i + 1, j, fx * (1.0 - fy)),
+            (i, j + 1, (1.0 - fx) * fy),
+            (i + 1, j + 1, fx * fy),
+        ];
+        let (sum, weight) = (corners.into_iter())
+            .filter(|&(ci, cj, _)| ci < width && cj < height && air[ci][cj])
+            .fold((0.0, 0.0), |(sum, weight), (ci, cj, w)| {
+                (sum + grid[ci][cj] * w, weight + w)
+            });
+        (weight > 0.0).then(|| sum / weight)
+    };
+    let new_grid = (grid.par_iter().enumerate())
+        .map(|(i, col)| {
+            (col.iter().enumerate())
+                .map(|(j, &temp)| {
+                    if !air[i][j] {
+                        return temp;
+                    }
+                    let pos = (vec2(i as f32 + 0.5, j as f32 + 0.5) * resolution).to_pos2();
+                    sample(pos - velocity[i][j] * dt).unwrap_or(temp)
+                })
+                .collect()
+        })
+        .collect();
+    *grid = new_grid;
+}
+
+/// A neighbor of a cell, which is either in the grid, open air beyond the sides
+/// and top, or the ground below
+enum Neighbor {
+    Cell(usize, usize),
+    Open,
+    Ground,
+}
+
+fn neighbor(
+    (width, height): (usize, usize),
+    i: usize,
+    j: usize,
+    (di, dj): (isize, isize),
+) -> Neighbor {
+    let (ni, nj) = (i as isize + di, j as isize + dj);
+    if nj < 0 {
+        Neighbor::Ground
+    } else if ni < 0 || ni >= width as isize || nj >= height as isize {
+        Neighbor::Open
+    } else {
+        Neighbor::Cell(ni as usize, nj as usize)
+    }
+}
+
+/// Remove the divergence from a velocity grid so that air is neither created nor destroyed
+///
+/// Air flows freely across the sides and top of the grid but not into the ground or solid cells.
+fn project(velocity: &mut [Vec<Vec2>], solid: &[Vec<bool>], resolution: f32) {
+    let size = (velocity.len(), velocity.first().map_or(0, Vec::len));
+    let cells = || (0..size.0).flat_map(move |i| (0..size.1).map(move |j| (i, j)));
+    // The velocity across the boundary with a neighbor
+    let flow =
+        |velocity: &[Vec<Vec2>], i: usize, j: usize, offset| match neighbor(size, i, j, offset) {
+            Neighbor::Cell(ni, nj) if solid[ni][nj] => Vec2::ZERO,
+            Neighbor::Cell(ni, nj) => velocity[ni][nj],
+            Neighbor::Open => velocity[i][j],
+            Neighbor::Ground => Vec2::ZERO,
+        };
+    let mut divergence = vec![vec![0.0; size.1]; size.0];
+    for (i, j) in cells().filter(|&(i, j)| !solid[i][j]) {
+        let dx = flow(velocity, i, j, (1, 0)).x - flow(velocity, i, j, (-1, 0)).x;
+        let dy = flow(velocity, i, j, (0, 1)).y - flow(velocity, i, j, (0, -1)).y;
+        divergence[i][j] = (dx + dy) / (2.0 * resolution);
+    }
+    // The pressure of a neighbor, which matches the cell's own where air cannot flow
+    let pressure_at =
+        |pressure: &[Vec<f32>], i: usize, j: usize, offset| match neighbor(size, i, j, offset) {
+            Neighbor::Cell(ni, nj) if !solid[ni][nj] => pressure[ni][nj],
+            Neighbor::Open => 0.0,
+            _ => pressure[i][j],
+        };
+    const OFFSETS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+    let mut pressure = vec![vec![0.0; size.1]; size.0];
+    for _ in 0..PRESSURE_ITERATIONS {
+        let mut next = pressure.clone();
+        for (i, j) in cells().filter(|&(i, j)| !solid[i][j]) {
+            let sum: f32 = (OFFSETS.iter())
+                .map(|&offset| pressure_at(&pressure, i, j, offset))
+                .sum();
+            next[i][j] = (sum - divergence[i][j] * resolution * resolution) / 4.0;
+        }
+        pressure = next;
+    }
+    for (i, j) in cells().filter(|&(i, j)| !solid[i][j]) {
+        let gradient = vec2(
+            pressure_at(&pressure, i, j, (1, 0)) - pressure_at(&pressure, i, j, (-1, 0)),
+            pressure_at(&pressure, i, j, (0, 1)) - pressure_at(&pressure, i, j, (0, -1)),
+        ) / (2.0 * resolution);
+        velocity[i][j] -= gradient;
+    }
+}
+
+impl World {
+    pub fn fluid_grid_width(&self) -> usize {
+        ((self.max_bound.x - self.min_bound.x) / FLUID_GRID_RESOLUTION).ceil() as usize
+    }
+    pub fn fluid_grid_height(&self) -> usize {
+        ((self.max_bound.y - self.min_bound.y) / FLUID_GRID_RESOLUTION).ceil() as usize
+    }
+    fn fluid_cell_center(&self, i: usize, j: usize) -> Pos2 {
+        self.min_bound + vec2(i as f32 + 0.5, j as f32 + 0.5) * FLUID_GRID_RESOLUTION
+    }
+    /// The velocity of the air at a position
+    pub fn wind_at(&self, pos: Pos2) -> Vec2 {
+        sample_grid(
+            &self.fluid.velocity,
+            self.min_bound,
+            FLUID_GRID_RESOLUTION,
+            pos,
+        )
+        .unwrap_or_default()
+    }
+    /// The density of smoke at a position
+    pub fn smoke_at(&self, pos: Pos2) -> f32 {
+        sample_grid(
+            &self.fluid.smoke,
+            self.min_bound,
+            FLUID_GRID_RESOLUTION,
+            pos,
+        )
+        .unwrap_or_default()
+    }
+    /// Move the air and the smoke and heat it carries
+    ///
+    /// `materials` is the material of each heat grid cell. Only heat in the air is carried.
+    pub fn run_fluid(&mut self, materials: &[Vec<CellMaterial>]) {
+        puffin::profile_function!();
+        let dt = self.physics.dt();
+        let (width, height) = self.fluid.size();
+        let solid: Vec<Vec<bool>> = (0..width)
+            .into_par_iter()
+            .map(|i| {
+                (0..height)
+                    .map(|j| self.is_solid_at(self.fluid_cell_center(i, j)))
+                    .collect()
+            })
+            .collect();
+        // Accelerate the air with force, unusual gravity, and the buoyancy of hot air
+        let velocity: Vec<Vec<Vec2>> = (0..width)
+            .into_par_iter()
+            .map(|i| {
+                (0..height)
+                    .map(|j| {
+                        let pos = self.fluid_cell_center(i, j);
+                        let gravity = self.sample_output_vector_field(
+                            VectorOutputFieldKind::Gravity,
+                            pos,
+                            true,
+                        );
+                        let force = self.sample_output_vector_field(
+                            VectorOutputFieldKind::Force,
+                            pos,
+                            true,
+                        );
+                        let temp = self.temperature_at(pos);
+                        let buoyancy =
+                            -gravity * (temp - ambient_temp_at(pos.y)) / (temp - ABSOLUTE_ZERO);
+                        let acc = force * FORCE_WIND_ACCELERATION + (gravity - GRAVITY) + buoyancy;
+                        self.fluid.velocity[i][j] + acc * dt
+                    })
+                    .collect()
+            })
+            .collect();
+        // Carry the air along with itself and keep it from piling up
+        let mut velocity = advect(&velocity, &velocity, FLUID_GRID_RESOLUTION, dt);
+        for (i, j) in (0..width).flat_map(|i| (0..height).map(move |j| (i, j))) {
+            if solid[i][j] {
+                velocity[i][j] = Vec2::ZERO;
+            }
+        }
+        project(&mut velocity, &solid, FLUID_GRID_RESOLUTION);
+        for vel in velocity.iter_mut().flatten() {
+            *vel *= 1.0 - WIND_DAMPING * dt;
+            if vel.length() > MAX_WIND_SPEED {
+                *vel = vel.normalized() * MAX_WIND_SPEED;
+            }
+        }
+        // Carry smoke and give off more from hot air
+        let mut smoke = advect(&self.fluid.smoke, &velocity, FLUID_GRID_RESOLUTION, dt);
+        for (i, j) in (0..width).flat_map(|i| (0..height).map(move |j| (i, j))) {
+            let temp = self.temperature_at(self.fluid_cell_center(i, j));
+            let cell = &mut smoke[i][j];
+            if solid[i][j] {
+                *cell = 0.0;
+            } else {
+                *cell += (temp - SMOKE_TEMP).max(0.0) * SMOKE_RATE * dt;
+                *cell *= 1.0 - SMOKE_DECAY * dt;
+            }
+        }
+        self.fluid = FluidGrid { velocity, smoke };
+        // Carry heat in the air
+        let wind: Vec<Vec<Vec2>> = (self.heat_grid.par_iter().enumerate())
+            .map(|(i, col)| {
+                (0..col.len())
+                    .map(|j| {
+                        let pos = self.min_bound
+                            + vec2(i as f32 + 0.5, j as f32 + 0.5) * HEAT_GRID_RESOLUTION;
+                        self.wind_at(pos)
+                    })
+                    .collect()
+            })
+            .collect();
+        let air: Vec<Vec<bool>> = (materials.iter())
+            .map(|col| col.iter().map(|m| *m == CellMaterial::AIR).collect())
+            .collect();
+        let min_y = self.min_bound.y;
+        advect_heat(
+            &mut self.heat_grid,
+            &wind,
+            &air,
+            HEAT_GRID_RESOLUTION,
+            dt,
+            |y| ambient_temp_at(min_y + y),
+        );
+    }
+}
+
+#[test]
+fn fluid_test() {
+    use crate::{
+        person::PersonId,
+        player::{Gender, Player},
+        replay::Command,
+        word::Word,
+    };
+    // A force field blows the air along
+    let mut world = World::new(Player::new("Kai".into(), Gender::Male));
+    for word in [Word::Te, Word::Ke, Word::Sa, Word::Wu] {
+        world.command(PersonId::Player, Command::Say(word)).unwrap();
+    }
+    for _ in 0..60 {
+        world.update();
+    }
+    assert!(world.wind_at(pos2(0.0, 2.5)).x > 1.0);
+    // Hot air rises
+    let mut world = World::new(Player::new("Kai".into(), Gender::Male));
+    for _ in 0..60 {
+        for col in &mut world.heat_grid[4..8] {
+            for cell in &mut col[0..4] {
+                *cell = 200.0;
+            }
+        }
+        world.update();
+    }
+    let wind = world.wind_at(world.min_bound + vec2(1.5, 2.0));
+    assert!(wind.y > 0.1, "{wind:?}");
+}
+
+#[test]
+fn heat_advection_test() {
+    let resolution = 0.25;
+    let dt = 1.0 / 60.0;
+    let (width, height) = (12, 8);
+    // A wall on the right, with wind blowing into it
+    let air: Vec<Vec<bool>> = (0..width)
+        .map(|i| (0..height).map(|j| i < 9 || j >= 6).collect())
+        .collect();
+    let velocity = vec![vec![vec2(6.0, -2.0); height]; width];
+    let mut grid = vec![vec![-3.0; height]; width];
+    for col in &mut grid[9..] {
+        col[..6].fill(50.0);
+    }
+    for _ in 0..300 {
+        advect_heat(&mut grid, &velocity, &air, resolution, dt, |_| -3.0);
+    }
+    for (i, j) in (0..width).flat_map(|i| (0..height).map(move |j| (i, j))) {
+        if air[i][j] {
+            assert!((grid[i][j] + 3.0).abs() < 1e-4, "{}", grid[i][j]);
+        } else {
+            assert_eq!(grid[i][j], 50.0);
+        }
+    }
+    // Hot air blows out through the open side and is replaced by ambient air
+    let air = vec![vec![true; height]; width];
+    let mut grid = vec![vec![-3.0; height]; width];
+    grid[2][4] = 100.0;
+    let velocity = vec![vec![vec2(-3.0, 0.0); height]; width];
+    for _ in 0..120 {
+        advect_heat(&mut grid, &velocity, &air, resolution, dt, |_| -3.0);
+    }
+    assert!(grid.iter().flatten().all(|&temp| (temp + 3.0).abs() < 1e-3));
+}
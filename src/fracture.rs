@@ -0,0 +1,122 @@
+use eframe::egui::*;
+use itertools::Itertools;
+use rapier2d::prelude::RigidBodyHandle;
+
+use crate::{
+    math::{rotate, Convert},
+    object::{ObjectDef, ObjectKind, OffsetShape},
+    world::World,
+};
+
+/// The force per unit of strength that a contact must exceed to damage an object
+///
+/// Loads below this never wear an object down, so heavy things can rest on light ones.
+const IMPACT_FORCE_PER_STRENGTH: f32 = 10.0;
+
+impl ObjectDef {
+    /// The contact force above which the object takes damage
+    pub fn impact_threshold(&self) -> f32 {
+        self.strength * IMPACT_FORCE_PER_STRENGTH
+    }
+}
+
+impl World {
+    /// Add the impacts of the last physics step to the damage of breakable objects
+    /// and break the ones that can take no more
+    pub fn run_fractures(&mut self) {
+        for impact in &self.physics.impacts {
+            for collider in [impact.collider1, impact.collider2] {
+                let Some(body) = (self.physics.colliders.get(collider)).and_then(|c| c.parent())
+                else {
+                    continue;
+                };
+                if let Some(obj) = self.objects.get_mut(&body) {
+                    // The impact may have been reported for a lower threshold
+                    if obj.def.breakable && impact.force > obj.def.impact_threshold() {
+                        obj.damage += impact.impulse;
+                    }
+                }
+            }
+        }
+        let broken = (self.objects.iter())
+            .filter(|(_, obj)| obj.def.breakable && obj.damage >= obj.def.strength)
+            .map(|(handle, _)| *handle)
+            .collect_vec();
+        for handle in broken {
+            self.break_object(handle);
+        }
+    }
+    /// Replace an object with debris made from its fragments
+    pub fn break_object(&mut self, handle: RigidBodyHandle) {
+        let Some(obj) = self.objects.shift_remove(&handle) else {
+            return;
+        };
+        let angvel = self.physics.bodies[handle].angvel();
+        self.physics.remove_body(handle);
+        let fragments = if obj.def.fragments.is_empty() {
+            &obj.def.shapes
+        } else {
+            &obj.def.fragments
+        };
+        for fragment in fragments {
+            let offset = rotate(fragment.offset, obj.pr.rot);
+            let pos = obj.pr.pos + offset;
+            // Each piece keeps the velocity its part of the spinning object had
+            let vel = obj.vel + angvel * vec2(-offset.y, offset.x);
+            let def = ObjectDef {
+                shapes: vec![OffsetShape {
+                    offset: Vec2::ZERO,
+                    ..fragment.clone()
+                }],
+                background: Vec::new(),
+                far: Vec::new(),
+                breakable: false,
+                fragments: Vec::new(),
+                ..obj.def.clone()
+            };
+            let piece = self.add_object(
+                ObjectKind::Debris,
+                def,
+                |rb| {
+                    rb.translation(pos.convert())
+                        .rotation(obj.pr.rot)
+                        .linvel(vel.convert())
+                        .angvel(angvel)
+                },
+                |c| c,
+            );
+            self.objects[&piece].heat = obj.heat;
+        }
+    }
+}
+
+#[test]
+fn fracture_test() {
+    use crate::object::OBJECTS;
+    let mut world = World::test_place("arena");
+    let count = |world: &World, kind: ObjectKind| {
+        (world.objects_named("stone_pillar"))
+            .filter(|(_, obj)| obj.kind == kind)
+            .count()
+    };
+    let pillars = count(&world, ObjectKind::Object);
+    assert!(pillars > 0);
+    // Nothing breaks under its own weight
+    for _ in 0..120 {
+        world.update();
+    }
+    assert_eq!(count(&world, ObjectKind::Object), pillars);
+    assert!(world
+        .objects
+        .values()
+        .all(|obj| obj.kind != ObjectKind::Debris));
+    // A pillar slammed into the ground shatters
+    let (handle, _) = world.objects_named("stone_pillar").next().unwrap();
+    world.physics.bodies[handle].set_linvel([0.0, -30.0].into(), true);
+    for _ in 0..30 {
+        world.update();
+    }
+    assert_eq!(count(&world, ObjectKind::Object), pillars - 1);
+    let fragments = OBJECTS["stone_pillar"].fragments.len();
+    assert_eq!(count(&world, ObjectKind::Debris), fragments);
+}
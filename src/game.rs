@@ -1,4 +1,7 @@
-use std::{collections::BTreeSet, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
 
 use eframe::egui::{style::Margin, *};
 use enum_iterator::all;
@@ -7,46 +10,74 @@ use itertools::Itertools;
 
 use crate::{
     color::Color,
+    conduit_ui::ConduitEditor,
     controls::{apply_color_fading, FadeButton},
     dialog::DialogState,
+    error::EidosError,
     field::*,
     function::Function,
     image::{image_plot, ImagePlotKind},
-    person::PersonId,
+    input::{
+        action_axis, action_down, action_pointer_button, action_pressed, action_released,
+        input_settings_ui, Action, Rebinding,
+    },
+    locale::tr,
+    person::{Controls, PersonId},
     player::Player,
     plot::*,
+    replay::{Command, Playback},
+    typing::SyllableInput,
     word::*,
-    world::{Controls, World},
+    world::World,
     GameState,
 };
 
 pub struct Game {
     pub world: World,
     pub ui_state: UiState,
+    /// The replay being watched, if any
+    pub playback: Option<Playback>,
     last_time: Instant,
     ticker: f32,
 }
 
 impl Game {
     pub fn new(player: Player) -> Self {
-        let mut game = Game {
-            world: World::new(player),
+        let mut game = Game::from_world(World::new(player));
+        game.set_dialog("intro");
+        game
+    }
+    pub fn from_world(world: World) -> Self {
+        Game {
+            world,
             ui_state: UiState::default(),
+            playback: None,
             last_time: Instant::now(),
             ticker: 0.0,
-        };
-        game.set_dialog("intro");
-        game
+        }
     }
 }
 
 pub struct UiState {
     pub fields_display: IndexMap<FieldKind, FieldDisplay>,
     pub dialog: Option<DialogState>,
-    last_stack_len: usize,
+    last_stack_len: HashMap<PersonId, usize>,
     paused: bool,
     next_player_target: Option<Pos2>,
     pub background: Option<String>,
+    pub show_transcript: bool,
+    export_message: Option<String>,
+    pub conduit_editor: Option<ConduitEditor>,
+    pub conduit_clipboard: Vec<Word>,
+    pub typing: SyllableInput,
+    show_input_settings: bool,
+    rebinding: Option<Rebinding>,
+    input_message: Option<String>,
+    replay_message: Option<String>,
+    /// The error of the last command given from the UI
+    pub command_error: Option<String>,
+    /// The people whose X slider is being set by a gamepad axis
+    axis_held: HashSet<PersonId>,
 }
 
 pub struct FieldDisplay {
@@ -61,15 +92,29 @@ impl Default for UiState {
         UiState {
             fields_display: IndexMap::new(),
             dialog: None,
-            last_stack_len: 0,
+            last_stack_len: HashMap::new(),
             paused: false,
             next_player_target: None,
             background: None,
+            show_transcript: false,
+            export_message: None,
+            conduit_editor: None,
+            conduit_clipboard: Vec::new(),
+            typing: SyllableInput::default(),
+            show_input_settings: false,
+            rebinding: None,
+            input_message: None,
+            replay_message: None,
+            command_error: None,
+            axis_held: HashSet::new(),
         }
     }
 }
 
 impl UiState {
+    pub fn dialog_allows_casting(&self) -> bool {
+        (self.dialog.as_ref()).is_none_or(DialogState::allows_casting)
+    }
     pub fn default_field_display(&self, kind: FieldKind) -> FieldDisplay {
         let index = if let Some(i) = self.fields_display.get_index_of(&kind) {
             i
@@ -97,7 +142,13 @@ impl UiState {
     }
 }
 
-const SMALL_PLOT_SIZE: f32 = 100.0;
+/// The seconds it takes for the charge control to fill
+const CHARGE_TIME: f32 = 2.0;
+/// The seconds it takes a fully pushed gamepad axis to move the Y slider across its range
+const Y_SLIDER_AXIS_TIME: f32 = 1.0;
+/// The scroll distance in points that changes the scroll control by one
+const SCROLL_STEP: f32 = 50.0;
+pub const SMALL_PLOT_SIZE: f32 = 100.0;
 
 impl Game {
     pub fn show(&mut self, ctx: &Context) -> Option<GameState> {
@@ -106,7 +157,11 @@ impl Game {
         let mut res = None;
 
         // Set player target
-        self.world.player.person.target = self.ui_state.next_player_target.take();
+        let next_player_target = self.ui_state.next_player_target.take();
+        if self.playback.is_none() {
+            self.world.player.person.target = next_player_target;
+            self.move_rival_target(ctx);
+        }
 
         // Set animation time
         let mut style = (*ctx.style()).clone();
@@ -131,7 +186,9 @@ impl Game {
         });
 
         // Show pause menu
-        if ctx.input(|input| input.key_pressed(Key::Escape)) {
+        if self.ui_state.rebinding.is_none()
+            && ctx.input(|input| action_pressed(input, Action::Pause))
+        {
             self.ui_state.paused = !self.ui_state.paused;
         }
 
@@ -150,17 +207,69 @@ impl Game {
             .show_animated(ctx, self.ui_state.paused, |ui| {
                 ui.spacing_mut().item_spacing.y = 10.0;
                 if ui
-                    .selectable_label(false, RichText::new("Resume").heading())
+                    .selectable_label(false, RichText::new(tr("Resume")).heading())
                     .clicked()
                 {
                     self.ui_state.paused = false;
                 }
                 if ui
-                    .selectable_label(false, RichText::new("Main Menu").heading())
+                    .selectable_label(false, RichText::new(tr("Main Menu")).heading())
                     .clicked()
                 {
                     res = Some(GameState::MainMenu);
                 }
+                if ui
+                    .selectable_label(false, RichText::new(tr("History")).heading())
+                    .clicked()
+                {
+                    self.ui_state.show_transcript = !self.ui_state.show_transcript;
+                }
+                if ui
+                    .selectable_label(false, RichText::new(tr("Controls")).heading())
+                    .clicked()
+                {
+                    self.ui_state.show_input_settings = !self.ui_state.show_input_settings;
+                }
+                if ui
+                    .selectable_label(false, RichText::new(tr("Save Replay")).heading())
+                    .clicked()
+                {
+                    self.ui_state.replay_message = Some(match self.world.replay().export() {
+                        Ok(path) => format!("{} {}", tr("Saved to"), path.display()),
+                        Err(e) => e.to_string(),
+                    });
+                }
+                if let Some(message) = &self.ui_state.replay_message {
+                    ui.small(message);
+                }
+            });
+
+        // Show input settings
+        Window::new(tr("Controls"))
+            .open(&mut self.ui_state.show_input_settings)
+            .show(ctx, |ui| {
+                input_settings_ui(
+                    ui,
+                    &mut self.ui_state.rebinding,
+                    &mut self.ui_state.input_message,
+                )
+            });
+        if !self.ui_state.show_input_settings {
+            self.ui_state.rebinding = None;
+        }
+
+        // Show conduit editor
+        self.conduit_editor_ui(ctx);
+
+        // Show dialog history
+        Window::new(tr("History"))
+            .open(&mut self.ui_state.show_transcript)
+            .default_size([400.0, 300.0])
+            .show(ctx, |ui| {
+                self.world
+                    .player
+                    .transcript
+                    .ui(ui, &mut self.ui_state.export_message)
             });
 
         // Set animation time
@@ -180,9 +289,17 @@ impl Game {
                 ..Default::default()
             })
             .show(ctx, |ui| {
+                if self.playback.is_some() {
+                    self.replay_ui(ui);
+                    return;
+                }
+                if self.world.rival.is_some() {
+                    self.duel_words_ui(ui);
+                    return;
+                }
                 ui.horizontal(|ui| {
                     self.words_ui(ui);
-                    self.controls_ui(ui);
+                    self.controls_ui(ui, PersonId::Player);
                     ui.with_layout(Layout::top_down(Align::Max), |ui| {
                         ui.with_layout(Layout::top_down(Align::Min), |ui| self.dialog_ui(ui))
                     });
@@ -200,13 +317,34 @@ impl Game {
                     .dialog
                     .as_ref()
                     .map_or(false, |dialog| dialog.speakers_ui(ui));
-                if !showed_speakers_ui {
-                    self.stack_ui(ui);
+                if showed_speakers_ui {
+                    return;
+                }
+                if self.world.rival.is_some() {
+                    ui.columns(2, |uis| {
+                        self.stack_ui(&mut uis[0], PersonId::Player);
+                        self.stack_ui(&mut uis[1], PersonId::Rival);
+                    });
+                } else {
+                    self.stack_ui(ui, PersonId::Player);
+                }
+                if let Some(error) = &self.ui_state.command_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
                 }
             });
 
         // Update world
         while self.ticker >= self.world.physics.dt() {
+            if let Some(playback) = &mut self.playback {
+                if playback.finished(&self.world) {
+                    self.ticker = 0.0;
+                    break;
+                }
+                // Commands that succeeded when they were recorded can still fail if
+                // the replay was edited or the simulation has changed since
+                let failures = playback.apply(&mut self.world);
+                playback.failures.extend(failures);
+            }
             self.world.update();
             self.ticker -= self.world.physics.dt();
         }
@@ -216,30 +354,18 @@ impl Game {
     fn top_ui(&mut self, ui: &mut Ui) {
         puffin::profile_function!();
         ui.horizontal(|ui| {
-            // Mana bar
-            ui.scope(|ui| {
-                let reserved = self.world.player.person.reserved_mana();
-                let capped = self.world.person(PersonId::Player).max_mana - reserved;
-                let color = Rgba::from_rgb(0.1, 0.1, 0.9).into();
-                ui.visuals_mut().selection.bg_fill = color;
-                let id = ui.make_persistent_id("mana bar");
-                let length_mul = ui
-                    .ctx()
-                    .animate_bool(id, self.world.player.progression.mana_bar);
-                if length_mul > 0.0 {
-                    ProgressBar::new(1.0)
-                        .text(format!("{capped:.0}"))
-                        .desired_width(capped * 10.0 * length_mul)
-                        .ui(ui);
-                    if reserved > 0.0 {
-                        ui.visuals_mut().selection.bg_fill = Rgba::from_rgb(0.2, 0.2, 0.9).into();
-                        ProgressBar::new(1.0)
-                            .text(format!("{reserved:.0}"))
-                            .desired_width(reserved * 10.0 * length_mul)
-                            .ui(ui);
-                    }
+            // Mana bars
+            if self.world.rival.is_some() {
+                for (person_id, name) in [
+                    (PersonId::Player, tr("Player 1")),
+                    (PersonId::Rival, tr("Player 2")),
+                ] {
+                    ui.label(name);
+                    self.mana_bar_ui(ui, person_id, true);
                 }
-            });
+            } else {
+                self.mana_bar_ui(ui, PersonId::Player, self.world.player.progression.mana_bar);
+            }
             // Fps
             let now = Instant::now();
             let dt = (now - self.last_time).as_secs_f32();
@@ -250,6 +376,32 @@ impl Game {
             ui.small(format!("{} fps", (1.0 / dt).round()));
         });
     }
+    fn mana_bar_ui(&self, ui: &mut Ui, person_id: PersonId, shown: bool) {
+        let Some(person) = self.world.try_person(person_id) else {
+            return;
+        };
+        ui.scope(|ui| {
+            let reserved = person.reserved_mana();
+            let capped = person.max_mana - reserved;
+            let color = Rgba::from_rgb(0.1, 0.1, 0.9).into();
+            ui.visuals_mut().selection.bg_fill = color;
+            let id = ui.make_persistent_id(("mana bar", person_id));
+            let length_mul = ui.ctx().animate_bool(id, shown);
+            if length_mul > 0.0 {
+                ProgressBar::new(1.0)
+                    .text(format!("{capped:.0}"))
+                    .desired_width(capped * 10.0 * length_mul)
+                    .ui(ui);
+                if reserved > 0.0 {
+                    ui.visuals_mut().selection.bg_fill = Rgba::from_rgb(0.2, 0.2, 0.9).into();
+                    ProgressBar::new(1.0)
+                        .text(format!("{reserved:.0}"))
+                        .desired_width(reserved * 10.0 * length_mul)
+                        .ui(ui);
+                }
+            }
+        });
+    }
     fn fields_ui(&mut self, ui: &mut Ui) {
         puffin::profile_function!();
         // Draw the fields themselves
@@ -258,6 +410,7 @@ impl Game {
         let mut drag_released = None;
         let mut hovered = Vec::new();
         let mut double_clicked = Vec::new();
+        let move_button = action_pointer_button(Action::MoveField).unwrap_or(PointerButton::Middle);
         // Input fields
         for kind in all::<InputFieldKind>() {
             let known = self.world.player.progression.known_fields.contains(&kind);
@@ -276,9 +429,9 @@ impl Game {
                 );
                 ui.allocate_ui_at_rect(plot_rect, |ui| {
                     let plot_resp = self.plot_io_field(ui, size, alpha, kind);
-                    if plot_resp.response.double_clicked_by(PointerButton::Middle) {
+                    if plot_resp.response.double_clicked_by(move_button) {
                         double_clicked.push(kind);
-                    } else if plot_resp.response.dragged_by(PointerButton::Middle) {
+                    } else if plot_resp.response.dragged_by(move_button) {
                         dragged.push((kind, plot_resp.response.drag_delta()));
                     } else if plot_resp.response.drag_released() {
                         drag_released = Some(kind);
@@ -306,7 +459,7 @@ impl Game {
                     ui.allocate_ui_at_rect(plot_rect, |ui| {
                         ui.horizontal_wrapped(|ui| {
                             let plot_resp = self.plot_io_field(ui, size, 1.0, kind);
-                            let player_person = &mut self.world.player.person;
+                            let player_person = &self.world.player.person;
                             let words = player_person.active_spells.spell_words(output_kind);
                             let mut to_dispel = None;
                             for (i, words) in words.enumerate() {
@@ -315,11 +468,11 @@ impl Game {
                                 }
                             }
                             if let Some(i) = to_dispel {
-                                player_person.active_spells.remove(output_kind, i);
+                                self.command(PersonId::Player, Command::Dispel(output_kind, i));
                             }
-                            if plot_resp.response.double_clicked_by(PointerButton::Middle) {
+                            if plot_resp.response.double_clicked_by(move_button) {
                                 double_clicked.push(kind);
-                            } else if plot_resp.response.dragged_by(PointerButton::Middle) {
+                            } else if plot_resp.response.dragged_by(move_button) {
                                 dragged.push((kind, plot_resp.response.drag_delta()));
                             } else if plot_resp.response.drag_released() {
                                 drag_released = Some(kind);
@@ -379,7 +532,7 @@ impl Game {
         const MARGIN: f32 = 4.0;
         ui.vertical(|ui| {
             let (dispel_height, dispelled) = if can_dispel {
-                let resp = ui.button("Dispel");
+                let resp = ui.button(tr("Dispel"));
                 (resp.rect.height(), resp.clicked())
             } else {
                 (0.0, false)
@@ -418,267 +571,344 @@ impl Game {
         })
         .inner
     }
-    fn stack_ui(&mut self, ui: &mut Ui) {
+    pub fn stack_ui(&mut self, ui: &mut Ui, person_id: PersonId) {
         puffin::profile_function!();
-        ScrollArea::horizontal().show(ui, |ui| {
-            ui.horizontal(|ui| {
-                ui.allocate_exact_size(vec2(0.0, SMALL_PLOT_SIZE), Sense::hover());
-                for item in self.world.player.person.stack.iter().collect_vec() {
-                    let plot_resp = self.plot_stack_field(ui, SMALL_PLOT_SIZE, 1.0, &item.field);
-                    Self::handle_plot_response_impl(
-                        ui,
-                        &mut self.ui_state,
-                        &mut self.world.controls,
-                        plot_resp,
-                    );
-                    Self::spell_words_ui(ui, &item.words, SMALL_PLOT_SIZE, false);
-                }
-                let stack = &self.world.player.person.stack;
-                if self.ui_state.last_stack_len != stack.len() {
-                    ui.scroll_to_cursor(None);
-                    self.ui_state.last_stack_len = stack.len();
-                }
+        let Some(person) = self.world.try_person(person_id) else {
+            return;
+        };
+        let items = person.stack.iter().cloned().collect_vec();
+        ScrollArea::horizontal()
+            .id_source(("stack", person_id))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.allocate_exact_size(vec2(0.0, SMALL_PLOT_SIZE), Sense::hover());
+                    for item in items {
+                        let plot_resp =
+                            self.plot_stack_field(ui, SMALL_PLOT_SIZE, 1.0, &item.field);
+                        let controls = (self.world.try_person_mut(person_id))
+                            .map(|person| &mut person.controls);
+                        Self::handle_plot_response_impl(
+                            ui,
+                            &mut self.ui_state,
+                            controls.filter(|_| self.playback.is_none()),
+                            plot_resp,
+                        );
+                        Self::spell_words_ui(ui, &item.words, SMALL_PLOT_SIZE, false);
+                    }
+                    let stack_len =
+                        (self.world.try_person(person_id)).map_or(0, |person| person.stack.len());
+                    let last_stack_len = self.ui_state.last_stack_len.entry(person_id).or_default();
+                    if *last_stack_len != stack_len {
+                        ui.scroll_to_cursor(None);
+                        *last_stack_len = stack_len;
+                    }
+                });
             });
-        });
     }
     fn words_ui(&mut self, ui: &mut Ui) {
         puffin::profile_function!();
         ui.horizontal_top(|ui| {
-            self.words_grid(ui);
+            ui.vertical(|ui| {
+                self.words_grid(ui, PersonId::Player);
+                self.typing_ui(ui);
+            });
             self.conduit_ui(ui);
         });
     }
-    fn conduit_ui(&mut self, ui: &mut Ui) {
-        if !self.world.player.progression.conduit {
-            return;
+    /// Check whether a person knows a word
+    ///
+    /// Only the player has to learn words.
+    pub fn knows_word(&self, person_id: PersonId, word: Word) -> bool {
+        person_id != PersonId::Player || self.world.player.progression.known_words.contains(&word)
+    }
+    /// Check whether a person can say a word right now
+    pub fn can_say(&self, person_id: PersonId, word: Word) -> Result<(), String> {
+        if !self.ui_state.dialog_allows_casting() {
+            return Err(tr("Casting is not allowed now"));
         }
-        Grid::new("conduits").show(ui, |ui| {
-            for stone in &mut self.world.player.person.rack.conduits {
-                let mut stack = self.world.player.person.stack.clone();
-                let button = Button::new(stone.format(16));
-                let mut res = Ok(());
-                for word in &stone.words {
-                    res = stack.say(PersonId::Player, *word, None);
-                    if res.is_err() {
-                        break;
-                    }
-                }
-                let on_hover = |ui: &mut Ui| {
-                    ui.label(stone.format(usize::MAX));
-                };
-                if res.is_ok() {
-                    if button.ui(ui).on_hover_ui(on_hover).clicked() {
-                        self.world.player.person.stack = stack;
-                    }
-                } else {
-                    ui.add_enabled(false, button).on_disabled_hover_ui(on_hover);
-                }
-                let can_add = !self.world.player.person.stack.is_empty();
-                if ui.add_enabled(can_add, Button::new("+")).clicked() {
-                    stone.etch(self.world.player.person.stack.words());
-                    self.world.player.person.stack.clear();
-                }
-                ui.end_row();
+        if !self.knows_word(person_id, word) {
+            return Err(format!("{}: {word}", tr("Unknown word")));
+        }
+        let Some(person) = self.world.try_person(person_id) else {
+            return Err(EidosError::UnknownPerson(person_id).to_string());
+        };
+        person
+            .stack
+            .validate_function_use(word.function())
+            .map_err(|e| e.to_string())?;
+        if person.capped_mana() < word.cost() {
+            return Err(tr("Not enough mana"));
+        }
+        Ok(())
+    }
+    /// Have a person say a word, casting any spell it completes
+    pub fn say(&mut self, person_id: PersonId, word: Word) -> Result<(), EidosError> {
+        if let (PersonId::Player, Function::ReadField(kind)) = (person_id, word.function()) {
+            if self.world.player.progression.known_fields.insert(kind) {
+                // Reveal the relevant field if this is the first time its word is said
+                self.ui_state.fields_display.insert(
+                    kind.into(),
+                    self.ui_state.default_field_display(kind.into()),
+                );
+                return Ok(());
             }
-        });
+        }
+        self.world.command(person_id, Command::Say(word))
     }
-    fn words_grid(&mut self, ui: &mut Ui) {
-        Grid::new("words").min_col_width(10.0).show(ui, |ui| {
-            // Words
-            let dialog_allows_casting = self
-                .ui_state
-                .dialog
-                .as_ref()
-                .map_or(true, |dialog| dialog.allows_casting());
-            let available_mana = self.world.player.person.capped_mana();
-            // Rows
-            for (i, row) in WORD_GRID.iter().enumerate() {
-                // Words in the row
-                for word in row {
-                    let player_person = &self.world.player.person;
-                    let f = word.function();
-                    let known = self.world.player.progression.known_words.contains(word);
-                    let enabled = dialog_allows_casting
-                        && known
-                        && player_person.stack.validate_function_use(f).is_ok()
-                        && available_mana >= word.cost();
-                    ui.scope(|ui| {
-                        let hilight = matches!(f, Function::WriteField(_));
-                        if enabled {
-                            ui.visuals_mut().override_text_color =
-                                word.text_color().map(Into::into);
-                        }
-                        let button =
-                            FadeButton::new(word, known, word.to_string()).hilight(hilight);
-                        if ui.add_enabled(enabled, button).clicked() {
-                            let player_person = &mut self.world.player.person;
-                            let mut say = || {
-                                player_person
-                                    .stack
-                                    .say(
-                                        PersonId::Player,
-                                        *word,
-                                        Some(&mut player_person.active_spells),
-                                    )
-                                    .err()
-                            };
-                            let _err = if let Function::ReadField(kind) = f {
-                                if self.world.player.progression.known_fields.insert(kind) {
-                                    // Reveal the relevant field if this is the first time its word is said
-                                    self.ui_state.fields_display.insert(
-                                        kind.into(),
-                                        self.ui_state.default_field_display(kind.into()),
-                                    );
-                                    None
-                                } else {
-                                    say()
-                                }
-                            } else {
-                                say()
-                            };
-                        }
-                    });
-                }
-                if i == 0 {
-                    // Free
-                    let show_free = self.world.player.progression.free;
-                    let id = ui.make_persistent_id("free");
-                    let visibility = ui.ctx().animate_bool(id, show_free);
-                    if show_free {
-                        apply_color_fading(ui.visuals_mut(), visibility);
-                        if ui.button("Free").clicked() {
-                            self.world.player.person.stack.clear();
+    /// Give a command from the UI, showing its error if it fails
+    pub fn command(&mut self, person_id: PersonId, command: Command) {
+        self.ui_state.command_error = self
+            .world
+            .command(person_id, command)
+            .err()
+            .map(|e| e.to_string());
+    }
+    pub fn words_grid(&mut self, ui: &mut Ui, person_id: PersonId) {
+        Grid::new(("words", person_id))
+            .min_col_width(10.0)
+            .show(ui, |ui| {
+                // Rows
+                for (i, row) in WORD_GRID.iter().enumerate() {
+                    // Words in the row
+                    for word in row {
+                        let f = word.function();
+                        let known = self.knows_word(person_id, *word);
+                        let enabled = self.can_say(person_id, *word).is_ok();
+                        ui.scope(|ui| {
+                            let hilight = matches!(f, Function::WriteField(_));
+                            if enabled {
+                                ui.visuals_mut().override_text_color =
+                                    word.text_color().map(Into::into);
+                            }
+                            let button =
+                                FadeButton::new(word, known, word.to_string()).hilight(hilight);
+                            if ui.add_enabled(enabled, button).clicked() {
+                                let res = self.say(person_id, *word);
+                                self.ui_state.command_error = res.err().map(|e| e.to_string());
+                            }
+                        });
+                    }
+                    if i == 0 {
+                        // Free
+                        let show_free =
+                            person_id != PersonId::Player || self.world.player.progression.free;
+                        let id = ui.make_persistent_id(("free", person_id));
+                        let visibility = ui.ctx().animate_bool(id, show_free);
+                        if show_free {
+                            apply_color_fading(ui.visuals_mut(), visibility);
+                            if ui.button(tr("Free")).clicked() {
+                                self.command(person_id, Command::Free);
+                            }
+                        } else {
+                            ui.label("");
                         }
-                    } else {
-                        ui.label("");
                     }
+                    if i == 1 {
+                        self.history_ui(ui, person_id);
+                    }
+                    ui.end_row();
                 }
-                ui.end_row();
+            });
+    }
+    /// Show undo and redo buttons and handle their keybindings
+    fn history_ui(&mut self, ui: &mut Ui, person_id: PersonId) {
+        let allowed = self.ui_state.dialog_allows_casting();
+        let Some(person) = self.world.try_person(person_id) else {
+            return;
+        };
+        let history = &person.history;
+        let (can_undo, can_redo) = (allowed && history.can_undo(), allowed && history.can_redo());
+        let (undo_key, redo_key) =
+            if person_id == PersonId::Player && ui.memory(|mem| mem.focus().is_none()) {
+                ui.input(|input| {
+                    (
+                        action_pressed(input, Action::Undo),
+                        action_pressed(input, Action::Redo),
+                    )
+                })
+            } else {
+                (false, false)
+            };
+        ui.horizontal(|ui| {
+            let undo_button = ui
+                .add_enabled(can_undo, Button::new("⟲").small())
+                .on_hover_text(tr("Undo"));
+            if can_undo && (undo_button.clicked() || undo_key) {
+                self.command(person_id, Command::Undo);
+            }
+            let redo_button = ui
+                .add_enabled(can_redo, Button::new("⟳").small())
+                .on_hover_text(tr("Redo"));
+            if can_redo && (redo_button.clicked() || redo_key) {
+                self.command(person_id, Command::Redo);
             }
         });
     }
-    fn controls_ui(&mut self, ui: &mut Ui) {
+    pub fn controls_ui(&mut self, ui: &mut Ui, person_id: PersonId) {
         // Controls
-        let player_person = &mut self.world.player.person;
-        let stack_controls = player_person
-            .stack
-            .iter()
-            .flat_map(|item| item.field.controls());
-        let scalar_output_controls = player_person
-            .active_spells
-            .scalars
-            .values()
-            .flatten()
-            .flat_map(|spell| spell.field.controls());
-        let vector_output_controls = player_person
-            .active_spells
-            .vectors
-            .values()
-            .flatten()
-            .flat_map(|spell| spell.field.controls());
-        let used_controls: BTreeSet<ControlKind> = stack_controls
-            .chain(scalar_output_controls)
-            .chain(vector_output_controls)
-            .collect();
+        let Some(person) = self.world.try_person_mut(person_id) else {
+            return;
+        };
+        let used_controls = person.used_controls();
+        let controls = &mut person.controls;
+        controls.retain(&used_controls);
+        let something_focused = ui.memory(|mem| mem.focus().is_some());
         // Vertical slider
         if used_controls.contains(&ControlKind::YSlider) {
-            let value = self.world.controls.y_slider.get_or_insert(0.0);
-            if ui.memory(|mem| mem.focus().is_none()) {
-                if let Some(i) = [
-                    Key::Num0,
-                    Key::Num1,
-                    Key::Num2,
-                    Key::Num3,
-                    Key::Num4,
-                    Key::Num5,
-                    Key::Num6,
-                    Key::Num7,
-                    Key::Num8,
-                    Key::Num9,
-                ]
-                .into_iter()
-                .position(|key| ui.input(|input| input.key_pressed(key)))
-                {
-                    *value = i as f32 / 9.0;
+            let value = controls.scalar_mut(ControlKind::YSlider);
+            if !something_focused {
+                let mut pressed = all::<Action>()
+                    .filter(|action| ui.input(|input| action_pressed(input, *action)));
+                if person_id == PersonId::Player {
+                    if let Some(v) = pressed.find_map(Action::y_slider_value) {
+                        *value = v;
+                    }
+                } else if person_id == PersonId::Rival {
+                    let step: f32 = pressed.filter_map(Action::rival_y_slider_step).sum();
+                    *value = (*value + step / 9.0).clamp(0.0, 1.0);
                 }
             }
+            if let Some(speed) = Action::axis_for_control(person_id, ControlKind::YSlider)
+                .and_then(action_axis)
+                .filter(|_| !something_focused)
+            {
+                let dt = ui.input(|input| input.stable_dt);
+                *value = (*value + speed * dt / Y_SLIDER_AXIS_TIME).clamp(0.0, 1.0);
+            }
             Slider::new(value, 0.0..=1.0)
                 .vertical()
                 .fixed_decimals(1)
                 .show_value(false)
                 .ui(ui);
-        } else {
-            self.world.controls.y_slider = None;
         }
         ui.vertical(|ui| {
-            let something_focused = ui.memory(|mem| mem.focus().is_some());
-            // Horizontal slider
-            if used_controls.contains(&ControlKind::XSlider) {
-                let value = self.world.controls.x_slider.get_or_insert(0.0);
-                ui.input(|input| {
-                    if input.key_down(Key::D) || input.key_down(Key::A) {
-                        if !something_focused {
-                            *value = input.key_down(Key::D) as u8 as f32
-                                - input.key_down(Key::A) as u8 as f32;
+            for kind in used_controls {
+                let word = all::<Word>()
+                    .find(|word| word.function() == Function::Control(kind))
+                    .map(|word| word.to_string())
+                    .unwrap_or_default();
+                match kind {
+                    ControlKind::YSlider => {}
+                    // Horizontal slider
+                    ControlKind::XSlider => {
+                        let value = controls.scalar_mut(kind);
+                        let left_action = Action::x_slider_left(person_id);
+                        let right_action = Action::for_control(person_id, kind).unwrap();
+                        ui.input(|input| {
+                            let left = action_down(input, left_action);
+                            let right = action_down(input, right_action);
+                            if left || right {
+                                if !something_focused {
+                                    *value = right as u8 as f32 - left as u8 as f32;
+                                }
+                            } else if action_released(input, left_action)
+                                || action_released(input, right_action)
+                            {
+                                *value = 0.0;
+                            }
+                        });
+                        // Gamepad axis
+                        let axis_action = Action::axis_for_control(person_id, kind).unwrap();
+                        if let Some(axis) = action_axis(axis_action).filter(|_| !something_focused)
+                        {
+                            *value = axis;
+                            self.ui_state.axis_held.insert(person_id);
+                        } else if self.ui_state.axis_held.remove(&person_id) {
+                            *value = 0.0;
                         }
-                    } else if input.key_released(Key::D) || input.key_released(Key::A) {
-                        *value = 0.0;
+                        Slider::new(value, -1.0..=1.0)
+                            .fixed_decimals(1)
+                            .show_value(false)
+                            .ui(ui);
                     }
-                });
-                Slider::new(value, -1.0..=1.0)
-                    .fixed_decimals(1)
-                    .show_value(false)
-                    .ui(ui);
-            } else {
-                self.world.controls.x_slider = None;
-            }
-            // Activators
-            for (word, kind, value, require_shift) in [
-                (
-                    Word::Ve,
-                    ControlKind::Activation1,
-                    &mut self.world.controls.activation1,
-                    false,
-                ),
-                (
-                    Word::Vi,
-                    ControlKind::Activation2,
-                    &mut self.world.controls.activation2,
-                    true,
-                ),
-            ] {
-                if used_controls.contains(&kind) {
-                    ui.toggle_value(value, word.to_string());
-                    ui.input(|input| {
-                        if input.key_down(Key::Space) && require_shift != input.modifiers.shift {
-                            if !something_focused {
-                                *value = true;
+                    // Activators
+                    ControlKind::Activation1
+                    | ControlKind::Activation2
+                    | ControlKind::Activation3 => {
+                        let action = Action::for_control(person_id, kind).unwrap();
+                        let mut active = controls.get(kind) > 0.0;
+                        ui.toggle_value(&mut active, word);
+                        ui.input(|input| {
+                            if action_down(input, action) {
+                                if !something_focused {
+                                    active = true;
+                                }
+                            } else if action_released(input, action) {
+                                active = false;
                             }
-                        } else if input.key_released(Key::Space) {
-                            *value = false;
-                        }
-                    });
-                } else {
-                    *value = false;
+                        });
+                        controls.set(kind, active as u8 as f32);
+                    }
+                    // Charge
+                    ControlKind::Charge => {
+                        let value = controls.scalar_mut(kind);
+                        let action = Action::for_control(person_id, kind).unwrap();
+                        ui.input(|input| {
+                            if action_down(input, action) && !something_focused {
+                                *value = (*value + input.stable_dt / CHARGE_TIME).min(1.0);
+                            } else {
+                                *value = 0.0;
+                            }
+                        });
+                        ProgressBar::new(*value)
+                            .desired_width(100.0)
+                            .text(word)
+                            .ui(ui);
+                    }
+                    // Scroll
+                    ControlKind::Scroll => {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{word} {:.1}", controls.get(kind)));
+                            if ui.small_button("⟲").clicked() {
+                                controls.set(kind, 0.0);
+                            }
+                        });
+                    }
+                    // Pointer
+                    ControlKind::Pointer => {
+                        let pointer = controls.get_vector(kind);
+                        ui.label(format!("{word} ({:.1}, {:.1})", pointer.x, pointer.y));
+                    }
                 }
             }
         });
     }
     fn handle_plot_response(&mut self, ui: &Ui, plot_resp: PlotResponse) {
-        Self::handle_plot_response_impl(ui, &mut self.ui_state, &mut self.world.controls, plot_resp)
+        let controls = &mut self.world.player.person.controls;
+        Self::handle_plot_response_impl(
+            ui,
+            &mut self.ui_state,
+            self.playback.is_none().then_some(controls),
+            plot_resp,
+        )
     }
     fn handle_plot_response_impl(
         ui: &Ui,
         ui_state: &mut UiState,
-        controls: &mut Controls,
+        controls: Option<&mut Controls>,
         plot_resp: PlotResponse,
     ) {
         if ui_state.next_player_target.is_none() {
             ui_state.next_player_target = plot_resp.hovered_pos;
         }
+        // Controls are not set by the mouse while watching a replay
+        let Some(controls) = controls else {
+            return;
+        };
         if plot_resp.response.hovered() {
-            controls.activation1 = ui.input(|input| input.pointer.primary_down());
-            controls.activation2 = ui.input(|input| input.pointer.secondary_down());
+            let (primary, secondary, scroll) = ui.input(|input| {
+                (
+                    input.pointer.primary_down(),
+                    input.pointer.secondary_down(),
+                    input.scroll_delta.y,
+                )
+            });
+            controls.set(ControlKind::Activation1, primary as u8 as f32);
+            controls.set(ControlKind::Activation2, secondary as u8 as f32);
+            *controls.scalar_mut(ControlKind::Scroll) += scroll / SCROLL_STEP;
+            if let Some(pos) = plot_resp.hovered_pos {
+                controls.set_vector(ControlKind::Pointer, pos.to_vec2());
+            }
         }
     }
     fn init_plot(&self, size: f32, global_alpha: f32) -> FieldPlot {
@@ -782,12 +1012,21 @@ impl FieldPlottable for ScalarFieldKind {
             ScalarFieldKind::Input(ScalarInputFieldKind::Light) => 5.0,
             ScalarFieldKind::Input(ScalarInputFieldKind::Disorder) => 2.0,
             ScalarFieldKind::Input(ScalarInputFieldKind::Memory) => 1.0,
+            ScalarFieldKind::Input(ScalarInputFieldKind::Impact) => 1.0,
             ScalarFieldKind::Input(ScalarInputFieldKind::Temperature)
             | ScalarFieldKind::Output(ScalarOutputFieldKind::Heat) => 20.0,
             ScalarFieldKind::Output(ScalarOutputFieldKind::Order) => 1.0,
             ScalarFieldKind::Output(ScalarOutputFieldKind::Anchor) => 1.0,
         }
     }
+    fn shows_order(&self) -> bool {
+        matches!(
+            self,
+            ScalarFieldKind::Input(ScalarInputFieldKind::Disorder)
+                | ScalarFieldKind::Output(ScalarOutputFieldKind::Order)
+                | ScalarFieldKind::Output(ScalarOutputFieldKind::Anchor)
+        )
+    }
     fn get_z(&self, world: &World, pos: Pos2) -> Self::Value {
         world.sample_scalar_field(*self, pos, true)
     }
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    field::*,
+    person::{ActiveSpell, ActiveSpells},
+    stack::Stack,
+};
+
+/// The number of stack changes that can be undone
+pub const MAX_HISTORY: usize = 100;
+
+/// The number of active spells of each kind
+pub type SpellCounts = HashMap<OutputFieldKind, usize>;
+
+/// A spell cast by a stack change
+#[derive(Clone)]
+pub enum CastSpell {
+    Scalar(ScalarOutputFieldKind, ActiveSpell<ScalarField>),
+    Vector(VectorOutputFieldKind, ActiveSpell<VectorField>),
+}
+
+#[derive(Clone)]
+struct HistoryEntry {
+    before: Stack,
+    after: Stack,
+    cast: Vec<CastSpell>,
+}
+
+/// A bounded history of a person's stack states
+#[derive(Default)]
+pub struct StackHistory {
+    undo: VecDeque<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+
+impl StackHistory {
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+    /// Record a change from one stack state to another that cast the given spells
+    pub fn record(&mut self, before: Stack, after: Stack, cast: Vec<CastSpell>) {
+        if cast.is_empty() && before.words().eq(after.words()) {
+            return;
+        }
+        self.redo.clear();
+        if self.undo.len() == MAX_HISTORY {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(HistoryEntry {
+            before,
+            after,
+            cast,
+        });
+    }
+    /// Revert the last change, removing any spells it cast
+    pub fn undo(&mut self, stack: &mut Stack, active_spells: &mut ActiveSpells) -> bool {
+        let Some(entry) = self.undo.pop_back() else {
+            return false;
+        };
+        *stack = entry.before.clone();
+        for spell in entry.cast.iter().rev() {
+            active_spells.remove_cast(spell);
+        }
+        self.redo.push(entry);
+        true
+    }
+    /// Reapply the last undone change, recasting any spells it cast
+    pub fn redo(&mut self, stack: &mut Stack, active_spells: &mut ActiveSpells) -> bool {
+        let Some(entry) = self.redo.pop() else {
+            return false;
+        };
+        *stack = entry.after.clone();
+        for spell in &entry.cast {
+            active_spells.add_cast(spell.clone());
+        }
+        self.undo.push_back(entry);
+        true
+    }
+}
+
+impl ActiveSpells {
+    /// The number of active spells of each kind
+    pub fn counts(&self) -> SpellCounts {
+        let scalars = self.scalars.iter().map(|(k, v)| ((*k).into(), v.len()));
+        let vectors = self.vectors.iter().map(|(k, v)| ((*k).into(), v.len()));
+        scalars.chain(vectors).collect()
+    }
+    /// Get the spells cast since the given counts were taken
+    pub fn cast_since(&self, counts: &SpellCounts) -> Vec<CastSpell> {
+        let prev = |kind: OutputFieldKind| counts.get(&kind).copied().unwrap_or(0);
+        let scalars = self.scalars.iter().flat_map(|(kind, spells)| {
+            spells
+                .iter()
+                .skip(prev((*kind).into()))
+                .map(|spell| CastSpell::Scalar(*kind, spell.clone()))
+        });
+        let vectors = self.vectors.iter().flat_map(|(kind, spells)| {
+            spells
+                .iter()
+                .skip(prev((*kind).into()))
+                .map(|spell| CastSpell::Vector(*kind, spell.clone()))
+        });
+        scalars.chain(vectors).collect()
+    }
+    fn add_cast(&mut self, spell: CastSpell) {
+        match spell {
+            CastSpell::Scalar(kind, spell) => self.scalars.entry(kind).or_default().push(spell),
+            CastSpell::Vector(kind, spell) => self.vectors.entry(kind).or_default().push(spell),
+        }
+    }
+    /// Remove the most recent spell with the same words as a cast one, if it is still active
+    fn remove_cast(&mut self, spell: &CastSpell) {
+        match spell {
+            CastSpell::Scalar(kind, spell) => {
+                if let Some(spells) = self.scalars.get_mut(kind) {
+                    if let Some(i) = spells.iter().rposition(|s| s.words == spell.words) {
+                        spells.remove(i);
+                    }
+                }
+            }
+            CastSpell::Vector(kind, spell) => {
+                if let Some(spells) = self.vectors.get_mut(kind) {
+                    if let Some(i) = spells.iter().rposition(|s| s.words == spell.words) {
+                        spells.remove(i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn stack_history_test() {
+    use crate::{
+        person::{Person, PersonId},
+        word::Word,
+    };
+    let mut person = Person::new(100.0);
+    let say = |person: &mut Person, word| {
+        person
+            .change_stack(|stack, active_spells| {
+                stack.say(PersonId::Player, word, Some(active_spells))
+            })
+            .unwrap()
+    };
+    say(&mut person, Word::Te);
+    say(&mut person, Word::Lo);
+    assert!(person.stack.is_empty());
+    assert_eq!(
+        person
+            .active_spells
+            .spell_words(ScalarOutputFieldKind::Heat.into())
+            .len(),
+        1
+    );
+    assert!(person.undo());
+    assert_eq!(person.stack.len(), 1);
+    assert_eq!(
+        person
+            .active_spells
+            .spell_words(ScalarOutputFieldKind::Heat.into())
+            .len(),
+        0
+    );
+    assert!(person.redo());
+    assert!(person.stack.is_empty());
+    assert_eq!(
+        person
+            .active_spells
+            .spell_words(ScalarOutputFieldKind::Heat.into())
+            .len(),
+        1
+    );
+    assert!(person.undo() && person.undo() && !person.undo());
+    say(&mut person, Word::Ti);
+    assert!(!person.history.can_redo());
+}
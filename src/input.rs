@@ -0,0 +1,625 @@
+#[cfg(feature = "gamepad")]
+use std::cell::RefCell;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+};
+
+use anyhow::bail;
+use derive_more::Display;
+use eframe::{egui::*, epaint::mutex::Mutex};
+use enum_iterator::{all, Sequence};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    field::ControlKind,
+    locale::tr,
+    person::PersonId,
+    utils::{fatal_error, resources_path},
+};
+
+/// Something the player can do with a key or mouse button
+#[derive(
+    Debug,
+    Display,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Sequence,
+    Serialize,
+    Deserialize,
+)]
+pub enum Action {
+    #[display(fmt = "X slider left")]
+    XSliderLeft,
+    #[display(fmt = "X slider right")]
+    XSliderRight,
+    /// A gamepad axis that sets the X slider
+    #[display(fmt = "X slider axis")]
+    XSliderAxis,
+    #[display(fmt = "Y slider 0")]
+    YSlider0,
+    #[display(fmt = "Y slider 1")]
+    YSlider1,
+    #[display(fmt = "Y slider 2")]
+    YSlider2,
+    #[display(fmt = "Y slider 3")]
+    YSlider3,
+    #[display(fmt = "Y slider 4")]
+    YSlider4,
+    #[display(fmt = "Y slider 5")]
+    YSlider5,
+    #[display(fmt = "Y slider 6")]
+    YSlider6,
+    #[display(fmt = "Y slider 7")]
+    YSlider7,
+    #[display(fmt = "Y slider 8")]
+    YSlider8,
+    #[display(fmt = "Y slider 9")]
+    YSlider9,
+    /// A gamepad axis that moves the Y slider
+    #[display(fmt = "Y slider axis")]
+    YSliderAxis,
+    #[display(fmt = "Activation 1")]
+    Activation1,
+    #[display(fmt = "Activation 2")]
+    Activation2,
+    #[display(fmt = "Activation 3")]
+    Activation3,
+    Charge,
+    Pause,
+    /// Drag or reset a field plot
+    #[display(fmt = "Move field")]
+    MoveField,
+    Undo,
+    Redo,
+    /// Focus the syllable typing field
+    #[display(fmt = "Type to cast")]
+    Type,
+    // The second person in a duel
+    #[display(fmt = "Rival X slider left")]
+    RivalXSliderLeft,
+    #[display(fmt = "Rival X slider right")]
+    RivalXSliderRight,
+    #[display(fmt = "Rival X slider axis")]
+    RivalXSliderAxis,
+    #[display(fmt = "Rival activation 1")]
+    RivalActivation1,
+    #[display(fmt = "Rival activation 2")]
+    RivalActivation2,
+    #[display(fmt = "Rival activation 3")]
+    RivalActivation3,
+    #[display(fmt = "Rival charge")]
+    RivalCharge,
+    #[display(fmt = "Rival Y slider up")]
+    RivalYSliderUp,
+    #[display(fmt = "Rival Y slider down")]
+    RivalYSliderDown,
+    #[display(fmt = "Rival Y slider axis")]
+    RivalYSliderAxis,
+    #[display(fmt = "Rival target left")]
+    RivalTargetLeft,
+    #[display(fmt = "Rival target right")]
+    RivalTargetRight,
+    #[display(fmt = "Rival target up")]
+    RivalTargetUp,
+    #[display(fmt = "Rival target down")]
+    RivalTargetDown,
+}
+
+impl Action {
+    /// The Y slider value this action sets, if any
+    pub fn y_slider_value(self) -> Option<f32> {
+        use Action::*;
+        [
+            YSlider0, YSlider1, YSlider2, YSlider3, YSlider4, YSlider5, YSlider6, YSlider7,
+            YSlider8, YSlider9,
+        ]
+        .into_iter()
+        .position(|action| action == self)
+        .map(|i| i as f32 / 9.0)
+    }
+    /// The number of tenths this action moves the rival's Y slider, if any
+    pub fn rival_y_slider_step(self) -> Option<f32> {
+        match self {
+            Action::RivalYSliderUp => Some(1.0),
+            Action::RivalYSliderDown => Some(-1.0),
+            _ => None,
+        }
+    }
+    /// The action that drives a person's control, if any
+    ///
+    /// The X slider is driven by two actions, so the one that moves it right is returned.
+    pub fn for_control(person_id: PersonId, kind: ControlKind) -> Option<Action> {
+        use Action::*;
+        Some(match (person_id, kind) {
+            (PersonId::Rival, ControlKind::XSlider) => RivalXSliderRight,
+            (PersonId::Rival, ControlKind::Activation1) => RivalActivation1,
+            (PersonId::Rival, ControlKind::Activation2) => RivalActivation2,
+            (PersonId::Rival, ControlKind::Activation3) => RivalActivation3,
+            (PersonId::Rival, ControlKind::Charge) => RivalCharge,
+            (PersonId::Rival, _) => return None,
+            (_, ControlKind::XSlider) => XSliderRight,
+            (_, ControlKind::Activation1) => Activation1,
+            (_, ControlKind::Activation2) => Activation2,
+            (_, ControlKind::Activation3) => Activation3,
+            (_, ControlKind::Charge) => Charge,
+            _ => return None,
+        })
+    }
+    /// The action that moves a person's X slider left
+    pub fn x_slider_left(person_id: PersonId) -> Action {
+        match person_id {
+            PersonId::Rival => Action::RivalXSliderLeft,
+            _ => Action::XSliderLeft,
+        }
+    }
+    /// The axis action that drives a person's slider, if any
+    pub fn axis_for_control(person_id: PersonId, kind: ControlKind) -> Option<Action> {
+        Some(match (person_id, kind) {
+            (PersonId::Rival, ControlKind::XSlider) => Action::RivalXSliderAxis,
+            (PersonId::Rival, ControlKind::YSlider) => Action::RivalYSliderAxis,
+            (PersonId::Rival, _) => return None,
+            (_, ControlKind::XSlider) => Action::XSliderAxis,
+            (_, ControlKind::YSlider) => Action::YSliderAxis,
+            _ => return None,
+        })
+    }
+    /// Whether this action can only be bound to mouse buttons
+    pub fn mouse_only(self) -> bool {
+        self == Action::MoveField
+    }
+    /// Whether this action can only be bound to gamepad axes
+    pub fn axis_only(self) -> bool {
+        matches!(
+            self,
+            Action::XSliderAxis
+                | Action::YSliderAxis
+                | Action::RivalXSliderAxis
+                | Action::RivalYSliderAxis
+        )
+    }
+}
+
+/// A key, mouse button, or gamepad axis, along with the modifiers that must be held
+///
+/// Modifiers that are not part of the binding may be held as well,
+/// unless another binding of the same input uses them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Binding {
+    pub input: BindingInput,
+    pub shift: bool,
+    pub command: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingInput {
+    Key(Key),
+    Mouse(PointerButton),
+    Axis(GamepadAxis),
+}
+
+/// An axis of a gamepad, read from whichever connected gamepad pushes it furthest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftZ,
+    RightZ,
+    DPadX,
+    DPadY,
+}
+
+#[cfg(feature = "gamepad")]
+impl From<GamepadAxis> for gilrs::Axis {
+    fn from(axis: GamepadAxis) -> Self {
+        match axis {
+            GamepadAxis::LeftStickX => gilrs::Axis::LeftStickX,
+            GamepadAxis::LeftStickY => gilrs::Axis::LeftStickY,
+            GamepadAxis::RightStickX => gilrs::Axis::RightStickX,
+            GamepadAxis::RightStickY => gilrs::Axis::RightStickY,
+            GamepadAxis::LeftZ => gilrs::Axis::LeftZ,
+            GamepadAxis::RightZ => gilrs::Axis::RightZ,
+            GamepadAxis::DPadX => gilrs::Axis::DPadX,
+            GamepadAxis::DPadY => gilrs::Axis::DPadY,
+        }
+    }
+}
+
+/// Axis values closer to zero than this are ignored
+const AXIS_DEADZONE: f32 = 0.15;
+/// How far an axis must be pushed to count as held
+const AXIS_THRESHOLD: f32 = 0.5;
+
+#[cfg(feature = "gamepad")]
+thread_local! {
+    static GILRS: RefCell<Option<gilrs::Gilrs>> = RefCell::new(gilrs::Gilrs::new().ok());
+}
+
+/// Read the events of connected gamepads so that their axes are up to date
+///
+/// This should be called once per frame.
+pub fn poll_gamepads() {
+    #[cfg(feature = "gamepad")]
+    GILRS.with(|gilrs| {
+        if let Some(gilrs) = &mut *gilrs.borrow_mut() {
+            while gilrs.next_event().is_some() {}
+        }
+    });
+}
+
+/// The value of a gamepad axis in `-1.0..=1.0`
+///
+/// This is always zero without the `gamepad` feature.
+pub fn axis_value(axis: GamepadAxis) -> f32 {
+    #[cfg(feature = "gamepad")]
+    {
+        GILRS.with(|gilrs| {
+            let gilrs = gilrs.borrow();
+            let Some(gilrs) = &*gilrs else {
+                return 0.0;
+            };
+            gilrs
+                .gamepads()
+                .map(|(_, gamepad)| gamepad.value(axis.into()))
+                .fold(0.0, |a: f32, b| if b.abs() > a.abs() { b } else { a })
+        })
+    }
+    #[cfg(not(feature = "gamepad"))]
+    {
+        let _ = axis;
+        0.0
+    }
+}
+
+/// The first axis pushed past the threshold
+fn moved_axis() -> Option<GamepadAxis> {
+    all::<GamepadAxis>().find(|axis| axis_value(*axis).abs() > AXIS_THRESHOLD)
+}
+
+/// All the keys that can be bound
+const KEYS: &[Key] = {
+    use Key::*;
+    &[
+        ArrowDown, ArrowLeft, ArrowRight, ArrowUp, Escape, Tab, Backspace, Enter, Space, Insert,
+        Delete, Home, End, PageUp, PageDown, Minus, PlusEquals, Num0, Num1, Num2, Num3, Num4, Num5,
+        Num6, Num7, Num8, Num9, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V,
+        W, X, Y, Z, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15, F16, F17,
+        F18, F19, F20,
+    ]
+};
+
+const MOUSE_BUTTONS: &[(PointerButton, &str)] = &[
+    (PointerButton::Primary, "MousePrimary"),
+    (PointerButton::Secondary, "MouseSecondary"),
+    (PointerButton::Middle, "MouseMiddle"),
+    (PointerButton::Extra1, "MouseExtra1"),
+    (PointerButton::Extra2, "MouseExtra2"),
+];
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.command {
+            write!(f, "Ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        match self.input {
+            BindingInput::Key(key) => write!(f, "{key:?}"),
+            BindingInput::Mouse(button) => {
+                let name = MOUSE_BUTTONS
+                    .iter()
+                    .find(|(b, _)| *b == button)
+                    .map_or("Mouse", |(_, name)| name);
+                write!(f, "{name}")
+            }
+            BindingInput::Axis(axis) => write!(f, "{axis:?}"),
+        }
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let Some(name) = parts.pop() else {
+            bail!("Empty binding");
+        };
+        let mut binding = Binding {
+            input: if let Some(key) = KEYS.iter().find(|key| format!("{key:?}") == name) {
+                BindingInput::Key(*key)
+            } else if let Some((button, _)) = MOUSE_BUTTONS.iter().find(|(_, n)| *n == name) {
+                BindingInput::Mouse(*button)
+            } else if let Some(axis) = all::<GamepadAxis>().find(|axis| format!("{axis:?}") == name)
+            {
+                BindingInput::Axis(axis)
+            } else {
+                bail!("Unknown key or button {name:?} in binding {s:?}");
+            },
+            shift: false,
+            command: false,
+        };
+        for modifier in parts {
+            match modifier {
+                "Shift" => binding.shift = true,
+                "Ctrl" | "Cmd" => binding.command = true,
+                _ => bail!("Unknown modifier {modifier:?} in binding {s:?}"),
+            }
+        }
+        if matches!(binding.input, BindingInput::Axis(_)) && (binding.shift || binding.command) {
+            bail!("Gamepad axes cannot have modifiers in binding {s:?}");
+        }
+        Ok(binding)
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl Binding {
+    fn modifier_count(&self) -> usize {
+        self.shift as usize + self.command as usize
+    }
+    fn modifiers_held(&self, modifiers: Modifiers) -> bool {
+        (!self.shift || modifiers.shift) && (!self.command || modifiers.command)
+    }
+    /// Held down, with a gamepad axis counting when pushed in the positive direction
+    pub fn down(&self, input: &InputState) -> bool {
+        self.modifiers_held(input.modifiers)
+            && match self.input {
+                BindingInput::Key(key) => input.key_down(key),
+                BindingInput::Mouse(button) => input.pointer.button_down(button),
+                BindingInput::Axis(axis) => axis_value(axis) > AXIS_THRESHOLD,
+            }
+    }
+    /// Pressed this frame, which gamepad axes never are
+    pub fn pressed(&self, input: &InputState) -> bool {
+        self.modifiers_held(input.modifiers)
+            && match self.input {
+                BindingInput::Key(key) => input.key_pressed(key),
+                BindingInput::Mouse(button) => input.pointer.button_pressed(button),
+                BindingInput::Axis(_) => false,
+            }
+    }
+    /// Released regardless of modifiers, which gamepad axes never are
+    pub fn released(&self, input: &InputState) -> bool {
+        match self.input {
+            BindingInput::Key(key) => input.key_released(key),
+            BindingInput::Mouse(button) => input.pointer.button_released(button),
+            BindingInput::Axis(_) => false,
+        }
+    }
+    /// Whether another binding of the same input uses more of the held modifiers
+    ///
+    /// This keeps `Space` from triggering along with `Shift+Space`.
+    fn shadowed(&self, map: &InputMap, modifiers: Modifiers) -> bool {
+        map.values().flatten().any(|other| {
+            other.input == self.input
+                && other.modifier_count() > self.modifier_count()
+                && other.modifiers_held(modifiers)
+        })
+    }
+}
+
+/// The bindings for each action, loaded from `input.yaml`
+pub type InputMap = HashMap<Action, Vec<Binding>>;
+
+pub static INPUT_MAP: Lazy<Mutex<InputMap>> = Lazy::new(|| {
+    let yaml = fs::read_to_string(resources_path().join("input.yaml"));
+    let yaml = yaml
+        .as_deref()
+        .unwrap_or(include_str!("../resources/input.yaml"));
+    let map = serde_yaml::from_str(yaml)
+        .unwrap_or_else(|e| fatal_error(format!("Unable to read input file: {e}")));
+    Mutex::new(map)
+});
+
+/// Write the input map back to `input.yaml`
+pub fn save_input_map() -> anyhow::Result<()> {
+    let map = INPUT_MAP.lock();
+    let sorted: BTreeMap<_, _> = map.iter().collect();
+    fs::write(
+        resources_path().join("input.yaml"),
+        serde_yaml::to_string(&sorted)?,
+    )?;
+    Ok(())
+}
+
+fn bindings(action: Action) -> Vec<Binding> {
+    INPUT_MAP.lock().get(&action).cloned().unwrap_or_default()
+}
+
+/// Check if any of an action's bindings satisfy a predicate and are not shadowed
+fn action_matches(
+    input: &InputState,
+    action: Action,
+    f: impl Fn(&Binding, &InputState) -> bool,
+) -> bool {
+    let map = INPUT_MAP.lock();
+    map.get(&action)
+        .into_iter()
+        .flatten()
+        .any(|binding| f(binding, input) && !binding.shadowed(&map, input.modifiers))
+}
+
+/// Check if any of an action's bindings are held
+pub fn action_down(input: &InputState, action: Action) -> bool {
+    action_matches(input, action, Binding::down)
+}
+
+/// Check if any of an action's bindings were pressed this frame
+pub fn action_pressed(input: &InputState, action: Action) -> bool {
+    action_matches(input, action, Binding::pressed)
+}
+
+/// Check if any of an action's bindings were released this frame
+pub fn action_released(input: &InputState, action: Action) -> bool {
+    bindings(action).iter().any(|b| b.released(input))
+}
+
+/// The value of the first gamepad axis bound to an action that is outside the deadzone
+pub fn action_axis(action: Action) -> Option<f32> {
+    bindings(action).iter().find_map(|b| match b.input {
+        BindingInput::Axis(axis) => Some(axis_value(axis)).filter(|v| v.abs() > AXIS_DEADZONE),
+        _ => None,
+    })
+}
+
+/// The first mouse button bound to an action
+pub fn action_pointer_button(action: Action) -> Option<PointerButton> {
+    bindings(action).iter().find_map(|b| match b.input {
+        BindingInput::Mouse(button) => Some(button),
+        BindingInput::Key(_) | BindingInput::Axis(_) => None,
+    })
+}
+
+/// An action binding waiting for the next key or button press
+#[derive(Debug, Clone, Copy)]
+pub struct Rebinding {
+    pub action: Action,
+    /// The index of the binding to replace, or `None` to add one
+    pub index: Option<usize>,
+}
+
+/// Show the bindings of every action and let them be changed
+pub fn input_settings_ui(
+    ui: &mut Ui,
+    rebinding: &mut Option<Rebinding>,
+    message: &mut Option<String>,
+) {
+    // Capture the next press
+    if let Some(Rebinding { action, index }) = *rebinding {
+        let pressed = ui.input(|input| {
+            input.events.iter().find_map(|event| match event {
+                Event::Key {
+                    key,
+                    pressed: true,
+                    repeat: false,
+                    modifiers,
+                } => Some((BindingInput::Key(*key), *modifiers)),
+                Event::PointerButton {
+                    button,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } => Some((BindingInput::Mouse(*button), *modifiers)),
+                _ => None,
+            })
+        });
+        let pressed = pressed
+            .or_else(|| moved_axis().map(|axis| (BindingInput::Axis(axis), Modifiers::NONE)));
+        match pressed {
+            Some((BindingInput::Key(Key::Escape), _)) => *rebinding = None,
+            Some((BindingInput::Key(_) | BindingInput::Axis(_), _)) if action.mouse_only() => {}
+            Some((BindingInput::Key(_) | BindingInput::Mouse(_), _)) if action.axis_only() => {}
+            Some((input, modifiers)) => {
+                let axis = matches!(input, BindingInput::Axis(_));
+                let binding = Binding {
+                    input,
+                    shift: modifiers.shift && !axis,
+                    command: modifiers.command && !axis,
+                };
+                let mut map = INPUT_MAP.lock();
+                let bindings = map.entry(action).or_default();
+                match index {
+                    Some(i) if i < bindings.len() => bindings[i] = binding,
+                    _ => bindings.push(binding),
+                }
+                drop(map);
+                *rebinding = None;
+                *message = save_input_map().err().map(|e| e.to_string());
+            }
+            None => {}
+        }
+    }
+    // Bindings
+    let mut to_remove = None;
+    Grid::new("input bindings").striped(true).show(ui, |ui| {
+        for action in all::<Action>() {
+            ui.label(tr(&action.to_string()));
+            ui.horizontal(|ui| {
+                for (i, binding) in bindings(action).into_iter().enumerate() {
+                    let waiting = matches!(
+                        rebinding,
+                        Some(r) if r.action == action && r.index == Some(i)
+                    );
+                    let text = if waiting {
+                        "...".to_string()
+                    } else {
+                        binding.to_string()
+                    };
+                    let resp = ui.selectable_label(waiting, text);
+                    if resp.clicked() {
+                        *rebinding = Some(Rebinding {
+                            action,
+                            index: Some(i),
+                        });
+                    }
+                    if resp.secondary_clicked() {
+                        to_remove = Some((action, i));
+                    }
+                }
+                let waiting = matches!(
+                    rebinding,
+                    Some(r) if r.action == action && r.index.is_none()
+                );
+                if ui.selectable_label(waiting, "+").clicked() {
+                    *rebinding = Some(Rebinding {
+                        action,
+                        index: None,
+                    });
+                }
+            });
+            ui.end_row();
+        }
+    });
+    if let Some((action, i)) = to_remove {
+        if let Some(bindings) = INPUT_MAP.lock().get_mut(&action) {
+            bindings.remove(i);
+        }
+        *message = save_input_map().err().map(|e| e.to_string());
+    }
+    ui.small(tr("Click a binding to change it, right click to remove it"));
+    if let Some(message) = message {
+        ui.colored_label(ui.visuals().error_fg_color, message.as_str());
+    }
+}
+
+#[test]
+fn binding_test() {
+    for s in [
+        "A",
+        "Shift+Space",
+        "Ctrl+Shift+Z",
+        "MouseMiddle",
+        "F12",
+        "LeftStickX",
+    ] {
+        let binding = Binding::try_from(s.to_string()).unwrap();
+        assert_eq!(binding.to_string(), s);
+    }
+    assert!(Binding::try_from("Hyper+A".to_string()).is_err());
+    assert!(Binding::try_from("Nope".to_string()).is_err());
+    assert!(Binding::try_from("Shift+LeftStickY".to_string()).is_err());
+    let map: InputMap = serde_yaml::from_str(include_str!("../resources/input.yaml")).unwrap();
+    assert!(all::<Action>().all(|action| map.contains_key(&action)));
+    // Unbound modifiers are ignored unless a binding of the same key uses them
+    let shift = Modifiers::SHIFT;
+    let binding = |s: &str| Binding::try_from(s.to_string()).unwrap();
+    assert!(binding("A").modifiers_held(shift) && !binding("A").shadowed(&map, shift));
+    assert!(binding("Space").shadowed(&map, shift));
+    assert!(!binding("Space").shadowed(&map, Modifiers::NONE));
+    assert!(binding("Ctrl+Z").shadowed(&map, Modifiers::COMMAND | shift));
+}
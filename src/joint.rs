@@ -0,0 +1,242 @@
+use eframe::egui::*;
+use rapier2d::{na::Unit, prelude::*};
+
+use crate::{
+    math::Convert,
+    object::{JointKind, PlacedJoint},
+    world::World,
+};
+
+/// The fraction of a rope's stretch that is pulled back each step
+const ROPE_CORRECTION: f32 = 0.2;
+/// The shortest lever arm an angular limit is taken to push through
+///
+/// This keeps an anchor at the center of mass from turning a small torque into a huge force.
+const MIN_LEVER_ARM: f32 = 0.1;
+
+/// A joint between two objects
+pub struct Joint {
+    pub kind: JointKind,
+    pub body1: RigidBodyHandle,
+    pub body2: RigidBodyHandle,
+    /// The anchors in the space of each body
+    pub local_anchor1: Point<Real>,
+    pub local_anchor2: Point<Real>,
+    /// The physics joint, which ropes do not have
+    pub handle: Option<ImpulseJointHandle>,
+    pub break_force: Option<f32>,
+    /// The force the joint held the objects together with in the last step
+    pub force: f32,
+}
+
+impl World {
+    /// Join two objects as described by a place
+    pub fn add_joint(
+        &mut self,
+        placed: &PlacedJoint,
+        body1: RigidBodyHandle,
+        body2: RigidBodyHandle,
+    ) {
+        let iso1 = *self.physics.bodies[body1].position();
+        let iso2 = *self.physics.bodies[body2].position();
+        let anchor: Point<Real> = placed.anchor.convert();
+        let to = match placed.kind {
+            JointKind::Rope { to, .. } => to.convert(),
+            _ => anchor,
+        };
+        let local_anchor1 = iso1.inverse_transform_point(&anchor);
+        let local_anchor2 = iso2.inverse_transform_point(&to);
+        let mut kind = placed.kind.clone();
+        let data: Option<GenericJoint> = match &mut kind {
+            JointKind::Revolute { limits } => {
+                let mut builder = RevoluteJointBuilder::new()
+                    .local_anchor1(local_anchor1)
+                    .local_anchor2(local_anchor2)
+                    .contacts_enabled(false);
+                if let Some(limits) = limits {
+                    builder = builder.limits(*limits);
+                }
+                Some(builder.build().into())
+            }
+            JointKind::Prismatic { axis, limits } => {
+                let axis = Unit::new_normalize(axis.convert());
+                let mut builder = PrismaticJointBuilder::new(axis)
+                    .local_anchor1(local_anchor1)
+                    .local_anchor2(local_anchor2)
+                    .local_axis1(iso1.inverse_transform_unit_vector(&axis))
+                    .local_axis2(iso2.inverse_transform_unit_vector(&axis))
+                    .contacts_enabled(false);
+                if let Some(limits) = limits {
+                    builder = builder.limits(*limits);
+                }
+                Some(builder.build().into())
+            }
+            JointKind::Fixed => {
+                let frame = Isometry::translation(anchor.x, anchor.y);
+                let builder = FixedJointBuilder::new()
+                    .local_frame1(iso1.inv_mul(&frame))
+                    .local_frame2(iso2.inv_mul(&frame))
+                    .contacts_enabled(false);
+                Some(builder.build().into())
+            }
+            JointKind::Rope { length, .. } => {
+                *length = Some(length.unwrap_or_else(|| (to - anchor).norm()));
+                None
+            }
+        };
+        let handle =
+            data.map(|data| (self.physics.impulse_joints).insert(body1, body2, data, true));
+        self.joints.push(Joint {
+            kind,
+            body1,
+            body2,
+            local_anchor1,
+            local_anchor2,
+            handle,
+            break_force: placed.break_force,
+            force: 0.0,
+        });
+    }
+    /// Get the world positions of a joint's anchors
+    pub fn joint_anchors(&self, joint: &Joint) -> Option<(Pos2, Pos2)> {
+        let body1 = self.physics.bodies.get(joint.body1)?;
+        let body2 = self.physics.bodies.get(joint.body2)?;
+        Some((
+            (body1.position() * joint.local_anchor1).convert(),
+            (body2.position() * joint.local_anchor2).convert(),
+        ))
+    }
+    /// Pull together the ends of ropes that are stretched past their length
+    pub fn pull_ropes(&mut self) {
+        let dt = self.physics.dt();
+        let bodies = &mut self.physics.bodies;
+        for joint in &mut self.joints {
+            let JointKind::Rope {
+                length: Some(length),
+                ..
+            } = joint.kind
+            else {
+                continue;
+            };
+            joint.force = 0.0;
+            let (Some(body1), Some(body2)) = (bodies.get(joint.body1), bodies.get(joint.body2))
+            else {
+                continue;
+            };
+            let p1 = body1.position() * joint.local_anchor1;
+            let p2 = body2.position() * joint.local_anchor2;
+            let dist = (p2 - p1).norm();
+            if dist <= length {
+                continue;
+            }
+            let normal = (p2 - p1) / dist;
+            let separating =
+                (body2.velocity_at_point(&p2) - body1.velocity_at_point(&p1)).dot(&normal);
+            let target = -ROPE_CORRECTION * (dist - length) / dt;
+            // The inverse of the mass the rope feels at each end
+            let inv_mass = |body: &RigidBody, point: &Point<Real>| {
+                if !body.is_dynamic() {
+                    return 0.0;
+                }
+                let props = body.mass_properties();
+                let arm = point - body.position() * props.local_com;
+                props.inv_mass
+                    + arm.perp(&normal).powi(2) * props.inv_principal_inertia_sqrt.powi(2)
+            };
+            let inv_mass = inv_mass(body1, &p1) + inv_mass(body2, &p2);
+            if inv_mass == 0.0 {
+                continue;
+            }
+            // A rope can only pull
+            let impulse = ((separating - target) / inv_mass).max(0.0);
+            joint.force = impulse / dt;
+            for (handle, point, sign) in [(joint.body1, p1, 1.0), (joint.body2, p2, -1.0)] {
+                let body = &mut bodies[handle];
+                if body.is_dynamic() {
+                    body.apply_impulse_at_point(normal * impulse * sign, point, true);
+                }
+            }
+        }
+    }
+    /// Measure the force on each joint and break the ones pulled too hard
+    pub fn run_joints(&mut self) {
+        let dt = self.physics.dt();
+        let objects = &self.objects;
+        let bodies = &self.physics.bodies;
+        let impulse_joints = &mut self.physics.impulse_joints;
+        self.joints.retain_mut(|joint| {
+            // Joints go with the objects they hold
+            if !objects.contains_key(&joint.body1) || !objects.contains_key(&joint.body2) {
+                return false;
+            }
+            if let Some(handle) = joint.handle {
+                let Some(physical) = impulse_joints.get(handle) else {
+                    return false;
+                };
+                // Limits push separately from locked axes
+                let limits = &physical.data.limits;
+                let impulse = physical.impulses.fixed_rows::<2>(0)
+                    + vector![limits[0].impulse, limits[1].impulse];
+                // The angular limit pushes on the anchor through its lever arm
+                let com = bodies[joint.body1].mass_properties().local_com;
+                let lever = (joint.local_anchor1 - com).norm().max(MIN_LEVER_ARM);
+                let angular = limits[2].impulse / lever;
+                joint.force = impulse.norm().hypot(angular) / dt;
+            }
+            if joint.break_force.is_some_and(|max| joint.force > max) {
+                if let Some(handle) = joint.handle {
+                    impulse_joints.remove(handle, true);
+                }
+                return false;
+            }
+            true
+        });
+    }
+}
+
+#[test]
+fn joint_test() {
+    use crate::object::PLACES;
+    let mut world = World::test_place("workshop");
+    assert_eq!(world.joints.len(), PLACES["workshop"].joints.len());
+    // Joints hold their objects together as they settle
+    for _ in 0..120 {
+        world.update();
+    }
+    for joint in &world.joints {
+        let (a, b) = world.joint_anchors(joint).unwrap();
+        match joint.kind {
+            JointKind::Rope {
+                length: Some(length),
+                ..
+            } => assert!(a.distance(b) < length * 1.05),
+            JointKind::Prismatic { .. } => {}
+            _ => assert!(a.distance(b) < 0.05, "{:?} came apart", joint.kind),
+        }
+    }
+    // Swinging a door into its limit pushes on its hinge
+    let hinge = (world.joints.iter())
+        .position(|joint| matches!(joint.kind, JointKind::Revolute { limits: Some(_) }))
+        .unwrap();
+    let door = world.joints[hinge].body1;
+    let resting_force = world.joints[hinge].force;
+    world.physics.bodies[door].set_angvel(30.0, true);
+    let mut max_force = 0.0f32;
+    for _ in 0..10 {
+        world.update();
+        max_force = max_force.max(world.joints[hinge].force);
+    }
+    assert!(
+        max_force > resting_force * 10.0,
+        "{max_force} {resting_force}"
+    );
+    // Yanking a rope too hard snaps it
+    let joints = world.joints.len();
+    let rope = (world.joints.iter())
+        .find(|joint| matches!(joint.kind, JointKind::Rope { .. }) && joint.break_force.is_some())
+        .unwrap();
+    let handle = rope.body1;
+    world.physics.bodies[handle].set_linvel([0.0, -40.0].into(), true);
+    world.update();
+    assert_eq!(world.joints.len(), joints - 1);
+}
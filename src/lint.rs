@@ -0,0 +1,593 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+};
+
+use enum_iterator::all;
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::{
+    dialog::*,
+    field::Type,
+    npc::NpcId,
+    person::PersonId,
+    stack::Stack,
+    utils::resources_path,
+    word::{Word, WORD_GRID},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Warning,
+    Error,
+}
+
+struct Diagnostic {
+    severity: Severity,
+    scene: String,
+    message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        if self.scene.is_empty() {
+            write!(f, "{severity}: {}", self.message)
+        } else {
+            write!(f, "{severity}: {}: {}", self.scene, self.message)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Linter {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter {
+    fn report(&mut self, severity: Severity, scene: &str, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            scene: scene.into(),
+            message: message.into(),
+        });
+    }
+    fn error(&mut self, scene: &str, message: impl Into<String>) {
+        self.report(Severity::Error, scene, message)
+    }
+    fn warning(&mut self, scene: &str, message: impl Into<String>) {
+        self.report(Severity::Warning, scene, message)
+    }
+}
+
+/// Run static analysis on all dialog scenes, print the results,
+/// and return the process exit code
+pub fn lint_dialog() -> i32 {
+    let mut load_errors = Vec::new();
+    let scenes = read_scenes(&mut load_errors);
+    let mut linter = Linter::default();
+    for error in load_errors {
+        linter.error("", error.to_string());
+    }
+    lint_scenes(&mut linter, &scenes);
+    for diagnostic in &linter.diagnostics {
+        eprintln!("{diagnostic}");
+    }
+    let errors = linter
+        .diagnostics
+        .iter()
+        .filter(|diag| diag.severity == Severity::Error)
+        .count();
+    let warnings = linter.diagnostics.len() - errors;
+    eprintln!(
+        "Linted {} dialog scenes: {errors} errors, {warnings} warnings",
+        scenes.len()
+    );
+    (errors > 0) as i32
+}
+
+fn lint_scenes(linter: &mut Linter, scenes: &DialogScenes) {
+    let exits: HashMap<&str, IndexedExits> = scenes
+        .iter()
+        .map(|(scene_name, scene)| (scene_name.as_str(), scene_exits(scene_name, scene)))
+        .collect();
+    let entries = scene_entries(scenes, &exits);
+    for (scene_name, scene) in scenes.iter().sorted_by_key(|(name, _)| *name) {
+        let entries = entries
+            .get(scene_name.as_str())
+            .cloned()
+            .unwrap_or_default();
+        lint_graph(
+            linter,
+            scene_name,
+            scene,
+            &exits[scene_name.as_str()],
+            entries,
+        );
+        lint_images(linter, scene_name, scene);
+    }
+    lint_speakers(linter, scenes);
+    lint_flags(linter, scenes);
+    lint_words(linter, scenes);
+}
+
+/// Where the dialog can go after a node
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Exit<'a> {
+    /// Go to a node in the same scene
+    Node(&'a str),
+    /// Go to another scene, possibly at a specific node
+    Leave(&'a str, Option<&'a str>),
+    /// End the scene
+    End,
+    /// Get stuck with no way to continue
+    Stuck,
+}
+
+impl<'a> Exit<'a> {
+    fn target(scene_name: &'a str, target: &'a str) -> Self {
+        match split_target(scene_name, target) {
+            (target_scene, node) if target_scene == scene_name => Exit::Node(node),
+            (target_scene, node) => Exit::Leave(target_scene, Some(node)),
+        }
+    }
+    fn finishes(&self) -> bool {
+        matches!(self, Exit::End | Exit::Leave(..))
+    }
+}
+
+type IndexedExits<'a> = IndexMap<&'a str, Vec<Exit<'a>>>;
+
+fn scene_exits<'a>(
+    scene_name: &'a str,
+    scene: &'a DialogScene<DeserializedLine>,
+) -> IndexedExits<'a> {
+    scene
+        .nodes
+        .keys()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), node_exits(scene_name, scene, i)))
+        .collect()
+}
+
+fn node_exits<'a>(
+    scene_name: &'a str,
+    scene: &'a DialogScene<DeserializedLine>,
+    index: usize,
+) -> Vec<Exit<'a>> {
+    let (_, node) = scene.nodes.get_index(index).unwrap();
+    let next = scene
+        .nodes
+        .get_index(index + 1)
+        .map(|(name, _)| name.as_str());
+    // Starting another scene skips the rest of the node
+    for line in &node.lines {
+        if let Line::Command(DialogCommand::StartScene(target)) = line {
+            let (target_scene, target_node) = split_scene_target(target);
+            return vec![Exit::Leave(target_scene, target_node)];
+        }
+    }
+    let ends_with_text = matches!(node.lines.last(), Some(Line::Text(_)));
+    if !ends_with_text {
+        // The children are skipped if the node doesn't end with text
+        return vec![match next {
+            Some(next) => Exit::Node(next),
+            None if no_children(&node.children) => Exit::End,
+            None => Exit::Stuck,
+        }];
+    }
+    children_exits(scene_name, &node.children, next)
+}
+
+fn no_children(children: &NodeChildren<DeserializedLine>) -> bool {
+    matches!(children, NodeChildren::Choices(choices) if choices.is_empty())
+}
+
+fn children_exits<'a>(
+    scene_name: &'a str,
+    children: &'a NodeChildren<DeserializedLine>,
+    next: Option<&'a str>,
+) -> Vec<Exit<'a>> {
+    match children {
+        NodeChildren::Choices(choices) if choices.is_empty() => {
+            vec![next.map_or(Exit::End, Exit::Node)]
+        }
+        NodeChildren::Choices(choices) => choices
+            .keys()
+            .map(|target| Exit::target(scene_name, target))
+            .collect(),
+        NodeChildren::Jump { jump } => vec![Exit::target(scene_name, jump)],
+        NodeChildren::Wait { then, .. } => vec![Exit::target(scene_name, then)],
+        NodeChildren::Condition { then, els, .. } => children_exits(scene_name, then, next)
+            .into_iter()
+            .chain(children_exits(scene_name, els, next))
+            .collect(),
+        NodeChildren::List(list) if list.is_empty() => vec![Exit::Stuck],
+        NodeChildren::List(list) => list
+            .iter()
+            .flat_map(|children| children_exits(scene_name, children, next))
+            .collect(),
+        NodeChildren::Next(_) => vec![next.map_or(Exit::Stuck, Exit::Node)],
+    }
+}
+
+/// Get the nodes of each scene that other scenes jump to or call directly
+fn scene_entries<'a>(
+    scenes: &'a DialogScenes,
+    exits: &HashMap<&'a str, IndexedExits<'a>>,
+) -> HashMap<&'a str, HashSet<&'a str>> {
+    let mut entries: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for scene_exits in exits.values() {
+        for exit in scene_exits.values().flatten() {
+            if let Exit::Leave(scene_name, Some(node)) = exit {
+                entries.entry(scene_name).or_default().insert(node);
+            }
+        }
+    }
+    for scene in scenes.values() {
+        for (_, command) in commands(scene) {
+            if let DialogCommand::Call(target) = command {
+                if let (scene_name, Some(node)) = split_scene_target(target) {
+                    entries.entry(scene_name).or_default().insert(node);
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// Find unreachable nodes, dead ends, and cycles that can never finish the scene
+fn lint_graph(
+    linter: &mut Linter,
+    scene_name: &str,
+    scene: &DialogScene<DeserializedLine>,
+    exits: &IndexedExits,
+    entries: HashSet<&str>,
+) {
+    // Dead ends and ignored children
+    for (i, (node_name, node)) in scene.nodes.iter().enumerate() {
+        if exits[node_name.as_str()].contains(&Exit::Stuck) {
+            linter.error(
+                scene_name,
+                format!("node {node_name} is a dead end with no way to continue"),
+            );
+        }
+        let ends_with_text = matches!(node.lines.last(), Some(Line::Text(_)));
+        if !ends_with_text && !no_children(&node.children) && i + 1 < scene.nodes.len() {
+            linter.warning(
+                scene_name,
+                format!(
+                    "node {node_name}'s children are never shown \
+                    because it does not end with a line of text"
+                ),
+            );
+        }
+    }
+    // Unreachable nodes
+    let first = scene.nodes.keys().next().unwrap().as_str();
+    let mut reachable = HashSet::new();
+    for entry in entries.into_iter().chain([first]) {
+        reachable.insert(entry);
+        reachable.extend(reachable_from(exits, entry));
+    }
+    for node_name in scene.nodes.keys() {
+        if !reachable.contains(node_name.as_str()) {
+            linter.warning(scene_name, format!("node {node_name} is unreachable"));
+        }
+    }
+    // Cycles that cannot progress
+    let reaches: HashMap<&str, HashSet<&str>> = exits
+        .keys()
+        .map(|&name| (name, reachable_from(exits, name)))
+        .collect();
+    let mut reported = HashSet::new();
+    for &node_name in exits.keys() {
+        let in_cycle = reaches[node_name].contains(node_name);
+        let can_end = reaches[node_name]
+            .iter()
+            .chain([&node_name])
+            .any(|node| exits[node].iter().any(Exit::finishes));
+        if !in_cycle || can_end || reported.contains(node_name) || !reachable.contains(node_name) {
+            continue;
+        }
+        let cycle = exits
+            .keys()
+            .copied()
+            .filter(|other| {
+                reaches[node_name].contains(other) && reaches[other].contains(node_name)
+            })
+            .collect_vec();
+        reported.extend(cycle.iter().copied());
+        linter.error(
+            scene_name,
+            format!(
+                "nodes {} form a cycle that never reaches the end of the scene",
+                cycle.join(", ")
+            ),
+        );
+    }
+}
+
+/// Get the set of nodes reachable from a node by following at least one exit
+fn reachable_from<'a>(exits: &IndexedExits<'a>, start: &'a str) -> HashSet<&'a str> {
+    let mut reachable = HashSet::new();
+    let mut queue = vec![start];
+    while let Some(name) = queue.pop() {
+        let Some(node_exits) = exits.get(name) else {
+            continue;
+        };
+        for exit in node_exits {
+            if let Exit::Node(next) = exit {
+                if reachable.insert(*next) {
+                    queue.push(next);
+                }
+            }
+        }
+    }
+    reachable
+}
+
+fn commands(scene: &DialogScene<DeserializedLine>) -> impl Iterator<Item = (&str, &DialogCommand)> {
+    scene.nodes.iter().flat_map(|(node_name, node)| {
+        node.lines.iter().filter_map(move |line| match line {
+            Line::Command(command) => Some((node_name.as_str(), command)),
+            Line::Text(_) => None,
+        })
+    })
+}
+
+/// Make sure all portraits and backgrounds exist
+fn lint_images(linter: &mut Linter, scene_name: &str, scene: &DialogScene<DeserializedLine>) {
+    let images_path = resources_path().join("images");
+    for (node_name, command) in commands(scene) {
+        let image = match command {
+            DialogCommand::Left(Some(speaker)) | DialogCommand::Right(Some(speaker)) => {
+                speaker.image().into_owned()
+            }
+            DialogCommand::Background(Some(image)) => image.clone(),
+            _ => continue,
+        };
+        if !images_path.join(&image).exists() {
+            linter.error(
+                scene_name,
+                format!("node {node_name} uses unknown image {image}"),
+            );
+        }
+    }
+}
+
+/// Make sure every npc speaker exists and every current speaker
+/// is either an npc or has a portrait
+fn lint_speakers(linter: &mut Linter, scenes: &DialogScenes) {
+    let npcs: HashSet<String> = all::<NpcId>().map(|id| format!("{id:?}")).collect();
+    let mut known = npcs.clone();
+    for scene in scenes.values() {
+        for (_, command) in commands(scene) {
+            if let DialogCommand::Left(Some(Speaker::Image { name, .. }))
+            | DialogCommand::Right(Some(Speaker::Image { name, .. })) = command
+            {
+                known.insert(name.clone());
+            }
+        }
+    }
+    for (scene_name, scene) in scenes.iter().sorted_by_key(|(name, _)| *name) {
+        for (node_name, command) in commands(scene) {
+            match command {
+                DialogCommand::Left(Some(Speaker::Npc(name)))
+                | DialogCommand::Right(Some(Speaker::Npc(name)))
+                    if !npcs.contains(name) =>
+                {
+                    linter.error(
+                        scene_name,
+                        format!("node {node_name} shows unknown npc {name}"),
+                    );
+                }
+                DialogCommand::Speaker(Some(CurrentSpeaker::Npc(name)))
+                    if !known.contains(name) =>
+                {
+                    linter.error(
+                        scene_name,
+                        format!("node {node_name} has unknown speaker {name}"),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn condition_flags(condition: &Condition) -> Vec<&str> {
+    match condition {
+        Condition::Flag(flag) => vec![flag],
+        Condition::Not(inner) => condition_flags(inner),
+        Condition::And(conditions) | Condition::Or(conditions) => {
+            conditions.iter().flat_map(condition_flags).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Make sure every flag that is read is set somewhere
+fn lint_flags(linter: &mut Linter, scenes: &DialogScenes) {
+    let set: HashSet<&str> = scenes
+        .values()
+        .flat_map(commands)
+        .filter_map(|(_, command)| match command {
+            DialogCommand::Set(flag) => Some(flag.as_str()),
+            _ => None,
+        })
+        .collect();
+    for (scene_name, scene) in scenes.iter().sorted_by_key(|(name, _)| *name) {
+        for (node_name, node) in &scene.nodes {
+            for condition in node.children.conditions() {
+                for flag in condition_flags(condition) {
+                    if !set.contains(flag) {
+                        linter.error(
+                            scene_name,
+                            format!("node {node_name} reads flag {flag}, which is never set"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Make sure every revealed word can actually be cast with the other revealed words
+fn lint_words(linter: &mut Linter, scenes: &DialogScenes) {
+    let all_revealed = scenes
+        .values()
+        .flat_map(commands)
+        .any(|(_, command)| matches!(command, DialogCommand::RevealAllWords));
+    let revealed: Vec<(&str, &str, Word)> = scenes
+        .iter()
+        .sorted_by_key(|(name, _)| *name)
+        .flat_map(|(scene_name, scene)| {
+            commands(scene).filter_map(move |(node_name, command)| match command {
+                DialogCommand::RevealWord(word) => Some((scene_name.as_str(), node_name, *word)),
+                _ => None,
+            })
+        })
+        .collect();
+    let known: HashSet<Word> = if all_revealed {
+        all::<Word>().collect()
+    } else {
+        revealed.iter().map(|(.., word)| *word).collect()
+    };
+    let castable = castable_words(&known);
+    for (scene_name, node_name, word) in revealed {
+        if !WORD_GRID.iter().flatten().contains(&word) {
+            linter.error(
+                scene_name,
+                format!("node {node_name} reveals {word}, which is not in the word grid"),
+            );
+        } else if !castable.contains(&word) {
+            linter.warning(
+                scene_name,
+                format!(
+                    "node {node_name} reveals {word}, which can never be cast \
+                    with the words the player learns"
+                ),
+            );
+        }
+    }
+}
+
+/// Find all the words that can be said on some stack built from the known words
+fn castable_words(known: &HashSet<Word>) -> HashSet<Word> {
+    const MAX_DEPTH: usize = 4;
+    let mut castable = HashSet::new();
+    let mut visited: BTreeSet<Vec<Type>> = BTreeSet::new();
+    let mut queue = vec![Stack::default()];
+    while let Some(stack) = queue.pop() {
+        if !visited.insert(stack.iter().map(|item| item.field.ty()).collect()) {
+            continue;
+        }
+        for &word in known {
+            if stack.validate_function_use(word.function()).is_err() {
+                continue;
+            }
+            castable.insert(word);
+            let mut stack = stack.clone();
+            if stack.say(PersonId::Player, word, None).is_ok() && stack.len() <= MAX_DEPTH {
+                queue.push(stack);
+            }
+        }
+    }
+    castable
+}
+
+#[cfg(test)]
+fn lint_test_scenes(scenes: &[(&str, &str)]) -> Vec<String> {
+    let scenes: DialogScenes = scenes
+        .iter()
+        .map(|(name, yaml)| (name.to_string(), parse_scene(name, yaml).unwrap().unwrap()))
+        .collect();
+    let mut linter = Linter::default();
+    lint_scenes(&mut linter, &scenes);
+    linter.diagnostics.iter().map(|d| d.to_string()).collect()
+}
+
+#[test]
+fn lint_graph_test() {
+    let diagnostics = lint_test_scenes(&[(
+        "a",
+        "
+start:
+  lines: [Hello]
+  children: { jump: spin }
+orphan:
+  lines: [Nobody comes here]
+spin:
+  lines: [Round and round]
+  children: { jump: spin }
+stuck:
+  lines: [There is no way on]
+  children: [Continue]
+",
+    )]);
+    assert!(diagnostics.contains(&"warning: a: node orphan is unreachable".into()));
+    assert!(diagnostics.contains(
+        &"error: a: nodes spin form a cycle that never reaches the end of the scene".into()
+    ));
+    assert!(
+        diagnostics.contains(&"error: a: node stuck is a dead end with no way to continue".into())
+    );
+}
+
+#[test]
+fn lint_flags_and_speakers_test() {
+    let diagnostics = lint_test_scenes(&[(
+        "a",
+        "
+start:
+  lines:
+    - left: Leavey
+    - speaker: Leavy
+    - set: greeted
+    - Hello
+  children:
+    if: { flag: met }
+    then: { jump: done }
+    else: { jump: done }
+done:
+  lines:
+    - speaker: Nobody
+    - Bye
+",
+    )]);
+    assert!(diagnostics.contains(&"error: a: node start reads flag met, which is never set".into()));
+    assert!(diagnostics.contains(&"error: a: node start shows unknown npc Leavey".into()));
+    assert!(diagnostics.contains(&"error: a: node done has unknown speaker Nobody".into()));
+    assert!(!diagnostics.iter().any(|d| d.contains("greeted")));
+    assert!(!diagnostics.iter().any(|d| d.contains("speaker Leavy")));
+}
+
+#[test]
+fn lint_words_test() {
+    use Word::*;
+    let castable = castable_words(&[Ma].into());
+    assert!(castable.is_empty());
+    let castable = castable_words(&[Te, Ma].into());
+    assert_eq!(castable, [Te, Ma].into());
+    let diagnostics = lint_test_scenes(&[(
+        "a",
+        "
+start:
+  lines:
+    - reveal_word: Ma
+    - Words
+",
+    )]);
+    assert_eq!(
+        diagnostics,
+        [
+            "warning: a: node start reveals Ma, which can never be cast \
+        with the words the player learns"
+        ]
+    );
+}
@@ -0,0 +1,248 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::{anyhow, bail};
+use eframe::epaint::mutex::Mutex;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::{
+    dialog::{parse_line, DeserializedLine, DIALOG_SCENES},
+    player::Gender,
+    utils::{fatal_error, resources_path},
+};
+
+/// A translation of the game into a language other than English
+pub struct Locale {
+    pub name: String,
+    strings: HashMap<String, String>,
+    gender: HashMap<String, GenderForms>,
+    dialog: HashMap<String, HashMap<String, NodeTranslation<DeserializedLine>>>,
+}
+
+#[derive(Deserialize)]
+struct LocaleDef {
+    name: String,
+    #[serde(default)]
+    strings: HashMap<String, String>,
+    #[serde(default)]
+    gender: HashMap<String, GenderForms>,
+}
+
+/// The forms of a word for each gender
+#[derive(Debug, Clone, Deserialize)]
+pub struct GenderForms {
+    pub male: String,
+    pub female: String,
+    pub enby: String,
+}
+
+impl GenderForms {
+    pub fn get(&self, gender: Gender) -> &str {
+        match gender {
+            Gender::Male => &self.male,
+            Gender::Female => &self.female,
+            Gender::Enby => &self.enby,
+        }
+    }
+}
+
+/// Translated lines of a dialog node
+///
+/// Lines are keyed by their id in the original node.
+/// Choices are keyed by the node they lead to.
+#[derive(Deserialize)]
+struct NodeTranslation<T> {
+    #[serde(default = "HashMap::new")]
+    lines: HashMap<String, T>,
+    #[serde(default = "HashMap::new")]
+    choices: HashMap<String, Vec<T>>,
+    #[serde(default = "Vec::new")]
+    next: Vec<T>,
+}
+
+pub static LOCALES: Lazy<HashMap<String, Locale>> = Lazy::new(|| {
+    load_locales().unwrap_or_else(|e| fatal_error(format!("Error loading locales: {e}")))
+});
+
+static CURRENT_LOCALE: Lazy<Mutex<Option<String>>> = Lazy::new(Default::default);
+
+fn load_locales() -> anyhow::Result<HashMap<String, Locale>> {
+    let mut map = HashMap::new();
+    let locales_path = resources_path().join("locales");
+    if !locales_path.exists() {
+        return Ok(map);
+    }
+    for entry in fs::read_dir(locales_path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let code = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        let yaml = fs::read_to_string(path.join("locale.yaml"))
+            .map_err(|e| anyhow!("Unable to read {code} locale: {e}"))?;
+        let def: LocaleDef = serde_yaml::from_str(&yaml)
+            .map_err(|e| anyhow!("Unable to read {code} locale: {e}"))?;
+        let mut dialog = HashMap::new();
+        let dialog_path = path.join("dialog");
+        if dialog_path.exists() {
+            for entry in fs::read_dir(dialog_path)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "yaml") {
+                    let scene_name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                    let yaml = fs::read_to_string(&path)?;
+                    // Skip files with nothing translated yet
+                    if yaml.lines().all(|line| {
+                        let line = line.trim();
+                        line.is_empty() || line.starts_with('#')
+                    }) {
+                        continue;
+                    }
+                    let nodes: HashMap<String, NodeTranslation<String>> =
+                        serde_yaml::from_str(&yaml).map_err(|e| {
+                            anyhow!("Unable to read {code} translation of {scene_name}: {e}")
+                        })?;
+                    let nodes = parse_translation(&scene_name, nodes)
+                        .map_err(|e| anyhow!("Error in {code} translation: {e}"))?;
+                    dialog.insert(scene_name, nodes);
+                }
+            }
+        }
+        map.insert(
+            code,
+            Locale {
+                name: def.name,
+                strings: def.strings,
+                gender: def.gender,
+                dialog,
+            },
+        );
+    }
+    Ok(map)
+}
+
+fn parse_translation(
+    scene_name: &str,
+    nodes: HashMap<String, NodeTranslation<String>>,
+) -> anyhow::Result<HashMap<String, NodeTranslation<DeserializedLine>>> {
+    let Some(scene) = DIALOG_SCENES.get(scene_name) else {
+        bail!("there is no {scene_name} scene")
+    };
+    let mut parsed = HashMap::new();
+    for (node_name, node) in nodes {
+        let Some(original) = scene.nodes.get(&node_name) else {
+            bail!("{scene_name} scene has no node {node_name}")
+        };
+        let parse_all = |texts: Vec<String>| -> anyhow::Result<Vec<DeserializedLine>> {
+            texts.iter().map(|text| parse_line(text)).collect()
+        };
+        let mut lines = HashMap::new();
+        for (id, text) in node.lines {
+            if !original.line_ids.values().any(|line_id| *line_id == id) {
+                bail!("{scene_name}/{node_name} has no line with id {id}")
+            }
+            lines.insert(id, parse_line(&text)?);
+        }
+        let mut choices = HashMap::new();
+        for (name, texts) in node.choices {
+            choices.insert(name, parse_all(texts)?);
+        }
+        parsed.insert(
+            node_name,
+            NodeTranslation {
+                lines,
+                choices,
+                next: parse_all(node.next)?,
+            },
+        );
+    }
+    Ok(parsed)
+}
+
+/// Set the current locale, or `None` for English
+pub fn set_locale(code: Option<&str>) {
+    *CURRENT_LOCALE.lock() = code.map(Into::into);
+}
+
+/// Get the code of the current locale, or `None` for English
+pub fn current_locale() -> Option<String> {
+    CURRENT_LOCALE.lock().clone()
+}
+
+fn with_locale<T>(f: impl FnOnce(&'static Locale) -> Option<T>) -> Option<T> {
+    let code = CURRENT_LOCALE.lock();
+    code.as_ref().and_then(|code| LOCALES.get(code)).and_then(f)
+}
+
+/// Translate a UI string into the current locale
+pub fn tr(text: &str) -> String {
+    with_locale(|locale| locale.strings.get(text).cloned()).unwrap_or_else(|| text.into())
+}
+
+/// Get the current locale's forms of a gendered word
+pub fn gender_forms(word: &str) -> Option<&'static GenderForms> {
+    with_locale(|locale| locale.gender.get(word))
+}
+
+fn node_translation(
+    scene_name: &str,
+    node_name: &str,
+) -> Option<&'static NodeTranslation<DeserializedLine>> {
+    with_locale(|locale| locale.dialog.get(scene_name)?.get(node_name))
+}
+
+/// Get the translation of a node's text line by its id
+pub fn dialog_line(
+    scene_name: &str,
+    node_name: &str,
+    id: &str,
+) -> Option<&'static DeserializedLine> {
+    node_translation(scene_name, node_name)?.lines.get(id)
+}
+
+/// Get the translation of a choice
+pub fn dialog_choice(
+    scene_name: &str,
+    node_name: &str,
+    choice: &str,
+    index: usize,
+) -> Option<&'static DeserializedLine> {
+    node_translation(scene_name, node_name)?
+        .choices
+        .get(choice)?
+        .get(index)
+}
+
+/// Get the translation of a continue option
+pub fn dialog_next(
+    scene_name: &str,
+    node_name: &str,
+    index: usize,
+) -> Option<&'static DeserializedLine> {
+    node_translation(scene_name, node_name)?.next.get(index)
+}
+
+#[test]
+fn dialog_override_test() {
+    use crate::dialog::{DialogFragment, Line};
+    let text = |line: &DeserializedLine| {
+        (line.iter())
+            .filter_map(|fragment| match fragment {
+                DialogFragment::String(s) => Some(s.as_str()),
+                DialogFragment::Variable(_) => None,
+            })
+            .collect::<String>()
+    };
+    let node = &DIALOG_SCENES["intro"].nodes["end"];
+    let (&index, _) = (node.line_ids.iter())
+        .find(|(_, id)| *id == "welcome")
+        .unwrap();
+    let Line::Text(original) = &node.lines[index] else {
+        panic!("welcome is not a text line");
+    };
+    for code in ["de", "es"] {
+        let translated = &LOCALES[code].dialog["intro"]["end"].lines["welcome"];
+        assert_ne!(text(translated), text(original));
+        assert_eq!(translated.len(), original.len());
+    }
+}
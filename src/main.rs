@@ -1,30 +1,51 @@
 mod color;
 mod conduit;
+mod conduit_ui;
+mod contact;
 mod controls;
 mod dialog;
+mod duel;
 mod error;
 mod field;
+mod fluid;
+mod fracture;
 mod function;
 mod game;
+mod history;
 mod image;
+mod input;
+mod joint;
+mod lint;
+mod locale;
 mod main_menu;
 mod math;
 mod new_game;
 mod npc;
 mod object;
+mod order;
 mod person;
 mod physics;
 mod player;
 mod plot;
+mod replay;
+mod sim;
 mod stack;
 mod texture;
+mod thermal;
+mod transcript;
+mod typing;
 mod utils;
 mod word;
 mod world;
 
+use std::{env, process::exit};
+
+use conduit::CONDUITS;
 use dialog::DIALOG_SCENES;
 use eframe::egui::*;
 use game::Game;
+use input::INPUT_MAP;
+use locale::LOCALES;
 use main_menu::main_menu;
 use new_game::NewGame;
 use npc::NPCS;
@@ -34,11 +55,22 @@ use player::{Gender, Player};
 use texture::load_textures;
 
 fn main() {
+    // Lint dialog
+    if env::args().any(|arg| arg == "lint-dialog") {
+        exit(lint::lint_dialog());
+    }
+    // Run a scenario without a window
+    if env::args().any(|arg| arg == "sim") {
+        exit(sim::run_sim());
+    }
     // Load resources
     Lazy::force(&DIALOG_SCENES);
     Lazy::force(&OBJECTS);
     Lazy::force(&PLACES);
     Lazy::force(&NPCS);
+    Lazy::force(&CONDUITS);
+    Lazy::force(&LOCALES);
+    Lazy::force(&INPUT_MAP);
     // Enable profiling
     puffin::set_scopes_on(cfg!(all(feature = "profile", not(debug_assertions))));
     // Run
@@ -91,6 +123,8 @@ impl eframe::App for GameState {
         });
         puffin::GlobalProfiler::lock().new_frame();
 
+        input::poll_gamepads();
+
         // Resize
         let screen_size = ctx.input(|input| input.screen_rect.size());
         let window_size = screen_size * ctx.pixels_per_point();
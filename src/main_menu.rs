@@ -4,7 +4,14 @@ use eframe::egui::{plot::*, *};
 use once_cell::sync::Lazy;
 use rand::prelude::*;
 
-use crate::{new_game::NewGame, plot::time, GameState};
+use crate::{
+    game::Game,
+    locale::{current_locale, set_locale, tr, LOCALES},
+    new_game::NewGame,
+    plot::time,
+    replay::Replay,
+    GameState,
+};
 
 const LOGO_ASCII: &str = "
    ▄████████   ▄█   ████████▄    ▄██████▄      ▄████████
@@ -61,16 +68,59 @@ fn main_menu_ui(ui: &mut Ui) -> Option<GameState> {
     let mut res = None;
     ui.with_layout(Layout::top_down(Align::Center), |ui| {
         ui.spacing_mut().item_spacing.y = 20.0;
-        if ui.button(RichText::new("New Game").heading()).clicked() {
+        if ui.button(RichText::new(tr("New Game")).heading()).clicked() {
             res = Some(GameState::NewGame(NewGame::default()));
         }
-        if ui.button(RichText::new("Quit").heading()).clicked() {
+        if ui.button(RichText::new(tr("Duel")).heading()).clicked() {
+            res = Some(GameState::Game(Game::new_duel().into()));
+        }
+        if ui
+            .button(RichText::new(tr("Watch Replay")).heading())
+            .clicked()
+        {
+            match Replay::import().and_then(Game::from_replay) {
+                Ok(game) => res = Some(GameState::Game(game.into())),
+                Err(e) => {
+                    ui.data_mut(|data| data.insert_temp(Id::new("replay error"), e.to_string()))
+                }
+            }
+        }
+        if let Some(error) = ui.data(|data| data.get_temp::<String>(Id::new("replay error"))) {
+            ui.small(error);
+        }
+        if ui.button(RichText::new(tr("Quit")).heading()).clicked() {
             res = Some(GameState::Quit);
         }
+        locale_ui(ui);
     });
     res
 }
 
+fn locale_ui(ui: &mut Ui) {
+    let current = current_locale();
+    let mut locales = LOCALES
+        .iter()
+        .map(|(code, locale)| (code.as_str(), locale.name.as_str()))
+        .collect::<Vec<_>>();
+    locales.sort();
+    ui.horizontal(|ui| {
+        // Center the row
+        let width = (locales.len() + 1) as f32 * 80.0;
+        ui.add_space((ui.available_width() - width).max(0.0) / 2.0);
+        if ui.selectable_label(current.is_none(), "English").clicked() {
+            set_locale(None);
+        }
+        for (code, name) in locales {
+            if ui
+                .selectable_label(current.as_deref() == Some(code), name)
+                .clicked()
+            {
+                set_locale(Some(code));
+            }
+        }
+    });
+}
+
 fn logo_ui(ui: &mut Ui) {
     Plot::new("logo")
         .view_aspect(3.0)
@@ -2,6 +2,7 @@ use eframe::egui::*;
 
 use crate::{
     game::Game,
+    locale::tr,
     player::{Gender, Player},
     GameState,
 };
@@ -24,14 +25,14 @@ impl NewGame {
     pub fn show(&mut self, ctx: &Context) -> Option<GameState> {
         let mut res = None;
         CentralPanel::default().show(ctx, |ui| {
-            if ui.button("Back").clicked() {
+            if ui.button(tr("Back")).clicked() {
                 res = Some(GameState::MainMenu);
             }
             ui.add_space((ui.available_height() - 100.0) / 2.0);
             ui.spacing_mut().item_spacing.y = 20.0;
             Grid::new(()).show(ui, |ui| {
                 // Name
-                ui.label("Name");
+                ui.label(tr("Name"));
                 let name_res = TextEdit::singleline(&mut self.name)
                     .desired_width(100.0)
                     .show(ui);
@@ -48,7 +49,7 @@ impl NewGame {
                 ui.end_row();
 
                 // Gender
-                ui.label("Gender");
+                ui.label(tr("Gender"));
                 ui.horizontal(|ui| {
                     for (gender, symbol, hover_text) in [
                         (Gender::Male, "♂", "uses he/him/his"),
@@ -60,14 +61,14 @@ impl NewGame {
                             gender,
                             RichText::new(symbol).heading(),
                         )
-                        .on_hover_text(hover_text);
+                        .on_hover_text(tr(hover_text));
                     }
                 });
                 ui.end_row();
 
                 // Start
                 if ui
-                    .add_enabled(!self.name.is_empty(), Button::new("Start"))
+                    .add_enabled(!self.name.is_empty(), Button::new(tr("Start")))
                     .clicked()
                 {
                     res = Some(GameState::Game(
@@ -2,14 +2,14 @@ use std::{collections::HashMap, fs};
 
 use enum_iterator::Sequence;
 use once_cell::sync::Lazy;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     person::Person,
     utils::{fatal_error, resources_path},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Sequence, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Sequence, Serialize, Deserialize)]
 pub enum NpcId {
     Leavy,
 }
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+};
 
 use anyhow::{anyhow, bail};
 use eframe::egui::*;
@@ -8,7 +11,8 @@ use serde::{Deserialize, Deserializer};
 
 use crate::{
     math::rotate,
-    utils::{fatal_error, resources_path},
+    thermal::PhaseChange,
+    utils::{fatal_error, find_resources_path},
 };
 
 pub struct Object {
@@ -18,6 +22,12 @@ pub struct Object {
     pub ordered_pr: PosRot,
     pub vel: Vec2,
     pub heat: f32,
+    /// The heat absorbed or released by the phase change in progress
+    pub phase_progress: f32,
+    /// The index of the phase change that `phase_progress` belongs to
+    pub phase_index: usize,
+    /// The impulse from impacts the object has taken
+    pub damage: f32,
     pub body_handle: RigidBodyHandle,
     pub foreground_handles: Vec<ColliderHandle>,
     pub background_handles: Vec<ColliderHandle>,
@@ -33,14 +43,38 @@ pub struct PosRot {
 pub enum ObjectKind {
     Object,
     Ground,
+    /// A piece of a broken object
+    Debris,
 }
 
-#[derive(Debug, Clone, Default, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct ObjectProperties {
     pub magic: f32,
     pub light: f32,
     pub constant_heat: Option<f32>,
+    /// The heat the object starts with
+    pub initial_heat: Option<f32>,
+    /// How quickly heat flows through the object
+    pub conductivity: f32,
+    /// The heat needed to warm a unit of the object's mass by one degree
+    pub specific_heat: f32,
+    /// How strongly the object radiates heat, from 0 to 1
+    pub emissivity: f32,
+}
+
+impl Default for ObjectProperties {
+    fn default() -> Self {
+        ObjectProperties {
+            magic: 0.0,
+            light: 0.0,
+            constant_heat: None,
+            initial_heat: None,
+            conductivity: 1.0,
+            specific_heat: 1.0,
+            emissivity: 0.0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -147,6 +181,9 @@ impl IntoShapes for Vec<OffsetShape> {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ObjectDef {
+    /// The name of this definition in the objects file
+    #[serde(skip)]
+    pub name: String,
     #[serde(rename = "type")]
     pub ty: RigidBodyType,
     #[serde(default)]
@@ -159,6 +196,18 @@ pub struct ObjectDef {
     pub restitution: f32,
     #[serde(default)]
     pub props: ObjectProperties,
+    /// The changes of state the object goes through as it heats and cools
+    #[serde(default)]
+    pub phases: Vec<PhaseChange>,
+    /// Whether impacts can break the object
+    #[serde(default)]
+    pub breakable: bool,
+    /// The impulse from impacts the object can take before it breaks
+    #[serde(default)]
+    pub strength: f32,
+    /// The shapes the object breaks into, or its own shapes if there are none
+    #[serde(default)]
+    pub fragments: Vec<OffsetShape>,
 }
 
 fn default_restitution() -> f32 {
@@ -168,12 +217,17 @@ fn default_restitution() -> f32 {
 impl ObjectDef {
     pub fn new(ty: RigidBodyType) -> Self {
         ObjectDef {
+            name: String::new(),
             ty,
             shapes: Vec::new(),
             background: Vec::new(),
             far: Vec::new(),
             restitution: default_restitution(),
             props: ObjectProperties::default(),
+            phases: Vec::new(),
+            breakable: false,
+            strength: 0.0,
+            fragments: Vec::new(),
         }
     }
     pub fn shapes(self, shapes: impl IntoShapes) -> Self {
@@ -199,20 +253,60 @@ impl ObjectDef {
     }
 }
 
-pub static OBJECTS: Lazy<HashMap<String, ObjectDef>> = Lazy::new(|| {
-    let yaml = fs::read_to_string(resources_path().join("objects.yaml"));
+pub static OBJECTS: Lazy<HashMap<String, ObjectDef>> =
+    Lazy::new(|| load_objects().unwrap_or_else(|e| fatal_error(e)));
+
+/// Read and check the object definitions in `objects.yaml`
+pub fn load_objects() -> anyhow::Result<HashMap<String, ObjectDef>> {
+    let yaml = fs::read_to_string(find_resources_path()?.join("objects.yaml"));
     let yaml = yaml
         .as_deref()
         .unwrap_or(include_str!("../resources/objects.yaml"));
-    match serde_yaml::from_str::<HashMap<String, ObjectDef>>(yaml) {
-        Ok(objects) => objects,
-        Err(e) => fatal_error(format!("Unable to read objects file: {e}")),
+    let mut objects = serde_yaml::from_str::<HashMap<String, ObjectDef>>(yaml)
+        .map_err(|e| anyhow!("Unable to read objects file: {e}"))?;
+    for (name, def) in &mut objects {
+        def.name = name.clone();
     }
-});
+    for (name, def) in &objects {
+        let props = &def.props;
+        if props.conductivity < 0.0 || props.specific_heat <= 0.0 {
+            bail!("{name} needs a non-negative conductivity and a positive specific heat");
+        }
+        if (def
+            .shapes
+            .iter()
+            .chain(&def.background)
+            .chain(&def.fragments))
+        .any(|shape| shape.density <= 0.0)
+        {
+            bail!("The shapes of {name} need a positive density");
+        }
+        if def.breakable && def.strength <= 0.0 {
+            bail!("{name} is breakable but has no strength");
+        }
+        if !(0.0..=1.0).contains(&props.emissivity) {
+            bail!("The emissivity of {name} must be between 0 and 1");
+        }
+        for change in &def.phases {
+            if let Some(into) = &change.into {
+                if !objects.contains_key(into) {
+                    bail!("Unknown object {into} in {name} phases");
+                }
+            }
+            if change.above.is_some() == change.below.is_some() {
+                bail!("A phase change of {name} needs either `above` or `below`");
+            }
+        }
+    }
+    Ok(objects)
+}
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct PlacedObject {
     pub name: String,
+    /// A name for this instance that joints can refer to
+    #[serde(default)]
+    pub id: Option<String>,
     #[serde(deserialize_with = "pos2_as_array")]
     pub pos: Pos2,
     #[serde(default)]
@@ -230,9 +324,57 @@ pub struct Replication {
 #[derive(Debug, Clone, Deserialize)]
 pub struct Place {
     pub objects: Vec<PlacedObject>,
+    #[serde(default)]
+    pub joints: Vec<PlacedJoint>,
     pub bounds: Bounds,
 }
 
+/// A joint between two placed objects
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlacedJoint {
+    #[serde(flatten)]
+    pub kind: JointKind,
+    /// The id of the first object
+    pub a: String,
+    /// The id of the second object, or `ground`
+    pub b: String,
+    /// Where the joint holds the objects together
+    #[serde(deserialize_with = "pos2_as_array")]
+    pub anchor: Pos2,
+    /// The force above which the joint breaks
+    #[serde(default)]
+    pub break_force: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JointKind {
+    /// The objects turn around the anchor
+    Revolute {
+        /// The range of angles between the objects
+        #[serde(default)]
+        limits: Option<[f32; 2]>,
+    },
+    /// The objects slide along an axis through the anchor
+    Prismatic {
+        #[serde(deserialize_with = "vec2_as_array")]
+        axis: Vec2,
+        /// The range of distances along the axis
+        #[serde(default)]
+        limits: Option<[f32; 2]>,
+    },
+    /// The objects move as one
+    Fixed,
+    /// The anchor on the first object is kept within a distance of a point on the second
+    Rope {
+        #[serde(deserialize_with = "pos2_as_array")]
+        to: Pos2,
+        /// The length of the rope, which is the starting distance if not given
+        #[serde(default)]
+        length: Option<f32>,
+    },
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Bounds {
     pub top: f32,
@@ -249,9 +391,10 @@ fn default_bottom() -> f32 {
 pub static PLACES: Lazy<HashMap<String, Place>> =
     Lazy::new(|| load_places().unwrap_or_else(|e| fatal_error(e)));
 
-fn load_places() -> anyhow::Result<HashMap<String, Place>> {
+/// Read and check the places in the `places` directory
+pub fn load_places() -> anyhow::Result<HashMap<String, Place>> {
     let mut map = HashMap::new();
-    for entry in fs::read_dir(resources_path().join("places"))
+    for entry in fs::read_dir(find_resources_path()?.join("places"))
         .map_err(|e| anyhow!("Unable to open places directory: {e}"))?
     {
         let entry = entry.unwrap();
@@ -267,6 +410,7 @@ fn load_places() -> anyhow::Result<HashMap<String, Place>> {
                         bail!("Error in {name} place");
                     }
                 }
+                validate_joints(&name, &place)?;
                 map.insert(name, place);
             }
         }
@@ -274,6 +418,45 @@ fn load_places() -> anyhow::Result<HashMap<String, Place>> {
     Ok(map)
 }
 
+fn validate_joints(name: &str, place: &Place) -> anyhow::Result<()> {
+    let mut ids = HashSet::new();
+    for po in &place.objects {
+        let Some(id) = &po.id else {
+            continue;
+        };
+        if po.replication.is_some() {
+            bail!("In {name} place, replicated object {id} cannot have an id");
+        }
+        if id == "ground" || !ids.insert(id.as_str()) {
+            bail!("In {name} place, object id {id} is used more than once");
+        }
+    }
+    for joint in &place.joints {
+        for id in [&joint.a, &joint.b] {
+            if id != "ground" && !ids.contains(id.as_str()) {
+                bail!("In {name} place, a joint refers to unknown object {id}");
+            }
+        }
+        if joint.a == joint.b {
+            bail!("In {name} place, a joint connects {} to itself", joint.a);
+        }
+        if joint.break_force.is_some_and(|force| force <= 0.0) {
+            bail!("In {name} place, a joint's break force must be positive");
+        }
+        match &joint.kind {
+            JointKind::Prismatic { axis, .. } if *axis == Vec2::ZERO => {
+                bail!("In {name} place, a prismatic joint needs a nonzero axis")
+            }
+            JointKind::Rope {
+                length: Some(length),
+                ..
+            } if *length <= 0.0 => bail!("In {name} place, a rope's length must be positive"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 fn vec2_as_array<'de, D>(deserializer: D) -> Result<Vec2, D::Error>
 where
     D: Deserializer<'de>,
@@ -0,0 +1,112 @@
+use std::f32::consts::PI;
+
+use eframe::egui::*;
+use itertools::Itertools;
+
+use crate::{
+    field::ScalarOutputFieldKind,
+    math::{angle_diff, go_to},
+    object::{Object, ObjectKind},
+    world::World,
+};
+
+/// The anchoring at which an object's current state becomes its ordered state at once
+const SNAPSHOT_ANCHORING: f32 = 1.0;
+/// The order at which objects are restored without blocking each other
+pub const RESTORING_ORDER: f32 = 1.0;
+
+impl Object {
+    /// How far the object is from its ordered state, where a half turn counts as one meter
+    pub fn disorder(&self) -> f32 {
+        self.pr.pos.distance(self.ordered_pr.pos)
+            + angle_diff(self.pr.rot, self.ordered_pr.rot).abs() / PI
+    }
+}
+
+impl World {
+    /// Take the current state of the objects in a region as their ordered state
+    pub fn record_order(&mut self, center: Pos2, radius: f32) {
+        for obj in self.objects.values_mut() {
+            if obj.kind != ObjectKind::Ground && obj.pr.pos.distance(center) <= radius {
+                obj.ordered_pr = obj.pr;
+            }
+        }
+    }
+    /// Move the ordered state of anchored objects toward their current state
+    ///
+    /// Strong enough anchoring takes a snapshot of the current state instead.
+    pub fn run_anchoring(&mut self) {
+        let dt = self.physics.dt();
+        for handle in self.objects.keys().copied().collect_vec() {
+            let pos = self.objects[&handle].pr.pos;
+            let anchoring =
+                self.sample_output_scalar_field(ScalarOutputFieldKind::Anchor, pos, true);
+            let obj = self.objects.get_mut(&handle).unwrap();
+            if anchoring >= SNAPSHOT_ANCHORING {
+                obj.ordered_pr = obj.pr;
+            } else if anchoring > 0.0 {
+                let delta = anchoring * dt;
+                let ordered = &mut obj.ordered_pr;
+                ordered.pos.x = go_to(ordered.pos.x, obj.pr.pos.x, delta);
+                ordered.pos.y = go_to(ordered.pos.y, obj.pr.pos.y, delta);
+                ordered.rot += go_to(0.0, angle_diff(ordered.rot, obj.pr.rot), delta);
+            }
+        }
+    }
+}
+
+#[test]
+fn order_test() {
+    use crate::{field::ScalarField, person::ActiveSpell};
+    let mut world = World::test_place("arena");
+    for _ in 0..30 {
+        world.update();
+    }
+    let cubes = (world.objects_named("small_cube"))
+        .sorted_by(|(_, a), (_, b)| a.pr.pos.y.total_cmp(&b.pr.pos.y))
+        .map(|(handle, _)| handle)
+        .collect_vec();
+    let (low, high, floating) = (cubes[0], cubes[cubes.len() - 1], cubes[1]);
+    // Swap the ordered states of two cubes and ask for one below the ground
+    let (low_pr, high_pr) = (world.objects[&low].pr, world.objects[&high].pr);
+    world.objects[&low].ordered_pr = high_pr;
+    world.objects[&high].ordered_pr = low_pr;
+    world.objects[&high].ordered_pr.pos.y = -0.5;
+    assert!(world.objects[&low].disorder() > 1.0);
+    // Disorder is negative where an object should be
+    let in_air = pos2(-1.5, 3.0);
+    world.objects[&floating].ordered_pr.pos = in_air;
+    let disorder = |world: &World, pos| {
+        use crate::field::ScalarInputFieldKind;
+        world.sample_input_scalar_field(ScalarInputFieldKind::Disorder, pos, false)
+    };
+    assert!(disorder(&world, in_air) < 0.0);
+    // Restoring cubes pass through each other but not the ground
+    let spell = ActiveSpell {
+        field: ScalarField::Uniform(2.0),
+        words: Vec::new(),
+        cost: 0.0,
+    };
+    (world.player.person.active_spells.scalars).insert(ScalarOutputFieldKind::Order, vec![spell]);
+    for _ in 0..600 {
+        world.update();
+    }
+    assert!(world.objects[&low].disorder() < 0.1);
+    assert!(world.objects[&floating].disorder() < 0.1);
+    assert!(world.objects[&high].pr.pos.y > 0.1);
+    // Recording order makes the current state ordered
+    world.player.person.active_spells.scalars.clear();
+    world.record_order(world.objects[&high].pr.pos, 0.1);
+    assert!(world.objects[&high].disorder() < 1e-6);
+    assert!(disorder(&world, world.objects[&high].pr.pos) < 1e-6);
+    // Strong anchoring does the same wherever it reaches
+    world.objects[&low].ordered_pr.pos = in_air;
+    let spell = ActiveSpell {
+        field: ScalarField::Uniform(SNAPSHOT_ANCHORING),
+        words: Vec::new(),
+        cost: 0.0,
+    };
+    (world.player.person.active_spells.scalars).insert(ScalarOutputFieldKind::Anchor, vec![spell]);
+    world.update();
+    assert!(world.objects[&low].disorder() < 1e-6);
+}
@@ -1,15 +1,29 @@
-use std::{collections::HashMap, iter::empty};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    iter::empty,
+};
 
 use derive_more::From;
-use eframe::epaint::Pos2;
+use eframe::epaint::{Pos2, Vec2};
 use enum_iterator::Sequence;
+use serde::{Deserialize, Serialize};
 
-use crate::{conduit::ConduitRack, field::*, npc::NpcId, stack::Stack, word::Word};
+use crate::{
+    conduit::{ConduitRack, TriggerEvent, CONDUITS},
+    error::EidosError,
+    field::*,
+    history::StackHistory,
+    npc::NpcId,
+    stack::Stack,
+    word::Word,
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, From, Sequence)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, From, Sequence, Serialize, Deserialize)]
 pub enum PersonId {
     Player,
     Npc(NpcId),
+    /// The second player in a duel
+    Rival,
 }
 
 pub struct Person {
@@ -18,6 +32,8 @@ pub struct Person {
     pub stack: Stack,
     pub rack: ConduitRack,
     pub active_spells: ActiveSpells,
+    pub history: StackHistory,
+    pub controls: Controls,
 }
 
 impl Person {
@@ -26,51 +42,184 @@ impl Person {
             max_mana,
             target: None,
             stack: Stack::default(),
-            rack: ConduitRack::new(4),
+            rack: ConduitRack::new(CONDUITS.starting_stones),
             active_spells: ActiveSpells::default(),
+            history: StackHistory::default(),
+            controls: Controls::default(),
         }
     }
+    /// Change the stack and record the change so that it can be undone
+    pub fn change_stack<T>(&mut self, f: impl FnOnce(&mut Stack, &mut ActiveSpells) -> T) -> T {
+        let before = self.stack.clone();
+        let counts = self.active_spells.counts();
+        let res = f(&mut self.stack, &mut self.active_spells);
+        let cast = self.active_spells.cast_since(&counts);
+        self.history.record(before, self.stack.clone(), cast);
+        res
+    }
+    pub fn undo(&mut self) -> bool {
+        self.history.undo(&mut self.stack, &mut self.active_spells)
+    }
+    /// Redo the last undone change if there is enough mana for it
+    pub fn redo(&mut self) -> bool {
+        if !self.history.redo(&mut self.stack, &mut self.active_spells) {
+            return false;
+        }
+        if self.capped_mana() < 0.0 {
+            self.history.undo(&mut self.stack, &mut self.active_spells);
+            return false;
+        }
+        true
+    }
     pub fn reserved_mana(&self) -> f32 {
         let from_scalars: f32 = self
             .active_spells
             .scalars
             .values()
             .flatten()
-            .flat_map(|spell| &spell.words)
-            .map(|word| word.cost())
+            .map(|spell| spell.cost)
             .sum();
         let from_vectors: f32 = self
             .active_spells
             .vectors
             .values()
             .flatten()
-            .flat_map(|spell| &spell.words)
-            .map(|word| word.cost())
+            .map(|spell| spell.cost)
             .sum();
-        let from_stack: f32 = self
-            .stack
+        let from_stack: f32 = self.stack.iter().map(|item| item.cost).sum();
+        let from_rack: f32 = self
+            .rack
+            .conduits
             .iter()
-            .flat_map(|item| &item.words)
-            .map(|word| word.cost())
+            .map(|stone| stone.reserved_mana())
             .sum();
-        from_scalars + from_vectors + from_stack
+        from_scalars + from_vectors + from_stack + from_rack
     }
     pub fn capped_mana(&self) -> f32 {
         self.max_mana - self.reserved_mana()
     }
+    /// Check that a conduit stone can hold a number of words and that there is
+    /// enough mana to etch them, counting the mana its current words and, if they
+    /// are being etched, the stack's words give back
+    pub fn check_etch(
+        &self,
+        stone: usize,
+        word_count: usize,
+        from_stack: bool,
+    ) -> Result<(), EidosError> {
+        let conduit = (self.rack.conduits.get(stone)).ok_or(EidosError::UnknownConduit(stone))?;
+        if word_count > conduit.capacity() {
+            return Err(EidosError::ConduitCapacity {
+                stone,
+                capacity: conduit.capacity(),
+                words: word_count,
+            });
+        }
+        let freed = if from_stack {
+            self.stack.iter().map(|item| item.cost).sum()
+        } else {
+            0.0
+        };
+        let needed = conduit.etch_cost(word_count);
+        let available = self.capped_mana() + conduit.reserved_mana() + freed;
+        if available < needed {
+            return Err(EidosError::NotEnoughMana { needed, available });
+        }
+        Ok(())
+    }
+    /// Get the controls used by the stack, active spells and conduit triggers
+    pub fn used_controls(&self) -> BTreeSet<ControlKind> {
+        let stack_controls = self.stack.iter().flat_map(|item| item.field.controls());
+        let scalar_output_controls = self
+            .active_spells
+            .scalars
+            .values()
+            .flatten()
+            .flat_map(|spell| spell.field.controls());
+        let vector_output_controls = self
+            .active_spells
+            .vectors
+            .values()
+            .flatten()
+            .flat_map(|spell| spell.field.controls());
+        let trigger_controls =
+            self.rack
+                .conduits
+                .iter()
+                .filter_map(|stone| match stone.trigger.as_ref()?.event {
+                    TriggerEvent::Activation(kind) => Some(kind),
+                    _ => None,
+                });
+        stack_controls
+            .chain(scalar_output_controls)
+            .chain(vector_output_controls)
+            .chain(trigger_controls)
+            .collect()
+    }
+}
+
+/// The values of the controls a person is using
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Controls {
+    values: BTreeMap<ControlKind, ControlValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ControlValue {
+    Scalar(f32),
+    Vector(Vec2),
+}
+
+impl Controls {
+    pub fn get(&self, kind: ControlKind) -> f32 {
+        match self.values.get(&kind) {
+            Some(ControlValue::Scalar(v)) => *v,
+            _ => 0.0,
+        }
+    }
+    pub fn get_vector(&self, kind: ControlKind) -> Vec2 {
+        match self.values.get(&kind) {
+            Some(ControlValue::Vector(v)) => *v,
+            _ => Vec2::ZERO,
+        }
+    }
+    pub fn set(&mut self, kind: ControlKind, value: f32) {
+        self.values.insert(kind, ControlValue::Scalar(value));
+    }
+    pub fn set_vector(&mut self, kind: ControlKind, value: Vec2) {
+        self.values.insert(kind, ControlValue::Vector(value));
+    }
+    /// Get a scalar control's value, adding it if it is not in use
+    pub fn scalar_mut(&mut self, kind: ControlKind) -> &mut f32 {
+        let value = self.values.entry(kind).or_insert(ControlValue::Scalar(0.0));
+        if let ControlValue::Vector(_) = value {
+            *value = ControlValue::Scalar(0.0);
+        }
+        match value {
+            ControlValue::Scalar(v) => v,
+            ControlValue::Vector(_) => unreachable!(),
+        }
+    }
+    /// Reset the controls that are not in the given set
+    pub fn retain(&mut self, used: &BTreeSet<ControlKind>) {
+        self.values.retain(|kind, _| used.contains(kind));
+    }
 }
 
 type TypedActiveSpells<K, V> = HashMap<K, Vec<ActiveSpell<V>>>;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ActiveSpells {
     pub scalars: TypedActiveSpells<ScalarOutputFieldKind, ScalarField>,
     pub vectors: TypedActiveSpells<VectorOutputFieldKind, VectorField>,
 }
 
+#[derive(Clone)]
 pub struct ActiveSpell<T> {
     pub field: T,
     pub words: Vec<Word>,
+    /// The mana this spell reserves
+    pub cost: f32,
 }
 
 impl ActiveSpells {
@@ -80,14 +229,18 @@ impl ActiveSpells {
             OutputFieldKind::Vector(kind) => self.vectors.contains_key(&kind),
         }
     }
-    pub fn remove(&mut self, kind: OutputFieldKind, i: usize) {
-        match kind {
-            OutputFieldKind::Scalar(kind) => {
-                self.scalars.entry(kind).or_default().remove(i);
-            }
-            OutputFieldKind::Vector(kind) => {
-                self.vectors.entry(kind).or_default().remove(i);
+    /// Remove an active spell, returning whether it existed
+    pub fn remove(&mut self, kind: OutputFieldKind, i: usize) -> bool {
+        fn remove_from<T>(spells: &mut Vec<T>, i: usize) -> bool {
+            let exists = i < spells.len();
+            if exists {
+                spells.remove(i);
             }
+            exists
+        }
+        match kind {
+            OutputFieldKind::Scalar(kind) => remove_from(self.scalars.entry(kind).or_default(), i),
+            OutputFieldKind::Vector(kind) => remove_from(self.vectors.entry(kind).or_default(), i),
         }
     }
     /// Get an iterator over all the words of all the active spells of a given kind.
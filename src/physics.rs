@@ -2,12 +2,18 @@ use std::panic::{catch_unwind, AssertUnwindSafe};
 
 use eframe::epaint::{Pos2, Vec2};
 use itertools::Itertools;
-use rapier2d::{na::Unit, prelude::*};
+use rapier2d::{
+    crossbeam::channel::{unbounded, Receiver},
+    na::Unit,
+    prelude::*,
+};
 
 use crate::{
     field::*,
+    fluid::DRAG_COEFFICIENT,
     math::{angle_diff, Convert},
     object::*,
+    order::RESTORING_ORDER,
     world::{World, ABSOLUTE_ZERO, AIR_DENSITY_AT_GROUND_TEMP, GROUND_TEMP},
 };
 
@@ -20,14 +26,36 @@ pub struct PhysicsContext {
     narrow_phase: NarrowPhase,
     pub bodies: RigidBodySet,
     pub colliders: ColliderSet,
-    impulse_joints: ImpulseJointSet,
+    pub impulse_joints: ImpulseJointSet,
     multibody_joints: MultibodyJointSet,
     ccd_solver: CCDSolver,
     pub queries: QueryPipeline,
+    event_collector: ChannelEventCollector,
+    collision_events: Receiver<CollisionEvent>,
+    contact_force_events: Receiver<ContactForceEvent>,
+    /// The collisions that started or stopped in the last step
+    pub collisions: Vec<CollisionEvent>,
+    /// The contacts that pushed harder than their colliders' thresholds in the last step
+    pub impacts: Vec<Impact>,
+}
+
+/// A hard contact between two colliders
+#[derive(Debug, Clone, Copy)]
+pub struct Impact {
+    pub collider1: ColliderHandle,
+    pub collider2: ColliderHandle,
+    /// The average position of the contact points
+    pub pos: Pos2,
+    /// The total force between the colliders
+    pub force: f32,
+    /// The total impulse between the colliders over the step
+    pub impulse: f32,
 }
 
 impl Default for PhysicsContext {
     fn default() -> Self {
+        let (collision_send, collision_events) = unbounded();
+        let (contact_force_send, contact_force_events) = unbounded();
         PhysicsContext {
             pipline: PhysicsPipeline::default(),
             gravity: vector!(0.0, 0.0),
@@ -41,6 +69,11 @@ impl Default for PhysicsContext {
             multibody_joints: MultibodyJointSet::default(),
             ccd_solver: CCDSolver::default(),
             queries: QueryPipeline::default(),
+            event_collector: ChannelEventCollector::new(collision_send, contact_force_send),
+            collision_events,
+            contact_force_events,
+            collisions: Vec::new(),
+            impacts: Vec::new(),
         }
     }
 }
@@ -59,10 +92,38 @@ impl PhysicsContext {
             &mut self.multibody_joints,
             &mut self.ccd_solver,
             &(),
-            &(),
+            &self.event_collector,
         );
         self.queries
             .update(&self.islands, &self.bodies, &self.colliders);
+        self.collisions = self.collision_events.try_iter().collect();
+        let dt = self.dt();
+        self.impacts = (self.contact_force_events.try_iter())
+            .map(|event| {
+                let points = (self.narrow_phase)
+                    .contact_pair(event.collider1, event.collider2)
+                    .into_iter()
+                    .flat_map(|pair| &pair.manifolds)
+                    .flat_map(|manifold| &manifold.data.solver_contacts)
+                    .map(|contact| contact.point.convert())
+                    .collect_vec();
+                let pos = if points.is_empty() {
+                    self.colliders[event.collider1].translation().convert()
+                } else {
+                    let sum = points
+                        .iter()
+                        .fold(Vec2::ZERO, |sum, p: &Pos2| sum + p.to_vec2());
+                    (sum / points.len() as f32).to_pos2()
+                };
+                Impact {
+                    collider1: event.collider1,
+                    collider2: event.collider2,
+                    pos,
+                    force: event.total_force_magnitude,
+                    impulse: event.total_force_magnitude * dt,
+                }
+            })
+            .collect();
     }
     pub fn dt(&self) -> f32 {
         self.integration_parameters.dt
@@ -99,6 +160,7 @@ impl World {
                 self.sample_output_vector_field(VectorOutputFieldKind::Force, pos, true);
             let order = self.sample_output_scalar_field(ScalarOutputFieldKind::Order, pos, true);
             let temp = self.temperature_at(pos);
+            let wind = self.wind_at(pos);
             let obj = &self.objects[&handle];
             let order_pos_diff = obj.ordered_pr.pos - obj.pr.pos;
             let body = &mut self.physics.bodies[handle];
@@ -109,8 +171,8 @@ impl World {
             } else {
                 Vec2::ZERO
             };
-            let (total_force, sensor) = if order_force.length() > 0.0 {
-                (order_force, order >= 1.0)
+            let (total_force, restoring) = if order_force.length() > 0.0 {
+                (order_force, order >= RESTORING_ORDER)
             } else {
                 let gravity_force = gravity_acc * body.mass();
                 let volume: f32 = body
@@ -118,12 +180,22 @@ impl World {
                     .iter()
                     .map(|&handle| self.physics.colliders[handle].volume())
                     .sum();
-                let buoyant_force = -air_density_at_temp(temp) * volume * gravity_acc;
-                (field_force + gravity_force + buoyant_force, false)
+                let air_density = air_density_at_temp(temp);
+                let buoyant_force = -air_density * volume * gravity_acc;
+                // Drag grows with the square of the speed through the air
+                let rel_vel = wind - obj.vel;
+                let width = 2.0 * obj.def.bounding_radius();
+                let drag_force =
+                    0.5 * DRAG_COEFFICIENT * air_density * width * rel_vel.length() * rel_vel;
+                (
+                    field_force + gravity_force + buoyant_force + drag_force,
+                    false,
+                )
             };
-            for &collider_handle in body.colliders() {
+            // Restoring objects only make way for each other
+            for &collider_handle in &obj.foreground_handles {
                 let collider = self.physics.colliders.get_mut(collider_handle).unwrap();
-                collider.set_sensor(sensor);
+                collider.set_collision_groups(object_groups(restoring));
             }
             body.reset_forces(true);
             body.add_force(total_force.convert(), true);
@@ -134,6 +206,7 @@ impl World {
                 body.add_torque(order_torque, true);
             }
         }
+        self.pull_ropes();
         // Step physics
         self.physics.step();
         // Set object positions from physics system
@@ -166,18 +239,31 @@ fn graphical_shape_to_shared(shape: &GraphicalShape) -> SharedShape {
     }
 }
 
+/// The acceleration a contact must give a collider to count as an impact
+const IMPACT_ACCELERATION: f32 = 100.0;
+
 const OBJECT: Group = Group::GROUP_1;
 const BACKGROUND: Group = Group::GROUP_2;
 const GROUND: Group = Group::GROUP_3;
+/// Objects being restored to their ordered state
+const RESTORING: Group = Group::GROUP_4;
+
+fn object_groups(restoring: bool) -> InteractionGroups {
+    if restoring {
+        InteractionGroups::new(RESTORING, OBJECT | GROUND)
+    } else {
+        InteractionGroups::new(OBJECT, OBJECT | GROUND | RESTORING)
+    }
+}
 
 impl World {
-    pub fn add_object_def(&mut self, pos: Pos2, def: ObjectDef) {
+    pub fn add_object_def(&mut self, pos: Pos2, def: ObjectDef) -> RigidBodyHandle {
         self.add_object(
             ObjectKind::Object,
             def,
             |rb| rb.translation(pos.convert()),
             |c| c,
-        );
+        )
     }
     pub fn add_object(
         &mut self,
@@ -196,19 +282,29 @@ impl World {
         let body_handle = self.physics.bodies.insert(body);
         // Create colliders
         let foreground_groups = match kind {
-            ObjectKind::Object => InteractionGroups::new(OBJECT, OBJECT | GROUND),
-            ObjectKind::Ground => InteractionGroups::new(GROUND, OBJECT | BACKGROUND),
+            ObjectKind::Object | ObjectKind::Debris => object_groups(false),
+            ObjectKind::Ground => InteractionGroups::new(GROUND, OBJECT | RESTORING | BACKGROUND),
         };
         let mut foreground_handles = Vec::new();
         let mut background_handles = Vec::new();
         // Foreground colliders
         for offset_shape in &def.shapes {
             let shared_shape = graphical_shape_to_shared(&offset_shape.shape);
-            let collider = build_collider(ColliderBuilder::new(shared_shape))
+            let mut collider = build_collider(ColliderBuilder::new(shared_shape))
                 .translation(offset_shape.offset.convert())
                 .density(offset_shape.density)
                 .collision_groups(foreground_groups)
                 .build();
+            if kind != ObjectKind::Ground {
+                let mut threshold = IMPACT_ACCELERATION * collider.mass();
+                if def.breakable {
+                    threshold = threshold.min(def.impact_threshold());
+                }
+                collider.set_active_events(
+                    ActiveEvents::COLLISION_EVENTS | ActiveEvents::CONTACT_FORCE_EVENTS,
+                );
+                collider.set_contact_force_event_threshold(threshold);
+            }
             foreground_handles.push(self.physics.colliders.insert_with_parent(
                 collider,
                 body_handle,
@@ -233,7 +329,12 @@ impl World {
         let transform = PosRot { pos, rot };
         let object = Object {
             kind,
-            heat: def.props.constant_heat.unwrap_or(GROUND_TEMP),
+            heat: (def.props.constant_heat)
+                .or(def.props.initial_heat)
+                .unwrap_or(GROUND_TEMP),
+            phase_progress: 0.0,
+            phase_index: 0,
+            damage: 0.0,
             def,
             pr: transform,
             ordered_pr: transform,
@@ -1,12 +1,15 @@
 use std::collections::HashSet;
 
-use crate::{field::InputFieldKind, person::Person, word::Word};
+use crate::{
+    conduit::CONDUITS, field::InputFieldKind, person::Person, transcript::Transcript, word::Word,
+};
 
 pub struct Player {
     pub person: Person,
     pub progression: Progression,
     pub name: String,
     pub gender: Gender,
+    pub transcript: Transcript,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,6 +25,8 @@ pub struct Progression {
     pub mana_bar: bool,
     pub free: bool,
     pub conduit: bool,
+    /// The number of stones in the conduit rack
+    pub conduit_stones: usize,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -33,6 +38,7 @@ impl Default for Progression {
             mana_bar: false,
             free: false,
             conduit: false,
+            conduit_stones: CONDUITS.starting_stones,
         }
     }
 }
@@ -44,6 +50,7 @@ impl Player {
             progression: Progression::default(),
             name,
             gender,
+            transcript: Transcript::default(),
         }
     }
 }
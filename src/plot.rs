@@ -15,11 +15,15 @@ use rayon::prelude::*;
 
 use crate::{
     color::Color,
-    math::{approach_one, round_to},
+    math::{approach_one, rotate, round_to},
+    object::{GraphicalShape, JointKind, ObjectKind},
     texture::textures,
     world::World,
 };
 
+/// The disorder above which an object's ordered state is drawn
+const GHOST_MIN_DISORDER: f32 = 0.05;
+
 pub struct FieldPlot<'w> {
     world: &'w World,
     world_center: Pos2,
@@ -45,6 +49,10 @@ pub trait FieldPlottable: Sync {
     fn wiggle_delta(&self, point_radius: f32) -> f32 {
         wiggle_delta(point_radius, self.precision())
     }
+    /// Whether to draw where objects would be in their ordered state
+    fn shows_order(&self) -> bool {
+        false
+    }
 }
 
 pub trait Plottable: Sized + Send {
@@ -126,6 +134,11 @@ impl<'w> FieldPlot<'w> {
         // Plot data
         let data = self.get_data(field_plot);
         F::Value::plot(ui, rect, field_plot, data);
+        self.plot_joints(ui, rect);
+        self.plot_rival_target(ui, rect);
+        if field_plot.shows_order() {
+            self.plot_ordered_ghosts(ui, rect);
+        }
         // Handle hovering
         let mut hovered_pos = None;
         if let Some(hpos) = response.hover_pos() {
@@ -170,6 +183,119 @@ impl<'w> FieldPlot<'w> {
             hovered_pos,
         }
     }
+    fn to_screen(&self, rect: Rect, pos: Pos2) -> Pos2 {
+        let world_tl = self.world_center + vec2(-self.world_range, self.world_range);
+        let ratio = rect.width() / (self.world_range * 2.0);
+        let rel_pos = pos - world_tl;
+        rect.left_top() + vec2(rel_pos.x, -rel_pos.y) * ratio
+    }
+    /// The alpha of things drawn at a position, which fades toward the edge of the plot
+    fn alpha_at(&self, pos: Pos2) -> f32 {
+        self.global_alpha
+            * (1.0
+                - (pos.distance(self.world_center) / self.world_range)
+                    .powf(2.0)
+                    .clamp(0.0, 1.0))
+    }
+    /// Draw the joints between objects over the field
+    fn plot_joints(&self, ui: &Ui, rect: Rect) {
+        let painter = ui.painter();
+        let to_screen = |pos| self.to_screen(rect, pos);
+        let radius = rect.width() / 80.0;
+        for joint in &self.world.joints {
+            let Some((a, b)) = self.world.joint_anchors(joint) else {
+                continue;
+            };
+            let alpha = self.alpha_at(a);
+            // Joints redden as they near their breaking point
+            let strain = (joint.break_force)
+                .map(|max| (joint.force / max).min(1.0))
+                .unwrap_or(0.0);
+            let color = Color::rgb(1.0, 1.0 - strain, 1.0 - strain).mul_a(alpha);
+            let stroke = Stroke::new(radius * 0.4, color);
+            match joint.kind {
+                JointKind::Revolute { .. } => {
+                    painter.circle_stroke(to_screen(a), radius, stroke);
+                }
+                JointKind::Prismatic { axis, .. } => {
+                    let rot = self.world.physics.bodies[joint.body1].rotation().angle();
+                    let axis = rotate(axis.normalized(), rot) * 0.5;
+                    painter.line_segment([to_screen(a - axis), to_screen(a + axis)], stroke);
+                    painter.circle_filled(to_screen(b), radius * 0.5, color);
+                }
+                JointKind::Fixed => {
+                    painter.circle_filled(to_screen(a), radius, color);
+                }
+                JointKind::Rope { .. } => {
+                    painter.line_segment([to_screen(a), to_screen(b)], stroke);
+                    painter.circle_filled(to_screen(a), radius * 0.5, color);
+                    painter.circle_filled(to_screen(b), radius * 0.5, color);
+                }
+            }
+        }
+    }
+    /// Draw a cross where the rival is aiming
+    fn plot_rival_target(&self, ui: &Ui, rect: Rect) {
+        let Some(target) = self.world.rival.as_ref().and_then(|rival| rival.target) else {
+            return;
+        };
+        let center = self.to_screen(rect, target);
+        let size = rect.width() / 60.0;
+        let color = Color::rgb(1.0, 0.6, 0.2).mul_a(self.alpha_at(target));
+        let stroke = Stroke::new(size * 0.3, color);
+        let painter = ui.painter();
+        for offset in [vec2(size, 0.0), vec2(0.0, size)] {
+            painter.line_segment([center - offset, center + offset], stroke);
+        }
+    }
+    /// Draw outlines of disordered objects where they would be in their ordered state
+    fn plot_ordered_ghosts(&self, ui: &Ui, rect: Rect) {
+        let painter = ui.painter();
+        let ratio = rect.width() / (self.world_range * 2.0);
+        for obj in self.world.objects.values() {
+            if obj.kind == ObjectKind::Ground || obj.disorder() < GHOST_MIN_DISORDER {
+                continue;
+            }
+            let ordered = obj.ordered_pr;
+            let alpha = self.alpha_at(ordered.pos);
+            let color = Color::rgb(0.6, 0.8, 1.0).mul_a(alpha * 0.8);
+            let stroke = Stroke::new(rect.width() / 250.0, color);
+            let to_screen =
+                |offset: Vec2| self.to_screen(rect, ordered.pos + rotate(offset, ordered.rot));
+            for offset_shape in &obj.def.shapes {
+                let offset = offset_shape.offset;
+                let outline = match offset_shape.shape {
+                    GraphicalShape::Circle(radius) => {
+                        painter.circle_stroke(to_screen(offset), radius * ratio, stroke);
+                        continue;
+                    }
+                    GraphicalShape::Box(size) => {
+                        let half = size / 2.0;
+                        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                            .map(|(x, y)| to_screen(offset + vec2(x * half.x, y * half.y)))
+                            .to_vec()
+                    }
+                    GraphicalShape::Capsule {
+                        half_height,
+                        radius,
+                    } => {
+                        let cap = |center: f32, from: f32| {
+                            (0..=8).map(move |i| {
+                                let angle = from + PI * i as f32 / 8.0;
+                                vec2(angle.cos(), angle.sin()) * radius + vec2(0.0, center)
+                            })
+                        };
+                        cap(half_height, 0.0)
+                            .chain(cap(-half_height, PI))
+                            .map(|point| to_screen(offset + point))
+                            .collect()
+                    }
+                    GraphicalShape::HalfSpace(_) => continue,
+                };
+                painter.add(Shape::closed_line(outline, stroke));
+            }
+        }
+    }
     pub fn show_number(ui: &mut Ui, size: f32, global_alpha: f32, n: f32) -> PlotResponse {
         let rect = Rect::from_min_size(ui.cursor().left_top(), Vec2::splat(size));
         let response = ui.allocate_rect(rect, Sense::drag());
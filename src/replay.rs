@@ -0,0 +1,357 @@
+use std::{collections::HashMap, env::current_dir, fs, path::PathBuf};
+
+use anyhow::bail;
+use eframe::egui::*;
+use enum_iterator::all;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conduit::{ConduitTrigger, TriggerEvent},
+    error::EidosError,
+    field::{InputFieldKind, OutputFieldKind},
+    game::Game,
+    locale::tr,
+    object::PLACES,
+    person::{Controls, PersonId},
+    player::{Gender, Player},
+    stack::Stack,
+    word::Word,
+    world::World,
+};
+
+/// Something a person does that changes the simulation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    Say(Word),
+    /// Remove the top of the stack without saying anything
+    RemoveTop,
+    /// Clear the stack
+    Free,
+    Undo,
+    Redo,
+    /// Dispel one of the active spells of a kind
+    Dispel(OutputFieldKind, usize),
+    /// Apply a conduit stone to the stack
+    RunConduit(usize),
+    /// Etch the words on the stack into a conduit stone
+    EtchStack(usize),
+    /// Etch words and a trigger into a conduit stone
+    Etch {
+        stone: usize,
+        words: Vec<Word>,
+        trigger: Option<TriggerEvent>,
+    },
+    /// Add stones to the conduit rack until it has the given size
+    GrowRack(usize),
+    Controls(Controls),
+    Target(Option<Pos2>),
+    /// Take the current state of the objects in a circle as their ordered state
+    RecordOrder {
+        center: Pos2,
+        radius: f32,
+    },
+}
+
+/// A command given by a person on a tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayEvent {
+    pub tick: u64,
+    pub person: PersonId,
+    pub command: Command,
+}
+
+/// A recording of everything people did since a place was loaded
+///
+/// Replaying the commands on the same ticks reproduces the simulation exactly.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub place: String,
+    /// The maximum mana of each person when the place was loaded
+    pub people: Vec<(PersonId, f32)>,
+    /// The number of ticks recorded
+    pub ticks: u64,
+    pub events: Vec<ReplayEvent>,
+}
+
+/// Records the commands given in a world
+#[derive(Default)]
+pub struct Recorder {
+    pub replay: Replay,
+    /// The last recorded controls and target of each person
+    inputs: HashMap<PersonId, (Controls, Option<Pos2>)>,
+}
+
+impl Recorder {
+    pub fn new(place: &str, people: Vec<(PersonId, f32)>) -> Self {
+        Recorder {
+            replay: Replay {
+                place: place.into(),
+                people,
+                ..Default::default()
+            },
+            inputs: HashMap::new(),
+        }
+    }
+    fn record(&mut self, tick: u64, person: PersonId, command: Command) {
+        let inputs = self.inputs.entry(person).or_default();
+        match &command {
+            Command::Controls(controls) => inputs.0 = controls.clone(),
+            Command::Target(target) => inputs.1 = *target,
+            _ => {}
+        }
+        self.replay.events.push(ReplayEvent {
+            tick,
+            person,
+            command,
+        });
+    }
+}
+
+impl Replay {
+    /// Write the replay to a file in the working directory
+    pub fn export(&self) -> anyhow::Result<PathBuf> {
+        let path = current_dir()?.join("replay.yaml");
+        fs::write(&path, serde_yaml::to_string(self)?)?;
+        Ok(path)
+    }
+    /// Read the replay written by [`Replay::export`]
+    pub fn import() -> anyhow::Result<Self> {
+        let yaml = fs::read_to_string(current_dir()?.join("replay.yaml"))?;
+        Ok(serde_yaml::from_str(&yaml)?)
+    }
+}
+
+/// Plays a replay back in a world
+pub struct Playback {
+    pub replay: Replay,
+    next_event: usize,
+    /// The events that failed so far along with their errors
+    pub failures: Vec<(ReplayEvent, EidosError)>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback {
+            replay,
+            next_event: 0,
+            failures: Vec::new(),
+        }
+    }
+    pub fn finished(&self, world: &World) -> bool {
+        world.tick >= self.replay.ticks
+    }
+    /// Give the commands recorded for the world's current tick and return
+    /// the ones that failed along with their errors
+    pub fn apply(&mut self, world: &mut World) -> Vec<(ReplayEvent, EidosError)> {
+        let mut failed = Vec::new();
+        while let Some(event) = self.replay.events.get(self.next_event) {
+            if event.tick > world.tick {
+                break;
+            }
+            if let Err(e) = world.command(event.person, event.command.clone()) {
+                failed.push((event.clone(), e));
+            }
+            self.next_event += 1;
+        }
+        failed
+    }
+}
+
+impl World {
+    /// Create the world a replay starts in
+    pub fn from_replay(replay: &Replay) -> anyhow::Result<Self> {
+        if !PLACES.contains_key(&replay.place) {
+            bail!("Unknown place {}", replay.place);
+        }
+        let mut world = World::empty(Player::new(String::new(), Gender::Enby));
+        for &(person_id, max_mana) in &replay.people {
+            world.add_person(person_id, max_mana);
+        }
+        if let Some(event) =
+            (replay.events.iter()).find(|event| world.try_person(event.person).is_none())
+        {
+            bail!(
+                "Tick {}: {}",
+                event.tick,
+                EidosError::UnknownPerson(event.person)
+            );
+        }
+        world.load_place(&replay.place);
+        Ok(world)
+    }
+    /// The recording of everything done since the place was loaded
+    pub fn replay(&self) -> Replay {
+        Replay {
+            ticks: self.tick,
+            ..self.recorder.replay.clone()
+        }
+    }
+    /// Have a person give a command, recording it for replay
+    pub fn command(&mut self, person_id: PersonId, command: Command) -> Result<(), EidosError> {
+        let person = self
+            .try_person_mut(person_id)
+            .ok_or(EidosError::UnknownPerson(person_id))?;
+        match &command {
+            Command::Say(word) => person.change_stack(|stack, active_spells| {
+                stack.say(person_id, *word, Some(active_spells))
+            })?,
+            Command::RemoveTop => {
+                person.change_stack(|stack, _| stack.remove_top());
+            }
+            Command::Free => person.change_stack(|stack, _| stack.clear()),
+            Command::Undo => {
+                person.undo();
+            }
+            Command::Redo => {
+                person.redo();
+            }
+            Command::Dispel(kind, i) => {
+                if !person.active_spells.remove(*kind, *i) {
+                    return Err(EidosError::UnknownSpell {
+                        kind: *kind,
+                        index: *i,
+                    });
+                }
+            }
+            Command::RunConduit(i) => {
+                let stone = (person.rack.conduits.get(*i)).ok_or(EidosError::UnknownConduit(*i))?;
+                let mut stack = person.stack.clone();
+                stack.apply_conduit(person_id, &stone.words, stone.material(), None)?;
+                let before = std::mem::replace(&mut person.stack, stack.clone());
+                person.history.record(before, stack, Vec::new());
+            }
+            Command::EtchStack(i) => {
+                person.check_etch(*i, person.stack.words().count(), true)?;
+                let words = person.stack.words();
+                person.rack.conduits[*i].etch(words);
+                let before = std::mem::take(&mut person.stack);
+                person.history.record(before, Stack::default(), Vec::new());
+            }
+            Command::Etch {
+                stone,
+                words,
+                trigger,
+            } => {
+                person.check_etch(*stone, words.len(), false)?;
+                let stone = &mut person.rack.conduits[*stone];
+                stone.etch(words.iter().copied());
+                if stone.trigger.as_ref().map(|trigger| trigger.event) != *trigger {
+                    stone.trigger = trigger.map(ConduitTrigger::new);
+                }
+            }
+            Command::GrowRack(size) => person.rack.grow(*size),
+            Command::Controls(controls) => person.controls = controls.clone(),
+            Command::Target(target) => person.target = *target,
+            Command::RecordOrder { .. } => {}
+        }
+        if let Command::RecordOrder { center, radius } = command {
+            self.record_order(center, radius);
+        }
+        self.recorder.record(self.tick, person_id, command);
+        Ok(())
+    }
+    /// Record any controls and targets that changed since they were last recorded
+    pub fn record_inputs(&mut self) {
+        for person_id in self.person_ids() {
+            let person = self.person(person_id);
+            let (controls, target) = (person.controls.clone(), person.target);
+            let recorded = self.recorder.inputs.get(&person_id);
+            if recorded.map(|(c, _)| c) != Some(&controls) {
+                self.recorder
+                    .record(self.tick, person_id, Command::Controls(controls));
+            }
+            let recorded = self.recorder.inputs.get(&person_id);
+            if recorded.map(|(_, t)| t) != Some(&target) {
+                self.recorder
+                    .record(self.tick, person_id, Command::Target(target));
+            }
+        }
+    }
+}
+
+impl Game {
+    /// Watch a replay
+    pub fn from_replay(replay: Replay) -> anyhow::Result<Self> {
+        let mut world = World::from_replay(&replay)?;
+        let progression = &mut world.player.progression;
+        progression.known_fields = all::<InputFieldKind>().collect();
+        progression.mana_bar = true;
+        let mut game = Game::from_world(world);
+        game.playback = Some(Playback::new(replay));
+        Ok(game)
+    }
+    /// Show the progress of the replay being watched
+    pub fn replay_ui(&mut self, ui: &mut Ui) {
+        let Some(playback) = &self.playback else {
+            return;
+        };
+        let ticks = playback.replay.ticks;
+        ui.horizontal(|ui| {
+            ui.heading(tr("Replay"));
+            ProgressBar::new(self.world.tick as f32 / ticks.max(1) as f32)
+                .text(format!("{} / {ticks}", self.world.tick))
+                .desired_width(300.0)
+                .ui(ui);
+            if playback.finished(&self.world) {
+                ui.label(tr("Finished"));
+            }
+        });
+        for (event, e) in &playback.failures {
+            let text = format!("{} {}: {:?}: {e}", tr("Tick"), event.tick, event.command);
+            ui.colored_label(ui.visuals().error_fg_color, text);
+        }
+    }
+}
+
+#[test]
+fn replay_test() {
+    use crate::field::{ControlKind, ScalarOutputFieldKind};
+    let mut world = World::new(Player::new("Kai".into(), Gender::Male));
+    // Push things up with the vertical slider and heat them
+    for word in [Word::Ki, Word::Li, Word::Sa, Word::Wu, Word::Te, Word::Lo] {
+        world.command(PersonId::Player, Command::Say(word)).unwrap();
+    }
+    for tick in 0..60 {
+        let controls = &mut world.player.person.controls;
+        controls.set(ControlKind::YSlider, (tick % 20) as f32 / 20.0);
+        world.update();
+    }
+    world.command(PersonId::Player, Command::Undo).unwrap();
+    for _ in 0..30 {
+        world.update();
+    }
+    let replay: Replay =
+        serde_yaml::from_str(&serde_yaml::to_string(&world.replay()).unwrap()).unwrap();
+    assert_eq!(replay, world.replay());
+    let mut replayed = World::from_replay(&replay).unwrap();
+    let mut playback = Playback::new(replay);
+    while !playback.finished(&replayed) {
+        playback.apply(&mut replayed);
+        replayed.update();
+    }
+    assert_eq!(replayed.replay(), world.replay());
+    let positions = |world: &World| {
+        (world.objects.values())
+            .map(|obj| (obj.pr.pos, obj.pr.rot, obj.heat))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(positions(&replayed), positions(&world));
+    assert_eq!(replayed.heat_grid, world.heat_grid);
+    // Bad commands and replays are errors rather than panics
+    for (person, command) in [
+        (PersonId::Player, Command::RunConduit(99)),
+        (PersonId::Player, Command::EtchStack(99)),
+        (
+            PersonId::Player,
+            Command::Dispel(ScalarOutputFieldKind::Heat.into(), 99),
+        ),
+        (PersonId::Rival, Command::Free),
+    ] {
+        assert!(world.command(person, command).is_err());
+    }
+    let replay = Replay {
+        place: "nowhere".into(),
+        ..world.replay()
+    };
+    assert!(World::from_replay(&replay).is_err());
+}
@@ -0,0 +1,266 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conduit::load_conduits,
+    object::{load_objects, load_places, ObjectKind},
+    person::PersonId,
+    replay::{Command, Playback, Replay, ReplayEvent},
+    utils::{set_headless, set_resources_path},
+    word::Word,
+    world::World,
+};
+
+/// A place and the casts to run in it, loaded from YAML
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    pub place: String,
+    /// The number of ticks to run
+    pub ticks: u64,
+    /// The world state is recorded every this many ticks
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default = "default_mana")]
+    pub max_mana: f32,
+    #[serde(default)]
+    pub casts: Vec<ScenarioCast>,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_mana() -> f32 {
+    50.0
+}
+
+/// Words said by the player on a tick
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioCast {
+    pub tick: u64,
+    pub words: Vec<Word>,
+}
+
+/// The state of the world on a tick
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub objects: Vec<ObjectSnapshot>,
+    pub heat_grid: Vec<Vec<f32>>,
+    pub memory_grid: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectSnapshot {
+    pub ground: bool,
+    pub x: f32,
+    pub y: f32,
+    pub rot: f32,
+    pub heat: f32,
+}
+
+impl Scenario {
+    /// The replay that gives the scenario's casts
+    pub fn replay(&self) -> Replay {
+        let events = (self.casts.iter())
+            .flat_map(|cast| {
+                cast.words.iter().map(|word| ReplayEvent {
+                    tick: cast.tick,
+                    person: PersonId::Player,
+                    command: Command::Say(*word),
+                })
+            })
+            .collect();
+        Replay {
+            place: self.place.clone(),
+            people: vec![(PersonId::Player, self.max_mana)],
+            ticks: self.ticks,
+            events,
+        }
+    }
+    /// Run the scenario and return the recorded states
+    pub fn run(&self) -> anyhow::Result<Vec<Snapshot>> {
+        if self.interval == 0 {
+            bail!("The interval must be at least 1");
+        }
+        let replay = self.replay();
+        let mut world = World::from_replay(&replay)?;
+        let mut playback = Playback::new(replay);
+        let mut snapshots = vec![Snapshot::new(&world)];
+        while !playback.finished(&world) {
+            if let Some((event, e)) = playback.apply(&mut world).into_iter().next() {
+                match event.command {
+                    Command::Say(word) => bail!("Unable to say {word} on tick {}: {e}", event.tick),
+                    command => bail!("Unable to {command:?} on tick {}: {e}", event.tick),
+                }
+            }
+            world.update();
+            if world.tick.is_multiple_of(self.interval) || playback.finished(&world) {
+                snapshots.push(Snapshot::new(&world));
+            }
+        }
+        Ok(snapshots)
+    }
+}
+
+impl Snapshot {
+    pub fn new(world: &World) -> Self {
+        Snapshot {
+            tick: world.tick,
+            objects: (world.objects.values())
+                .map(|obj| ObjectSnapshot {
+                    ground: obj.kind == ObjectKind::Ground,
+                    x: obj.pr.pos.x,
+                    y: obj.pr.pos.y,
+                    rot: obj.pr.rot,
+                    heat: obj.heat,
+                })
+                .collect(),
+            heat_grid: world.heat_grid.clone(),
+            memory_grid: world.memory_grid.clone(),
+        }
+    }
+}
+
+/// Format snapshots as CSV with one row per object or grid cell
+pub fn snapshots_csv(snapshots: &[Snapshot]) -> String {
+    let mut csv = String::from("tick,record,i,j,x,y,rot,value\n");
+    for snapshot in snapshots {
+        let tick = snapshot.tick;
+        for (i, obj) in snapshot.objects.iter().enumerate() {
+            let record = if obj.ground { "ground" } else { "object" };
+            csv.push_str(&format!(
+                "{tick},{record},{i},,{},{},{},{}\n",
+                obj.x, obj.y, obj.rot, obj.heat
+            ));
+        }
+        for (record, grid) in [
+            ("heat", &snapshot.heat_grid),
+            ("memory", &snapshot.memory_grid),
+        ] {
+            for (i, col) in grid.iter().enumerate() {
+                for (j, value) in col.iter().enumerate() {
+                    csv.push_str(&format!("{tick},{record},{i},{j},,,,{value}\n"));
+                }
+            }
+        }
+    }
+    csv
+}
+
+/// Run the scenario file given after `sim` on the command line, print the results,
+/// and return the process exit code
+///
+/// Results are printed as JSON, or as CSV if `--csv` is given.
+pub fn run_sim() -> i32 {
+    set_headless();
+    let args: Vec<String> = env::args().skip_while(|arg| arg != "sim").skip(1).collect();
+    let csv = args.iter().any(|arg| arg == "--csv");
+    let resources = (args.iter())
+        .position(|arg| arg == "--resources")
+        .map(|i| args.get(i + 1));
+    let path = (args.iter().enumerate())
+        .find(|(i, arg)| !arg.starts_with("--") && (*i == 0 || args[i - 1] != "--resources"))
+        .map(|(_, arg)| arg);
+    let (Some(path), None | Some(Some(_))) = (path, resources) else {
+        eprintln!("usage: eidos sim <scenario.yaml> [--csv] [--resources <dir>]");
+        return 2;
+    };
+    let resources = match resources.flatten() {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => scenario_resources(Path::new(path)),
+    };
+    let res = resources
+        .and_then(|dir| {
+            set_resources_path(dir);
+            load_objects()?;
+            load_places()?;
+            load_conduits()?;
+            Ok(fs::read_to_string(path)?)
+        })
+        .and_then(|yaml| Ok(serde_yaml::from_str::<Scenario>(&yaml)?))
+        .and_then(|scenario| scenario.run())
+        .and_then(|snapshots| {
+            Ok(if csv {
+                snapshots_csv(&snapshots)
+            } else {
+                serde_json::to_string(&snapshots)?
+            })
+        });
+    match res {
+        Ok(output) => {
+            println!("{output}");
+            0
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            1
+        }
+    }
+}
+
+/// Find the resources directory that holds a scenario file
+fn scenario_resources(path: &Path) -> anyhow::Result<PathBuf> {
+    let path = fs::canonicalize(path)
+        .map_err(|e| anyhow!("Unable to open scenario {}: {e}", path.display()))?;
+    for dir in path.ancestors().skip(1) {
+        for dir in [dir.to_path_buf(), dir.join("resources")] {
+            if dir.join("objects.yaml").is_file() {
+                return Ok(dir);
+            }
+        }
+    }
+    bail!(
+        "Unable to find the resources directory of {}, pass it with --resources",
+        path.display()
+    )
+}
+
+#[test]
+fn sim_test() {
+    let scenario = |casts| Scenario {
+        place: "magician_house".into(),
+        ticks: 120,
+        interval: 60,
+        max_mana: 50.0,
+        casts,
+    };
+    let still = scenario(Vec::new()).run().unwrap();
+    let heated = scenario(vec![ScenarioCast {
+        tick: 0,
+        words: vec![Word::Te, Word::Lo],
+    }])
+    .run()
+    .unwrap();
+    assert_eq!(still.len(), 3);
+    // Nothing falls through the ground
+    for obj in &still.last().unwrap().objects {
+        assert!(obj.ground || obj.y > -0.5);
+    }
+    // Heat spreads into the grid
+    let total_heat = |snapshot: &Snapshot| snapshot.heat_grid.iter().flatten().sum::<f32>();
+    assert!(total_heat(heated.last().unwrap()) > total_heat(still.last().unwrap()));
+    // A cast that cannot be said fails the run
+    let error = scenario(vec![ScenarioCast {
+        tick: 30,
+        words: vec![Word::Lo],
+    }])
+    .run()
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("tick 30"), "{error}");
+    assert!(error.contains(&Word::Lo.to_string()), "{error}");
+}
+
+#[test]
+fn scenario_resources_test() {
+    let resources = fs::canonicalize("resources").unwrap();
+    let found = scenario_resources(&resources.join("scenarios").join("heat.yaml")).unwrap();
+    assert_eq!(found, resources);
+    assert!(scenario_resources(Path::new("missing.yaml")).is_err());
+}
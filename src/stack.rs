@@ -1,4 +1,5 @@
 use crate::{
+    conduit::{conduit_params, ConduitMaterial},
     error::EidosError,
     field::*,
     function::*,
@@ -9,12 +10,19 @@ use crate::{
 #[derive(Default, Clone)]
 pub struct Stack {
     stack: Vec<StackItem>,
+    /// The slot of the next variable to be said
+    next_variable: usize,
+    /// The lowest the stack has been since it was last marked,
+    /// used to find the items a conduit made
+    lowest: usize,
 }
 
 #[derive(Clone)]
 pub struct StackItem {
     pub field: Field,
     pub words: Vec<Word>,
+    /// The mana this item reserves
+    pub cost: f32,
 }
 
 trait IntoWords {
@@ -74,16 +82,25 @@ impl Stack {
     }
     #[track_caller]
     fn pop(&mut self) -> StackItem {
-        self.stack.pop().expect("Nothing to pop")
+        self.remove_top().expect("Nothing to pop")
     }
-    fn push(&mut self, words: impl IntoWords, field: impl Into<Field>) {
+    fn push(&mut self, words: impl IntoWords, cost: f32, field: impl Into<Field>) {
         self.stack.push(StackItem {
             field: field.into(),
             words: words.into_words(),
+            cost,
         })
     }
     pub fn clear(&mut self) {
         self.stack.clear();
+        self.lowest = 0;
+        self.next_variable = 0;
+    }
+    /// Remove the top item without saying anything
+    pub fn remove_top(&mut self) -> Option<StackItem> {
+        let item = self.stack.pop();
+        self.lowest = self.lowest.min(self.stack.len());
+        item
     }
     pub fn words(&self) -> impl Iterator<Item = Word> + '_ {
         self.stack.iter().flat_map(|item| &item.words).copied()
@@ -93,14 +110,26 @@ impl Stack {
         person_id: PersonId,
         word: Word,
         active_spells: Option<&mut ActiveSpells>,
+    ) -> Result<(), EidosError> {
+        self.say_with_cost(person_id, word, word.cost(), active_spells)
+    }
+    /// Say a word that costs the given amount of mana
+    ///
+    /// The cost of every item made is the cost of the items it was made from plus `cost`.
+    fn say_with_cost(
+        &mut self,
+        person_id: PersonId,
+        word: Word,
+        cost: f32,
+        active_spells: Option<&mut ActiveSpells>,
     ) -> Result<(), EidosError> {
         puffin::profile_function!();
         let function = word.function();
         self.validate_function_use(function)?;
         match function {
             Function::ReadField(field_kind) => match field_kind {
-                InputFieldKind::Scalar(kind) => self.push(word, ScalarField::Input(kind)),
-                InputFieldKind::Vector(kind) => self.push(word, VectorField::Input(kind)),
+                InputFieldKind::Scalar(kind) => self.push(word, cost, ScalarField::Input(kind)),
+                InputFieldKind::Vector(kind) => self.push(word, cost, VectorField::Input(kind)),
             },
             Function::WriteField(field_kind) => {
                 let item = self.pop();
@@ -114,6 +143,7 @@ impl Stack {
                                 .push(ActiveSpell {
                                     field,
                                     words: item.words.into_iter().chain([word]).collect(),
+                                    cost: item.cost + cost,
                                 });
                         }
                         (OutputFieldKind::Scalar(kind), Field::Scalar(field)) => {
@@ -124,6 +154,7 @@ impl Stack {
                                 .push(ActiveSpell {
                                     field,
                                     words: item.words.into_iter().chain([word]).collect(),
+                                    cost: item.cost + cost,
                                 });
                         }
                         _ => unreachable!(),
@@ -131,14 +162,17 @@ impl Stack {
                 }
                 self.clear();
             }
-            Function::Control(kind) => self.push(word, ScalarField::Control(kind)),
-            Function::Nullary(nullary) => self.push(word, nullary.field(person_id)),
+            Function::Control(kind) => match kind.ty() {
+                Type::Scalar => self.push(word, cost, ScalarField::Control(person_id, kind)),
+                Type::Vector => self.push(word, cost, VectorField::Control(person_id, kind)),
+            },
+            Function::Nullary(nullary) => self.push(word, cost, nullary.field(person_id)),
             Function::Combinator1(com1) => {
                 let a = self.pop();
                 match com1 {
                     Combinator1::Duplicate => {
-                        self.push(a.words, a.field.clone());
-                        self.push(word, a.field);
+                        self.push(a.words, a.cost, a.field.clone());
+                        self.push(word, cost, a.field);
                     }
                     Combinator1::Drop => {}
                 }
@@ -152,48 +186,53 @@ impl Stack {
                         self.stack.push(a);
                     }
                     Combinator2::Over => {
-                        self.push(a.words, a.field.clone());
+                        self.push(a.words, a.cost, a.field.clone());
                         self.stack.push(b);
-                        self.push(word, a.field);
+                        self.push(word, cost, a.field);
                     }
                 }
             }
             Function::Un(op) => {
                 let a = self.pop();
                 let words = (a.words, word);
+                let cost = a.cost + cost;
                 match op {
                     UnOp::Math(op) => match a.field {
                         Field::Scalar(f) => self.push(
                             words,
+                            cost,
                             ScalarField::ScalarUn(TypedUnOp::Math(op), f.into()).reduce(),
                         ),
                         Field::Vector(f) => self.push(
                             words,
+                            cost,
                             VectorField::VectorUn(TypedUnOp::Math(op), f.into()).reduce(),
                         ),
                     },
                     UnOp::Scalar(op) => match a.field {
                         Field::Scalar(f) => self.push(
                             words,
+                            cost,
                             ScalarField::ScalarUn(TypedUnOp::Typed(op), f.into()).reduce(),
                         ),
                         _ => unreachable!(),
                     },
                     UnOp::ScalarVector(op) => match a.field {
                         Field::Scalar(f) => {
-                            self.push(words, VectorField::ScalarUn(op, f.into()).reduce())
+                            self.push(words, cost, VectorField::ScalarUn(op, f.into()).reduce())
                         }
                         _ => unreachable!(),
                     },
                     UnOp::VectorScalar(op) => match a.field {
                         Field::Vector(f) => {
-                            self.push(words, ScalarField::VectorUn(op, f.into()).reduce())
+                            self.push(words, cost, ScalarField::VectorUn(op, f.into()).reduce())
                         }
                         _ => unreachable!(),
                     },
                     UnOp::VectorVector(op) => match a.field {
                         Field::Vector(f) => self.push(
                             words,
+                            cost,
                             VectorField::VectorUn(TypedUnOp::Typed(op), f.into()).reduce(),
                         ),
                         _ => unreachable!(),
@@ -201,6 +240,7 @@ impl Stack {
                     UnOp::ToScalar(op) => match a.field {
                         Field::Scalar(f) => self.push(
                             words,
+                            cost,
                             ScalarField::ScalarUn(
                                 TypedUnOp::Typed(ScalarUnOp::ToScalar(op)),
                                 f.into(),
@@ -209,6 +249,7 @@ impl Stack {
                         ),
                         Field::Vector(f) => self.push(
                             words,
+                            cost,
                             ScalarField::VectorUn(VectorUnScalarOp::ToScalar(op), f.into())
                                 .reduce(),
                         ),
@@ -219,11 +260,13 @@ impl Stack {
                 let b = self.pop();
                 let a = self.pop();
                 let words = (a.words, b.words, word);
+                let cost = a.cost + b.cost + cost;
                 match op {
                     BinOp::Math(op) => match (a.field, b.field) {
                         (Field::Scalar(a), Field::Scalar(b)) => {
                             self.push(
                                 words,
+                                cost,
                                 ScalarField::Bin(TypedBinOp::Hetero(op), a.into(), b.into())
                                     .reduce(),
                             );
@@ -231,18 +274,21 @@ impl Stack {
                         (Field::Scalar(a), Field::Vector(b)) => {
                             self.push(
                                 words,
+                                cost,
                                 VectorField::BinSV(TypedBinOp::Hetero(op), a, b.into()).reduce(),
                             );
                         }
                         (Field::Vector(a), Field::Scalar(b)) => {
                             self.push(
                                 words,
+                                cost,
                                 VectorField::BinVS(TypedBinOp::Hetero(op), a.into(), b).reduce(),
                             );
                         }
                         (Field::Vector(a), Field::Vector(b)) => {
                             self.push(
                                 words,
+                                cost,
                                 VectorField::BinVV(TypedBinOp::Hetero(op), a.into(), b.into())
                                     .reduce(),
                             );
@@ -251,30 +297,136 @@ impl Stack {
                     BinOp::Homo(op) => match (a.field, b.field) {
                         (Field::Scalar(a), Field::Scalar(b)) => self.push(
                             words,
+                            cost,
                             ScalarField::Bin(TypedBinOp::Typed(op), a.into(), b.into()).reduce(),
                         ),
                         (Field::Vector(a), Field::Vector(b)) => self.push(
                             words,
+                            cost,
                             VectorField::BinVV(TypedBinOp::Typed(op), a.into(), b.into()).reduce(),
                         ),
                         _ => unreachable!(),
                     },
                     BinOp::Index => match (a.field, b.field) {
                         (Field::Vector(a), Field::Scalar(b)) => {
-                            self.push(words, ScalarField::Index(a.into(), b.into()))
+                            self.push(words, cost, ScalarField::Index(a.into(), b.into()))
                         }
                         (Field::Vector(a), Field::Vector(b)) => {
-                            self.push(words, VectorField::Index(a.into(), b.into()))
+                            self.push(words, cost, VectorField::Index(a.into(), b.into()))
                         }
                         _ => unreachable!(),
                     },
                 }
             }
-            Function::Variable(var) => match var {
-                Variable::Scalar => self.push(word, ScalarField::Variable),
-                Variable::Vector => self.push(word, VectorField::Variable),
-            },
+            Function::Variable(var) => {
+                let slot = self.next_variable;
+                self.next_variable += 1;
+                match var {
+                    Variable::Scalar => self.push(word, cost, ScalarField::Variable(slot)),
+                    Variable::Vector => self.push(word, cost, VectorField::Variable(slot)),
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Say a conduit's words, casting spells only if `active_spells` is given
+    ///
+    /// The conduit's parameters are popped from the stack and bound to its variables.
+    /// The cost of the words is modified by the stone's material.
+    pub fn apply_conduit(
+        &mut self,
+        person_id: PersonId,
+        words: &[Word],
+        material: &ConduitMaterial,
+        mut active_spells: Option<&mut ActiveSpells>,
+    ) -> Result<(), EidosError> {
+        let params = conduit_params(words);
+        if self.len() < params.len() {
+            return Err(EidosError::NotEnoughConduitArguments {
+                expected: params.len(),
+                stack_size: self.len(),
+            });
+        }
+        let first_arg = self.len() - params.len();
+        for (position, (param, item)) in params.iter().zip(&self.stack[first_arg..]).enumerate() {
+            if item.field.ty() != *param {
+                return Err(EidosError::InvalidConduitArgument {
+                    position,
+                    expected: *param,
+                    found: item.field.ty(),
+                });
+            }
+        }
+        let mut stack = self.clone();
+        let args: Vec<StackItem> = stack.stack.drain(first_arg..).collect();
+        let mut args_cost: f32 = args.iter().map(|item| item.cost).sum();
+        let args: Vec<Field> = args.into_iter().map(|item| item.field).collect();
+        let first_variable = stack.next_variable;
+        stack.lowest = first_arg;
+        for (index, word) in words.iter().enumerate() {
+            if active_spells.is_some() && matches!(word.function(), Function::WriteField(_)) {
+                // Spells must be cast with the arguments bound
+                stack.settle_conduit(first_variable, &args, args_cost);
+                args_cost = 0.0;
+            }
+            stack
+                .say_with_cost(
+                    person_id,
+                    *word,
+                    material.word_cost(*word),
+                    active_spells.as_deref_mut(),
+                )
+                .map_err(|error| EidosError::ConduitWord {
+                    index,
+                    error: error.into(),
+                })?;
         }
+        stack.settle_conduit(first_variable, &args, args_cost);
+        stack.next_variable = first_variable;
+        *self = stack;
         Ok(())
     }
+    /// Bind a conduit's arguments to the items on the stack
+    fn settle_conduit(&mut self, first_variable: usize, args: &[Field], args_cost: f32) {
+        for item in &mut self.stack {
+            item.field = item.field.clone().bind(first_variable, args);
+        }
+        // The arguments stay reserved by the last item the conduit made
+        let made = &mut self.stack[self.lowest..];
+        if let Some(item) = made.last_mut() {
+            item.cost += args_cost;
+        }
+    }
+}
+
+#[test]
+fn conduit_cost_test() {
+    use crate::conduit::CONDUITS;
+    use Word::*;
+    let amethyst = &CONDUITS.materials["Amethyst"];
+    let cost = |stack: &Stack| stack.iter().map(|item| item.cost).sum::<f32>();
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+    // Base items merged by a conduit keep their base cost
+    let mut stack = Stack::default();
+    for word in [Te, Ti] {
+        stack.say(PersonId::Player, word, None).unwrap();
+    }
+    stack
+        .apply_conduit(PersonId::Player, &[Ma], amethyst, None)
+        .unwrap();
+    assert_eq!(stack.len(), 1);
+    assert!(close(cost(&stack), 11.8), "{}", cost(&stack));
+    // Later words keep the discount
+    for word in [Ti, Ma] {
+        stack.say(PersonId::Player, word, None).unwrap();
+    }
+    assert!(close(cost(&stack), 13.8), "{}", cost(&stack));
+    // Arguments stay reserved by the result
+    let mut stack = Stack::default();
+    stack.say(PersonId::Player, Te, None).unwrap();
+    stack
+        .apply_conduit(PersonId::Player, &[Su, Ti, Ma], amethyst, None)
+        .unwrap();
+    assert_eq!(stack.len(), 1);
+    assert!(close(cost(&stack), 12.4), "{}", cost(&stack));
 }
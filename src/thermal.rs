@@ -0,0 +1,483 @@
+use eframe::egui::*;
+use itertools::Itertools;
+use rapier2d::prelude::ColliderSet;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    math::Convert,
+    object::{GraphicalShape, Object, ObjectDef, ObjectKind, ObjectProperties, OBJECTS},
+    world::{World, ABSOLUTE_ZERO},
+};
+
+/// How quickly heat flows through air
+pub const AIR_CONDUCTIVITY: f32 = 0.5;
+/// The heat needed to warm a square meter of air by one degree
+pub const AIR_HEAT_CAPACITY: f32 = 1.0;
+/// How much heat a perfectly emissive object radiates per degree to the fourth power
+pub const STEFAN_BOLTZMANN: f32 = 1e-11;
+/// The largest share of its heat difference a cell can exchange in one diffusion step
+///
+/// Keeping this at or below a half keeps the explicit scheme stable and free of oscillation.
+const MAX_STEP_EXCHANGE: f32 = 0.5;
+/// The most explicit steps one diffusion is split into
+///
+/// Faces that would need more steps have their conductance limited instead,
+/// so a single light or very conductive cell cannot set the cost of the whole grid.
+const MAX_DIFFUSION_STEPS: f32 = 4.0;
+
+/// The thermal properties of a heat grid cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellMaterial {
+    pub conductivity: f32,
+    /// The heat needed to warm a square meter of the cell by one degree
+    pub capacity: f32,
+}
+
+impl CellMaterial {
+    pub const AIR: Self = CellMaterial {
+        conductivity: AIR_CONDUCTIVITY,
+        capacity: AIR_HEAT_CAPACITY,
+    };
+    /// The material of a cell filled by a shape with the given properties and density
+    pub fn new(props: &ObjectProperties, density: f32) -> Self {
+        CellMaterial {
+            conductivity: props.conductivity,
+            capacity: props.specific_heat * density,
+        }
+    }
+}
+
+/// The conductivity between two materials, which is limited by the worse conductor
+fn conductance(a: f32, b: f32) -> f32 {
+    if a + b > 0.0 {
+        2.0 * a * b / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// The material and temperature of a cell's neighbor at an offset
+fn neighbor(
+    grid: &[Vec<f32>],
+    materials: &[Vec<CellMaterial>],
+    (i, j): (usize, usize),
+    (di, dj): (isize, isize),
+    edge: &(impl Fn(isize) -> Option<f32> + Sync),
+) -> Option<(CellMaterial, f32)> {
+    let (ni, nj) = (i as isize + di, j as isize + dj);
+    let inside = usize::try_from(ni).ok().zip(usize::try_from(nj).ok());
+    match inside.and_then(|(ni, nj)| Some((*materials.get(ni)?.get(nj)?, grid[ni][nj]))) {
+        Some(neighbor) => Some(neighbor),
+        None => edge(nj).map(|temp| (CellMaterial::AIR, temp)),
+    }
+}
+
+const NEIGHBOR_OFFSETS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+/// Diffuse heat through a grid of cells over a time step
+///
+/// Heat flows between neighboring cells in proportion to their shared conductance,
+/// so none is lost within the grid. `edge` gives the temperature of the air beyond
+/// the edge at a row, or `None` if no heat crosses the edges. The step is split into
+/// as many explicit steps as it takes to stay stable, up to [`MAX_DIFFUSION_STEPS`].
+/// Past that, the conductance of each face is limited so that neither of its cells
+/// exchanges more than it stably can.
+pub fn diffuse_heat(
+    grid: &mut Vec<Vec<f32>>,
+    materials: &[Vec<CellMaterial>],
+    resolution: f32,
+    dt: f32,
+    edge: impl Fn(isize) -> Option<f32> + Sync,
+) {
+    let area = resolution * resolution;
+    let face_conductance = |material: CellMaterial, other: CellMaterial| {
+        conductance(material.conductivity, other.conductivity)
+    };
+    let max_rate = (0..grid.len())
+        .into_par_iter()
+        .flat_map_iter(|i| (0..grid[i].len()).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let material = materials[i][j];
+            let total: f32 = (NEIGHBOR_OFFSETS.iter())
+                .filter_map(|&offset| neighbor(grid, materials, (i, j), offset, &edge))
+                .map(|(other, _)| face_conductance(material, other))
+                .sum();
+            total / (material.capacity * area)
+        })
+        .reduce(|| 0.0, f32::max);
+    let steps = (max_rate * dt / MAX_STEP_EXCHANGE)
+        .ceil()
+        .clamp(1.0, MAX_DIFFUSION_STEPS);
+    let step_dt = dt / steps;
+    for _ in 0..steps as usize {
+        let new_grid = (grid.par_iter().enumerate())
+            .map(|(i, col)| {
+                (col.iter().enumerate())
+                    .map(|(j, &temp)| {
+                        let material = materials[i][j];
+                        let flow: f32 = (NEIGHBOR_OFFSETS.iter())
+                            .filter_map(|&offset| neighbor(grid, materials, (i, j), offset, &edge))
+                            .map(|(other, neighbor_temp)| {
+                                // Each face may move at most its share of the lighter cell's heat
+                                let limit = MAX_STEP_EXCHANGE / NEIGHBOR_OFFSETS.len() as f32
+                                    * material.capacity.min(other.capacity)
+                                    * area
+                                    / step_dt;
+                                let conductance = face_conductance(material, other).min(limit);
+                                conductance * (neighbor_temp - temp)
+                            })
+                            .sum();
+                        temp + flow * step_dt / (material.capacity * area)
+                    })
+                    .collect()
+            })
+            .collect();
+        *grid = new_grid;
+    }
+}
+
+/// The heat that flows from one body to another over a time step by conduction and radiation
+///
+/// Each body is given as its temperature and heat capacity. The flow never carries
+/// the bodies past the temperature they would share.
+pub fn heat_flow(
+    from: (f32, f32),
+    to: (f32, f32),
+    conductance: f32,
+    emissivity: f32,
+    dt: f32,
+) -> f32 {
+    let ((from_temp, from_capacity), (to_temp, to_capacity)) = (from, to);
+    if from_capacity <= 0.0 || to_capacity <= 0.0 {
+        return 0.0;
+    }
+    let diff = from_temp - to_temp;
+    let radiated = |temp: f32| (temp - ABSOLUTE_ZERO).max(0.0).powi(4);
+    let flow = conductance * diff * dt
+        + emissivity * STEFAN_BOLTZMANN * (radiated(from_temp) - radiated(to_temp)) * dt;
+    let limit = diff * from_capacity * to_capacity / (from_capacity + to_capacity);
+    flow.clamp(limit.min(0.0), limit.max(0.0))
+}
+
+impl Object {
+    pub fn mass(&self, colliders: &ColliderSet) -> f32 {
+        (self.foreground_handles.iter())
+            .map(|handle| colliders[*handle].mass())
+            .sum()
+    }
+    /// The heat needed to warm the object by one degree
+    pub fn heat_capacity(&self, colliders: &ColliderSet) -> f32 {
+        self.mass(colliders) * self.def.props.specific_heat
+    }
+}
+
+/// A change of state that happens when an object's heat passes a point
+///
+/// Exactly one of `above` and `below` is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhaseChange {
+    /// The object changes when its heat rises above this point, like melting or boiling
+    #[serde(default)]
+    pub above: Option<f32>,
+    /// The object changes when its heat falls below this point, like freezing
+    #[serde(default)]
+    pub below: Option<f32>,
+    /// The heat per unit of mass that must pass the point before the change is complete
+    #[serde(default)]
+    pub latent_heat: f32,
+    /// The object this one becomes, or nothing if it disappears
+    #[serde(default)]
+    pub into: Option<String>,
+    /// The number of objects this one becomes
+    #[serde(default = "default_pieces")]
+    pub pieces: usize,
+}
+
+fn default_pieces() -> usize {
+    1
+}
+
+impl PhaseChange {
+    pub fn point(&self) -> f32 {
+        self.above.or(self.below).unwrap_or_default()
+    }
+    /// Whether heat above the point drives the change, rather than heat below it
+    fn rising(&self) -> bool {
+        self.above.is_some()
+    }
+    /// How far past the point a heat is, if it is past it
+    pub fn past(&self, heat: f32) -> Option<f32> {
+        let past = match (self.above, self.below) {
+            (Some(point), _) => heat - point,
+            (_, Some(point)) => point - heat,
+            (None, None) => return None,
+        };
+        (past > 0.0).then_some(past)
+    }
+}
+
+impl GraphicalShape {
+    /// The radius of a circle around the origin that contains the shape
+    pub fn bounding_radius(&self) -> f32 {
+        match self {
+            GraphicalShape::Circle(radius) => *radius,
+            GraphicalShape::Box(size) => size.length() / 2.0,
+            GraphicalShape::HalfSpace(_) => 0.0,
+            GraphicalShape::Capsule {
+                half_height,
+                radius,
+            } => half_height + radius,
+        }
+    }
+}
+
+impl ObjectDef {
+    /// The radius of a circle around the object's origin that contains its shapes
+    pub fn bounding_radius(&self) -> f32 {
+        self.shapes
+            .iter()
+            .map(|shape| shape.offset.length() + shape.shape.bounding_radius())
+            .fold(0.0, f32::max)
+    }
+}
+
+impl World {
+    /// Advance the phase changes of objects past their points and replace
+    /// the objects whose changes are complete
+    pub fn run_phase_changes(&mut self) {
+        let mut changed = Vec::new();
+        for (handle, obj) in &mut self.objects {
+            if obj.kind == ObjectKind::Ground || obj.def.props.constant_heat.is_some() {
+                continue;
+            }
+            let capacity = obj.heat_capacity(&self.physics.colliders);
+            if capacity <= 0.0 {
+                continue;
+            }
+            let past_change =
+                |heat: f32| (obj.def.phases.iter()).position(|change| change.past(heat).is_some());
+            // Heat put into a change that stopped comes back out
+            if obj.phase_progress > 0.0 && past_change(obj.heat) != Some(obj.phase_index) {
+                if let Some(change) = obj.def.phases.get(obj.phase_index) {
+                    let sign = if change.rising() { 1.0 } else { -1.0 };
+                    obj.heat += sign * obj.phase_progress / capacity;
+                }
+                obj.phase_progress = 0.0;
+            }
+            let Some(index) = past_change(obj.heat) else {
+                continue;
+            };
+            let change = &obj.def.phases[index];
+            // Heat past the point goes into the change
+            obj.phase_index = index;
+            obj.phase_progress += change.past(obj.heat).unwrap() * capacity;
+            obj.heat = change.point();
+            let latent_heat = change.latent_heat * obj.mass(&self.physics.colliders);
+            if obj.phase_progress >= latent_heat {
+                changed.push((*handle, change.clone(), obj.phase_progress - latent_heat));
+            }
+        }
+        for (handle, change, surplus) in changed {
+            let obj = self.objects.shift_remove(&handle).unwrap();
+            self.physics.remove_body(handle);
+            let Some(into) = &change.into else {
+                continue;
+            };
+            let def = OBJECTS[into].clone();
+            // Lay the pieces out in a square grid centered on the old object
+            let pieces = change.pieces.max(1);
+            let spacing = def.bounding_radius() * 2.0;
+            let columns = (pieces as f32).sqrt().ceil() as usize;
+            let rows = pieces.div_ceil(columns);
+            let corner = vec2(columns as f32 - 1.0, rows as f32 - 1.0) * spacing / 2.0;
+            let mut handles = Vec::with_capacity(pieces);
+            for (i, j) in (0..rows).cartesian_product(0..columns).take(pieces) {
+                let offset = vec2(j as f32, i as f32) * spacing - corner;
+                let pos = obj.pr.pos + offset;
+                handles.push(self.add_object(
+                    ObjectKind::Object,
+                    def.clone(),
+                    |rb| {
+                        rb.translation(pos.convert())
+                            .rotation(obj.pr.rot)
+                            .linvel(obj.vel.convert())
+                    },
+                    |c| c,
+                ));
+            }
+            // Heat past what the change needed carries on into the pieces
+            let capacity: f32 = (handles.iter())
+                .map(|handle| self.objects[handle].heat_capacity(&self.physics.colliders))
+                .sum();
+            let sign = if change.rising() { 1.0 } else { -1.0 };
+            let heat = obj.heat + sign * surplus / capacity.max(f32::EPSILON);
+            for handle in handles {
+                self.objects[&handle].heat = heat;
+            }
+        }
+    }
+}
+
+#[test]
+fn phase_change_test() {
+    let mut world = World::test_place("arena");
+    let count = |world: &World, name: &str| world.objects_named(name).count();
+    let pillars = count(&world, "ice_pillar");
+    assert!(pillars > 0);
+    // Heat one pillar well past its melting point
+    let (handle, _) = world.objects_named("ice_pillar").next().unwrap();
+    world.objects[&handle].heat = 1000.0;
+    world.update();
+    assert_eq!(count(&world, "ice_pillar"), pillars - 1);
+    let pieces = OBJECTS["ice_pillar"].phases[0].pieces;
+    assert_eq!(count(&world, "water_drop"), pieces);
+    // A pillar that stays cold does not change
+    for _ in 0..60 {
+        world.update();
+    }
+    assert_eq!(count(&world, "ice_pillar"), pillars - 1);
+    // Heat put into a change that stops is given back
+    let (handle, _) = world.objects_named("ice_pillar").next().unwrap();
+    let capacity = world.objects[&handle].heat_capacity(&world.physics.colliders);
+    world.objects[&handle].heat = 1.0;
+    world.run_phase_changes();
+    let pillar = &world.objects[&handle];
+    assert_eq!(pillar.heat, 0.0);
+    assert!((pillar.phase_progress - capacity).abs() < 1e-3);
+    world.objects[&handle].heat = -2.0;
+    world.run_phase_changes();
+    let pillar = &world.objects[&handle];
+    assert!((pillar.heat + 1.0).abs() < 1e-3, "{}", pillar.heat);
+    assert_eq!(pillar.phase_progress, 0.0);
+}
+
+#[test]
+fn phase_change_energy_test() {
+    // The heat that melts a pillar is either latent or warms the water
+    let mut world = World::test_place("arena");
+    let (handle, pillar) = world.objects_named("ice_pillar").next().unwrap();
+    let colliders = &world.physics.colliders;
+    let (capacity, mass) = (pillar.heat_capacity(colliders), pillar.mass(colliders));
+    let before = world.objects.keys().copied().collect_vec();
+    world.objects[&handle].heat = 50.0;
+    world.run_phase_changes();
+    assert!(!world.objects.contains_key(&handle));
+    let latent_heat = OBJECTS["ice_pillar"].phases[0].latent_heat * mass;
+    let sensible: f32 = (world.objects.iter())
+        .filter(|(handle, _)| !before.contains(handle))
+        .map(|(_, obj)| obj.heat * obj.heat_capacity(&world.physics.colliders))
+        .sum();
+    let added = 50.0 * capacity;
+    assert!((latent_heat + sensible - added).abs() / added < 1e-3);
+}
+
+#[test]
+fn heat_conservation_test() {
+    let resolution = 0.25;
+    let dt = 1.0 / 60.0;
+    let material = |name: &str| {
+        let def = &OBJECTS[name];
+        CellMaterial::new(&def.props, def.shapes[0].density)
+    };
+    let (metal, wood) = (material("iron_bar"), material("wooden_crate"));
+    let materials = (0..12)
+        .map(|i| {
+            (0..8)
+                .map(|j| match (i + j) % 3 {
+                    0 => metal,
+                    1 => wood,
+                    _ => CellMaterial::AIR,
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    let mut grid = (0..12)
+        .map(|i| {
+            (0..8)
+                .map(|j| (i * 37 + j * 11) as f32 % 100.0)
+                .collect_vec()
+        })
+        .collect_vec();
+    let energy = |grid: &[Vec<f32>]| -> f32 {
+        (grid.iter().flatten())
+            .zip(materials.iter().flatten())
+            .map(|(temp, material)| temp * material.capacity)
+            .sum()
+    };
+    let before = energy(&grid);
+    for _ in 0..300 {
+        diffuse_heat(&mut grid, &materials, resolution, dt, |_| None);
+    }
+    assert!((energy(&grid) - before).abs() / before < 1e-4);
+    // The grid stays between its extremes and evens out
+    let (min, max) = grid
+        .iter()
+        .flatten()
+        .fold((f32::MAX, f32::MIN), |(min, max), &t| {
+            (min.min(t), max.max(t))
+        });
+    assert!(min >= 0.0 && max <= 100.0);
+    assert!(max - min < 50.0);
+    // Heat flowing between a body and a cell is conserved and never overshoots
+    let (hot, cold) = ((300.0, 0.5), (0.0, 0.1));
+    let flow = heat_flow(hot, cold, 1000.0, 1.0, dt);
+    let (hot_after, cold_after) = (hot.0 - flow / hot.1, cold.0 + flow / cold.1);
+    assert!((hot_after - cold_after).abs() < 1e-3);
+    assert!(
+        (hot.0 * hot.1 + cold.0 * cold.1 - hot_after * hot.1 - cold_after * cold.1).abs() < 1e-3
+    );
+}
+
+#[test]
+fn extreme_material_test() {
+    // A nearly weightless cell beside a very good conductor stays stable
+    let (light, conductor) = (
+        CellMaterial {
+            conductivity: 1.0,
+            capacity: 1e-6,
+        },
+        CellMaterial {
+            conductivity: 1e4,
+            capacity: 1.0,
+        },
+    );
+    let materials = (0..6)
+        .map(|i| {
+            (0..6)
+                .map(|j| [light, conductor][(i + j) % 2])
+                .collect_vec()
+        })
+        .collect_vec();
+    let mut grid = (0..6)
+        .map(|i| {
+            (0..6)
+                .map(|j| ((i * 5 + j * 3) % 7) as f32 * 10.0)
+                .collect_vec()
+        })
+        .collect_vec();
+    for _ in 0..60 {
+        diffuse_heat(&mut grid, &materials, 0.25, 1.0 / 60.0, |_| None);
+    }
+    assert!(grid.iter().flatten().all(|t| (0.0..=60.0).contains(t)));
+}
+
+#[test]
+fn conductivity_test() {
+    // Heat one end of a bar and see how warm the other end gets
+    let far_end = |name: &str| {
+        let def = &OBJECTS[name];
+        let material = CellMaterial::new(&def.props, def.shapes[0].density);
+        let materials = vec![vec![material]; 8];
+        let mut grid = vec![vec![0.0]; 8];
+        for _ in 0..60 {
+            grid[0][0] = 100.0;
+            diffuse_heat(&mut grid, &materials, 0.25, 1.0 / 60.0, |_| None);
+        }
+        grid[7][0]
+    };
+    let metal = far_end("iron_bar");
+    let wood = far_end("wooden_crate");
+    assert!(metal > 50.0, "{metal}");
+    assert!(wood < 1.0, "{wood}");
+}
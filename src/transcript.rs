@@ -0,0 +1,93 @@
+use std::{env::current_dir, fs, path::PathBuf};
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::tr;
+
+/// A record of everything said in dialog
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TranscriptEntry {
+    /// A line spoken by a character, or narration if there is no speaker
+    Line {
+        speaker: Option<String>,
+        text: String,
+    },
+    /// A choice or response picked by the player
+    Choice(String),
+}
+
+impl Transcript {
+    pub fn push_line(&mut self, speaker: Option<String>, text: String) {
+        if !text.is_empty() {
+            self.entries.push(TranscriptEntry::Line { speaker, text });
+        }
+    }
+    pub fn push_choice(&mut self, text: String) {
+        self.entries.push(TranscriptEntry::Choice(text));
+    }
+    /// Format the transcript as plain text
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            match entry {
+                TranscriptEntry::Line {
+                    speaker: Some(speaker),
+                    text: line,
+                } => text.push_str(&format!("{speaker}: {line}\n")),
+                TranscriptEntry::Line {
+                    speaker: None,
+                    text: line,
+                } => text.push_str(&format!("{line}\n")),
+                TranscriptEntry::Choice(choice) => text.push_str(&format!("> {choice}\n")),
+            }
+        }
+        text
+    }
+    /// Write the transcript to a text file in the working directory
+    pub fn export(&self) -> anyhow::Result<PathBuf> {
+        let path = current_dir()?.join("transcript.txt");
+        fs::write(&path, self.to_text())?;
+        Ok(path)
+    }
+    /// Show the scrollable backlog
+    pub fn ui(&self, ui: &mut Ui, export_message: &mut Option<String>) {
+        ui.horizontal(|ui| {
+            if ui.button(tr("Export")).clicked() {
+                *export_message = Some(match self.export() {
+                    Ok(path) => format!("{} {}", tr("Saved to"), path.display()),
+                    Err(e) => e.to_string(),
+                });
+            }
+            if let Some(message) = export_message {
+                ui.small(message.as_str());
+            }
+        });
+        ui.separator();
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for entry in &self.entries {
+                    match entry {
+                        TranscriptEntry::Line { speaker, text } => {
+                            ui.horizontal_wrapped(|ui| {
+                                if let Some(speaker) = speaker {
+                                    ui.strong(format!("{speaker}:"));
+                                }
+                                ui.label(text);
+                            });
+                        }
+                        TranscriptEntry::Choice(choice) => {
+                            ui.label(RichText::new(format!("> {choice}")).italics().weak());
+                        }
+                    }
+                }
+            });
+    }
+}
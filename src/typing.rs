@@ -0,0 +1,99 @@
+use eframe::egui::*;
+use enum_iterator::all;
+
+use crate::{
+    game::Game,
+    input::{action_pressed, Action},
+    locale::tr,
+    person::PersonId,
+    replay::Command,
+    word::Word,
+};
+
+/// The state of typed syllable casting
+#[derive(Default)]
+pub struct SyllableInput {
+    /// The letters of the syllable being typed
+    pub text: String,
+    /// Feedback about the last syllable
+    pub message: Option<String>,
+}
+
+/// Find the word spelled by a syllable, ignoring case
+pub fn word_for_syllable(syllable: &str) -> Option<Word> {
+    all::<Word>().find(|word| word.to_string().eq_ignore_ascii_case(syllable))
+}
+
+impl Game {
+    /// Show the field for casting by typing syllables
+    ///
+    /// Each completed consonant-vowel pair is said. Backspace on an empty
+    /// syllable removes the top of the stack.
+    pub fn typing_ui(&mut self, ui: &mut Ui) {
+        let was_empty = self.ui_state.typing.text.is_empty();
+        let resp = TextEdit::singleline(&mut self.ui_state.typing.text)
+            .hint_text(tr("Type to cast"))
+            .desired_width(100.0)
+            .show(ui)
+            .response;
+        // Focus with enter
+        if ui.input(|input| action_pressed(input, Action::Type))
+            && ui.memory(|mem| mem.focus().is_none())
+        {
+            resp.request_focus();
+        }
+        if resp.has_focus() && was_empty && ui.input(|input| input.key_pressed(Key::Backspace)) {
+            self.ui_state.typing.message = if self.ui_state.dialog_allows_casting() {
+                (self.world)
+                    .command(PersonId::Player, Command::RemoveTop)
+                    .err()
+                    .map(|e| e.to_string())
+            } else {
+                None
+            };
+        }
+        if resp.changed() {
+            self.say_typed_syllables();
+        }
+        // Feedback and completions
+        let input = &self.ui_state.typing;
+        if let Some(message) = &input.message {
+            ui.colored_label(ui.visuals().error_fg_color, message);
+        } else if !input.text.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                for word in all::<Word>().filter(|word| {
+                    word.to_string()
+                        .to_lowercase()
+                        .starts_with(&input.text.to_lowercase())
+                        && self.can_say(PersonId::Player, *word).is_ok()
+                }) {
+                    let mut text = RichText::new(word.to_string());
+                    if let Some(color) = word.text_color() {
+                        text = text.color(color);
+                    }
+                    ui.label(text);
+                }
+            });
+        }
+    }
+    /// Say every complete syllable that has been typed
+    fn say_typed_syllables(&mut self) {
+        let input = &mut self.ui_state.typing;
+        input.text.retain(char::is_alphabetic);
+        input.message = None;
+        while self.ui_state.typing.text.chars().count() >= 2 {
+            let syllable: String = self.ui_state.typing.text.chars().take(2).collect();
+            self.ui_state.typing.text = self.ui_state.typing.text.chars().skip(2).collect();
+            let res = match word_for_syllable(&syllable) {
+                Some(word) => self
+                    .can_say(PersonId::Player, word)
+                    .and_then(|_| self.say(PersonId::Player, word).map_err(|e| e.to_string())),
+                None => Err(format!("{}: {syllable}", tr("Unknown word"))),
+            };
+            if let Err(e) = res {
+                self.ui_state.typing.message = Some(e);
+                self.ui_state.typing.text.clear();
+            }
+        }
+    }
+}
@@ -2,34 +2,63 @@ use std::{
     env::{current_dir, current_exe},
     path::PathBuf,
     process::exit,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+use anyhow::{anyhow, bail};
 use eframe::egui::*;
+use once_cell::sync::OnceCell;
+
+/// A resources directory to use instead of looking for one
+static RESOURCES_PATH: OnceCell<PathBuf> = OnceCell::new();
+/// Whether the game is running without a window
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+
+/// Use the given resources directory instead of looking for one
+pub fn set_resources_path(path: PathBuf) {
+    let _already_set = RESOURCES_PATH.set(path);
+}
+
+/// Report fatal errors on stderr instead of in a window
+pub fn set_headless() {
+    HEADLESS.store(true, Ordering::Relaxed);
+}
 
 pub fn resources_path() -> PathBuf {
-    let mut path = current_dir()
-        .map_err(fatal_error)
-        .unwrap()
-        .join("resources");
+    find_resources_path().unwrap_or_else(|e| fatal_error(e))
+}
+
+/// Find the resources directory, which is either the one that was set,
+/// or one next to the working directory or the executable
+pub fn find_resources_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = RESOURCES_PATH.get() {
+        if !path.is_dir() {
+            bail!("Resources directory {} does not exist", path.display());
+        }
+        return Ok(path.clone());
+    }
+    let path = current_dir()?.join("resources");
     if path.exists() {
-        return path;
+        return Ok(path);
     }
-    path = current_exe()
-        .map_err(fatal_error)
-        .unwrap()
-        .parent()
-        .unwrap()
+    let exe = current_exe()?;
+    let path = (exe.parent())
+        .ok_or_else(|| anyhow!("Unable to find resources directory"))?
         .join("resources");
     if path.exists() {
-        return path;
+        return Ok(path);
     }
-    fatal_error("Unable to find resources directory")
+    bail!("Unable to find resources directory")
 }
 
 pub fn fatal_error(message: impl ToString) -> ! {
     fatal_error_impl(message.to_string())
 }
 fn fatal_error_impl(message: String) -> ! {
+    if HEADLESS.load(Ordering::Relaxed) {
+        eprintln!("error: {message}");
+        exit(1)
+    }
     eframe::run_native(
         "Error",
         eframe::NativeOptions {
@@ -126,6 +126,22 @@ pub enum Word {
     Ru,
     /// Over
     Vo,
+
+    // More controls, after the rest to keep conduit codes stable
+    /// Activation 3
+    Ne,
+    /// Charge
+    Po,
+    /// Scroll
+    Pa,
+    /// Pointer
+    Pu,
+
+    // More inputs
+    /// Wind
+    Wo,
+    /// Impact
+    So,
 }
 
 impl Word {
@@ -148,6 +164,8 @@ impl Word {
             Lu => ScalarInputFieldKind::Temperature.into(),
             Ko => ScalarInputFieldKind::Disorder.into(),
             Re => ScalarInputFieldKind::Memory.into(),
+            So => ScalarInputFieldKind::Impact.into(),
+            Wo => VectorInputFieldKind::Wind.into(),
             Vu => VectorOutputFieldKind::Gravity.into(),
             Wu => VectorOutputFieldKind::Force.into(),
             Lo => ScalarOutputFieldKind::Heat.into(),
@@ -175,11 +193,12 @@ impl Word {
             Pi => Nullary::TargetY.into(),
             Ve => ControlKind::Activation1.into(),
             Vi => ControlKind::Activation2.into(),
+            Ne => ControlKind::Activation3.into(),
+            Po => ControlKind::Charge.into(),
+            Pa => ControlKind::Scroll.into(),
+            Pu => ControlKind::Pointer.into(),
         }
     }
-    pub fn etchable(&self) -> bool {
-        !matches!(self.function(), Function::Variable(_))
-    }
     pub fn cost(&self) -> f32 {
         use Word::*;
         match self {
@@ -192,6 +211,7 @@ impl Word {
             Li => 2.0,
             Pe => 3.0,
             Pi => 3.0,
+            Pu => 3.0,
             No | Ru | Vo => 0.0,
             _ => 1.0,
         }
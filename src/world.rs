@@ -1,33 +1,49 @@
-use std::{f32::consts::PI, iter::once};
+use std::{collections::HashMap, iter::once};
 
-use eframe::{egui::*, epaint::ahash::HashMap};
-use itertools::Itertools;
+use eframe::egui::*;
+use indexmap::IndexMap;
 use rapier2d::prelude::*;
 use rayon::prelude::*;
 
 use crate::{
+    contact::ContactEvent,
     field::*,
-    math::{angle_diff, go_to},
+    fluid::{FluidGrid, SMOKE_DENSITY},
+    joint::Joint,
     npc::{Npc, NpcId},
     object::*,
     person::{Person, PersonId},
     physics::PhysicsContext,
     player::Player,
+    replay::Recorder,
+    thermal::{diffuse_heat, heat_flow, CellMaterial},
 };
 
+/// The simulation
+///
+/// Maps are kept in insertion order so that the world updates the same way every run.
 pub struct World {
     pub player: Player,
-    pub npcs: HashMap<NpcId, Npc>,
-    pub objects: HashMap<RigidBodyHandle, Object>,
+    pub npcs: IndexMap<NpcId, Npc>,
+    /// The second player, if this is a duel
+    pub rival: Option<Person>,
+    pub objects: IndexMap<RigidBodyHandle, Object>,
     pub min_bound: Pos2,
     pub max_bound: Pos2,
     pub heat_grid: Vec<Vec<f32>>,
     pub memory_grid: Vec<Vec<f32>>,
+    pub fluid: FluidGrid,
+    /// Recent collisions and impacts
+    pub contacts: Vec<ContactEvent>,
+    /// Joints between placed objects
+    pub joints: Vec<Joint>,
     pub physics: PhysicsContext,
-    pub controls: Controls,
+    /// The number of updates since the place was loaded
+    pub tick: u64,
+    pub recorder: Recorder,
 }
 
-const HEAT_GRID_RESOLUTION: f32 = 0.25;
+pub const HEAT_GRID_RESOLUTION: f32 = 0.25;
 const MEMORY_GRID_RESOLUTION: f32 = 0.25;
 pub const GROUND_TEMP: f32 = -3.0;
 pub const ABSOLUTE_ZERO: f32 = -(20.0 + GROUND_TEMP + 273.15);
@@ -35,74 +51,98 @@ pub const TEMP_DROP_PER_METER: f32 = 6.5 / 1000.0;
 pub const GRAVITY: Vec2 = vec2(0.0, -10.0);
 pub const AIR_DENSITY_AT_GROUND_TEMP: f32 = 0.001279176;
 
-#[derive(Default)]
-pub struct Controls {
-    pub x_slider: Option<f32>,
-    pub y_slider: Option<f32>,
-    pub activation1: bool,
-    pub activation2: bool,
-}
-
-impl Controls {
-    pub fn get(&self, kind: ControlKind) -> f32 {
-        match kind {
-            ControlKind::XSlider => self.x_slider.unwrap_or(0.0),
-            ControlKind::YSlider => self.y_slider.unwrap_or(0.0),
-            ControlKind::Activation1 => self.activation1 as u8 as f32,
-            ControlKind::Activation2 => self.activation2 as u8 as f32,
-        }
-    }
-}
-
 impl World {
     pub fn new(player: Player) -> Self {
-        // Init world
-        let mut world = World {
+        let mut world = World::empty(player);
+        // Place
+        world.load_place("magician_house");
+        world
+    }
+    /// Create a world without a place
+    pub fn empty(player: Player) -> Self {
+        World {
             player,
-            npcs: HashMap::default(),
+            npcs: IndexMap::new(),
+            rival: None,
             physics: PhysicsContext::default(),
             min_bound: Pos2::ZERO,
             max_bound: Pos2::ZERO,
             heat_grid: Vec::new(),
             memory_grid: Vec::new(),
-            objects: HashMap::default(),
-            controls: Controls::default(),
-        };
-        // Place
-        world.load_place("magician_house");
-        world
+            fluid: FluidGrid::default(),
+            contacts: Vec::new(),
+            joints: Vec::new(),
+            objects: IndexMap::new(),
+            tick: 0,
+            recorder: Recorder::default(),
+        }
     }
-    #[track_caller]
-    pub fn person(&self, person_id: PersonId) -> &Person {
+    /// Add a person, or set their maximum mana if they already exist
+    pub fn add_person(&mut self, person_id: PersonId, max_mana: f32) {
         match person_id {
-            PersonId::Player => &self.player.person,
+            PersonId::Player => self.player.person.max_mana = max_mana,
             PersonId::Npc(npc_id) => {
-                if let Some(npc) = self.npcs.get(&npc_id) {
-                    &npc.person
-                } else {
-                    panic!("No npc with id {npc_id:?}");
-                }
+                let npc = self.npcs.entry(npc_id).or_insert_with(|| Npc {
+                    person: Person::new(max_mana),
+                });
+                npc.person.max_mana = max_mana;
+            }
+            PersonId::Rival => {
+                self.rival
+                    .get_or_insert_with(|| Person::new(max_mana))
+                    .max_mana = max_mana;
             }
         }
     }
-    #[track_caller]
-    pub fn person_mut(&mut self, person_id: PersonId) -> &mut Person {
+    pub fn try_person(&self, person_id: PersonId) -> Option<&Person> {
         match person_id {
-            PersonId::Player => &mut self.player.person,
-            PersonId::Npc(npc_id) => {
-                if let Some(npc) = self.npcs.get_mut(&npc_id) {
-                    &mut npc.person
-                } else {
-                    panic!("No npc with id {npc_id:?}");
-                }
-            }
+            PersonId::Player => Some(&self.player.person),
+            PersonId::Npc(npc_id) => self.npcs.get(&npc_id).map(|npc| &npc.person),
+            PersonId::Rival => self.rival.as_ref(),
+        }
+    }
+    pub fn try_person_mut(&mut self, person_id: PersonId) -> Option<&mut Person> {
+        match person_id {
+            PersonId::Player => Some(&mut self.player.person),
+            PersonId::Npc(npc_id) => self.npcs.get_mut(&npc_id).map(|npc| &mut npc.person),
+            PersonId::Rival => self.rival.as_mut(),
         }
     }
+    #[track_caller]
+    pub fn person(&self, person_id: PersonId) -> &Person {
+        self.try_person(person_id)
+            .unwrap_or_else(|| panic!("No person with id {person_id:?}"))
+    }
+    #[track_caller]
+    pub fn person_mut(&mut self, person_id: PersonId) -> &mut Person {
+        self.try_person_mut(person_id)
+            .unwrap_or_else(|| panic!("No person with id {person_id:?}"))
+    }
     pub fn max_rect(&self) -> Rect {
         Rect::from_min_max(self.min_bound, self.max_bound)
     }
 }
 
+#[cfg(test)]
+impl World {
+    /// Create a world in a place for a test
+    pub fn test_place(place_name: &str) -> Self {
+        use crate::player::Gender;
+        let mut world = World::empty(Player::new("Kai".into(), Gender::Male));
+        world.load_place(place_name);
+        world
+    }
+    /// Get the objects made from the definition with a name
+    pub fn objects_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = (RigidBodyHandle, &'a Object)> + 'a {
+        (self.objects.iter())
+            .filter(move |(_, obj)| obj.def.name == name)
+            .map(|(handle, obj)| (*handle, obj))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ShapeLayer {
     Foreground,
@@ -188,6 +228,11 @@ impl World {
     fn find_object_at(&self, p: Pos2) -> Option<FoundObject> {
         self.find_object_filtered_at(p, |_, _| true)
     }
+    /// Check whether a position is inside the foreground of an object
+    pub fn is_solid_at(&self, p: Pos2) -> bool {
+        self.find_object_at(p)
+            .is_some_and(|found| matches!(found.layer, ShapeLayer::Foreground))
+    }
     pub fn sample_scalar_field(
         &self,
         kind: ScalarFieldKind,
@@ -229,7 +274,7 @@ impl World {
             ScalarInputFieldKind::Density => self
                 .find_object_at(pos)
                 .map(|found| found.shape.density * found.layer.multiplier())
-                .unwrap_or(0.0),
+                .unwrap_or_else(|| self.smoke_at(pos) * SMOKE_DENSITY),
             ScalarInputFieldKind::Elevation => {
                 let mut test = pos;
                 while test.y > 0.0 {
@@ -282,20 +327,20 @@ impl World {
                     .unwrap_or_else(|| ambient_temp_at(pos.y))
             }
             ScalarInputFieldKind::Disorder => {
+                // Objects are disordered where they are and missing where they should be
                 if let Some(found) = self.find_object_at(pos) {
-                    found.obj.pr.pos.distance(found.obj.ordered_pr.pos)
-                        + angle_diff(found.obj.pr.rot, found.obj.ordered_pr.rot).abs() / PI
+                    found.obj.disorder()
                 } else if let Some(found) = self.find_obj_filtered_at_impl(
                     pos,
                     |_, _| true,
                     Object::transform_point_as_ordered,
                 ) {
-                    -(found.obj.pr.pos.distance(found.obj.ordered_pr.pos)
-                        + angle_diff(found.obj.pr.rot, found.obj.ordered_pr.rot).abs() / PI)
+                    -found.obj.disorder()
                 } else {
                     0.0
                 }
             }
+            ScalarInputFieldKind::Impact => self.impact_at(pos),
             ScalarInputFieldKind::Memory => {
                 let i = ((pos.x - self.min_bound.x) / MEMORY_GRID_RESOLUTION + 0.5) as usize;
                 let j = ((pos.y - self.min_bound.y) / MEMORY_GRID_RESOLUTION + 0.5) as usize;
@@ -307,8 +352,10 @@ impl World {
             }
         }
     }
-    pub fn sample_input_vector_field(&self, kind: VectorInputFieldKind, _pos: Pos2) -> Vec2 {
-        match kind {}
+    pub fn sample_input_vector_field(&self, kind: VectorInputFieldKind, pos: Pos2) -> Vec2 {
+        match kind {
+            VectorInputFieldKind::Wind => self.wind_at(pos),
+        }
     }
     pub fn sample_output_scalar_field(
         &self,
@@ -347,22 +394,56 @@ impl World {
         self.person_ids_iter().map(|id| self.person(id))
     }
     pub fn person_ids_iter(&self) -> impl Iterator<Item = PersonId> + '_ {
-        once(PersonId::Player).chain(self.npcs.keys().copied().map(PersonId::Npc))
+        once(PersonId::Player)
+            .chain(self.npcs.keys().copied().map(PersonId::Npc))
+            .chain(self.rival.as_ref().map(|_| PersonId::Rival))
     }
     pub fn person_ids(&self) -> Vec<PersonId> {
         self.person_ids_iter().collect()
     }
 }
 
-fn ambient_temp_at(y: f32) -> f32 {
+pub fn ambient_temp_at(y: f32) -> f32 {
     let y = y.max(0.0);
     GROUND_TEMP - TEMP_DROP_PER_METER * y
 }
 
+impl World {
+    /// The thermal material of each heat grid cell
+    pub fn heat_materials(&self) -> Vec<Vec<CellMaterial>> {
+        (0..self.heat_grid.len())
+            .into_par_iter()
+            .map(|i| {
+                let pos_x = self.min_bound.x + (i as f32 + 0.5) * HEAT_GRID_RESOLUTION;
+                (0..self.heat_grid[i].len())
+                    .map(|j| {
+                        let pos_y = self.min_bound.y + (j as f32 + 0.5) * HEAT_GRID_RESOLUTION;
+                        self.find_object_at(pos2(pos_x, pos_y))
+                            .map(|found| {
+                                CellMaterial::new(&found.obj.def.props, found.shape.density)
+                            })
+                            .unwrap_or(CellMaterial::AIR)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
 impl World {
     pub fn update(&mut self) {
+        // Record controls and targets
+        self.record_inputs();
         // Run physics
         self.run_physics();
+        // Remember collisions and impacts
+        self.record_contacts();
+        // Break joints that were pulled too hard
+        self.run_joints();
+        // Break objects that took too much damage
+        self.run_fractures();
+        // Run triggered conduits
+        self.run_conduit_triggers();
         // Update memory
         for i in 0..self.memory_grid.len() {
             for j in 0..self.memory_grid[i].len() {
@@ -394,63 +475,87 @@ impl World {
             }
         }
         // Transer heat between objects and grid
+        let dt = self.physics.dt();
+        let materials = self.heat_materials();
+        let cell_area = HEAT_GRID_RESOLUTION * HEAT_GRID_RESOLUTION;
         for obj in self.objects.values_mut() {
             let i = ((obj.pr.pos.x - self.min_bound.x) / HEAT_GRID_RESOLUTION + 0.5) as usize;
             let j = ((obj.pr.pos.y - self.min_bound.y) / HEAT_GRID_RESOLUTION + 0.5) as usize;
             if let Some(cell_heat) = self.heat_grid.get_mut(i).and_then(|col| col.get_mut(j)) {
-                let diff = (obj.heat - *cell_heat) * 0.01;
-                *cell_heat += diff;
-                obj.heat -= diff;
+                let material = materials[i][j];
+                let capacity = obj.heat_capacity(&self.physics.colliders);
+                // The object touches its cell on all four sides
+                let conductance = 4.0 * obj.def.props.conductivity.min(material.conductivity);
+                let flow = heat_flow(
+                    (obj.heat, capacity),
+                    (*cell_heat, material.capacity * cell_area),
+                    conductance,
+                    obj.def.props.emissivity,
+                    dt,
+                );
+                if flow != 0.0 {
+                    *cell_heat += flow / (material.capacity * cell_area);
+                    obj.heat -= flow / capacity;
+                }
             }
             if let Some(constant_heat) = obj.def.props.constant_heat {
                 obj.heat = constant_heat;
             }
         }
+        // Change the state of objects past their phase points
+        self.run_phase_changes();
         // Transfer heat between grid cells
-        let new_grid: Vec<Vec<f32>> = self
-            .heat_grid
-            .par_iter()
-            .enumerate()
-            .map(|(i, col)| {
-                let mut new_col = col.clone();
-                let pos_x = self.min_bound.x + (i as f32 + 0.5) * HEAT_GRID_RESOLUTION;
-                new_col.par_iter_mut().enumerate().for_each(|(j, cell)| {
-                    let pos_y = self.min_bound.y + (j as f32 + 0.5) * HEAT_GRID_RESOLUTION;
-                    let pos = pos2(pos_x, pos_y);
-                    let ambient_temp = ambient_temp_at(pos_y);
-                    let (center_mul, side_mul) = if self.find_object_at(pos).is_some() {
-                        (4.6, 0.1)
-                    } else {
-                        (1.0, 1.0)
-                    };
-                    let center = col[j];
-                    let left = self
-                        .heat_grid
-                        .get((i as isize - 1) as usize)
-                        .map(|col| col[j])
-                        .unwrap_or(ambient_temp);
-                    let right = self
-                        .heat_grid
-                        .get((i as isize + 1) as usize)
-                        .map(|col| col[j])
-                        .unwrap_or(ambient_temp);
-                    let up = *col.get((j as isize + 1) as usize).unwrap_or(&ambient_temp);
-                    let down = *col.get((j as isize - 1) as usize).unwrap_or(&ambient_temp);
-                    *cell = (center_mul * center + side_mul * (left + right + up + down)) / 5.0;
-                });
-                new_col
-            })
-            .collect();
-        self.heat_grid = new_grid;
+        let min_y = self.min_bound.y;
+        diffuse_heat(
+            &mut self.heat_grid,
+            &materials,
+            HEAT_GRID_RESOLUTION,
+            dt,
+            |j| {
+                Some(ambient_temp_at(
+                    min_y + (j as f32 + 0.5) * HEAT_GRID_RESOLUTION,
+                ))
+            },
+        );
+        // Move the air
+        self.run_fluid(&materials);
         // Apply anchoring
-        for handle in self.objects.keys().copied().collect_vec() {
-            let pos = self.objects[&handle].pr.pos;
-            let anchoring = self.physics.dt()
-                * self.sample_output_scalar_field(ScalarOutputFieldKind::Anchor, pos, true);
-            let obj = self.objects.get_mut(&handle).unwrap();
-            obj.ordered_pr.pos.x = go_to(obj.ordered_pr.pos.x, obj.pr.pos.x, anchoring);
-            obj.ordered_pr.pos.y = go_to(obj.ordered_pr.pos.y, obj.pr.pos.y, anchoring);
-            obj.ordered_pr.rot = go_to(obj.ordered_pr.rot, obj.pr.rot, anchoring);
+        self.run_anchoring();
+        self.tick += 1;
+    }
+    /// Advance every conduit trigger and run the stones whose triggers fire
+    fn run_conduit_triggers(&mut self) {
+        let dt = self.physics.dt();
+        for person_id in self.person_ids() {
+            let stone_count = self.person(person_id).rack.conduits.len();
+            for i in 0..stone_count {
+                let stone = &self.person(person_id).rack.conduits[i];
+                let Some(mut trigger) = stone.trigger.clone() else {
+                    continue;
+                };
+                let fired = trigger.update(person_id, self, dt);
+                let person = self.person_mut(person_id);
+                person.rack.conduits[i].trigger = Some(trigger);
+                let stone = &person.rack.conduits[i];
+                if !fired || stone.words.is_empty() || person.capped_mana() < stone.cost() {
+                    continue;
+                }
+                let words = stone.words.clone();
+                let material = stone.material();
+                // The stack and spells only change if the whole conduit succeeds,
+                // and the change can be undone like any other
+                person.change_stack(|stack, active_spells| {
+                    let mut new_stack = stack.clone();
+                    let mut new_spells = active_spells.clone();
+                    if new_stack
+                        .apply_conduit(person_id, &words, material, Some(&mut new_spells))
+                        .is_ok()
+                    {
+                        *stack = new_stack;
+                        *active_spells = new_spells;
+                    }
+                });
+            }
         }
     }
     pub fn temperature_at(&self, pos: Pos2) -> f32 {
@@ -483,13 +588,22 @@ impl World {
         self.max_bound.x = place.bounds.right;
         self.min_bound.y = place.bounds.bottom;
         self.max_bound.y = place.bounds.top;
+        // Start a new recording
+        self.tick = 0;
+        self.contacts.clear();
+        let people = (self.people())
+            .zip(self.person_ids())
+            .map(|(person, person_id)| (person_id, person.max_mana))
+            .collect();
+        self.recorder = Recorder::new(place_name, people);
         // Remove old objects
-        for (handle, _) in self.objects.drain() {
+        for (handle, _) in self.objects.drain(..) {
             self.physics.remove_body(handle);
         }
+        self.joints.clear();
         // Add objects
         // Ground
-        self.add_object(
+        let ground = self.add_object(
             ObjectKind::Ground,
             ObjectDef::new(RigidBodyType::Fixed).shapes(
                 GraphicalShape::HalfSpace(Vec2::Y)
@@ -500,6 +614,7 @@ impl World {
             |c| c.restitution(0.5),
         );
         // Place objects
+        let mut ids = HashMap::new();
         for po in &place.objects {
             let object = OBJECTS[&po.name].clone();
             if let Some(repli) = &po.replication {
@@ -511,9 +626,17 @@ impl World {
                     }
                 }
             } else {
-                self.add_object_def(po.pos, object);
+                let handle = self.add_object_def(po.pos, object);
+                if let Some(id) = &po.id {
+                    ids.insert(id.as_str(), handle);
+                }
             }
         }
+        // Joints
+        let handle_of = |id: &str| if id == "ground" { ground } else { ids[id] };
+        for joint in &place.joints {
+            self.add_joint(joint, handle_of(&joint.a), handle_of(&joint.b));
+        }
         // Init heat grid
         self.heat_grid = vec![vec![GROUND_TEMP; self.heat_grid_height()]; self.heat_grid_width()];
         for col in self.heat_grid.iter_mut() {
@@ -524,5 +647,7 @@ impl World {
         }
         // Init memory grid
         self.memory_grid = vec![vec![0.0; self.memory_grid_height()]; self.memory_grid_width()];
+        // Init fluid grid
+        self.fluid = FluidGrid::new(self.fluid_grid_width(), self.fluid_grid_height());
     }
 }